@@ -575,7 +575,11 @@ impl DisplaySnapshot {
                     diagnostic_highlight.underline = Some(UnderlineStyle {
                         color: Some(diagnostic_color),
                         thickness: 1.0.into(),
+                        relative_thickness: None,
                         wavy: true,
+                        wavy_amplitude: None,
+                        wavy_wavelength: None,
+                        skip_descenders: false,
                     });
                 }
             }
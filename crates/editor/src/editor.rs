@@ -10293,8 +10293,12 @@ impl ViewInputHandler for Editor {
                     HighlightStyle {
                         underline: Some(UnderlineStyle {
                             thickness: px(1.),
+                            relative_thickness: None,
                             color: None,
                             wavy: false,
+                            wavy_amplitude: None,
+                            wavy_wavelength: None,
+                            skip_descenders: false,
                         }),
                         ..Default::default()
                     },
@@ -890,9 +890,7 @@ impl EditorElement {
                                             len,
                                             font: self.style.text.font(),
                                             color: self.style.background,
-                                            background_color: None,
-                                            strikethrough: None,
-                                            underline: None,
+                                            ..Default::default()
                                         }],
                                     )
                                     .log_err()
@@ -1233,9 +1231,7 @@ impl EditorElement {
                         len: line_number.len(),
                         font: self.style.text.font(),
                         color,
-                        background_color: None,
-                        underline: None,
-                        strikethrough: None,
+                        ..Default::default()
                     };
                     let shaped_line = cx
                         .text_system()
@@ -1293,9 +1289,7 @@ impl EditorElement {
                         len: line.len(),
                         font: self.style.text.font(),
                         color: placeholder_color,
-                        background_color: None,
-                        underline: Default::default(),
-                        strikethrough: None,
+                        ..Default::default()
                     };
                     cx.text_system()
                         .shape_line(line.to_string().into(), font_size, &[run])
@@ -2748,10 +2742,7 @@ impl EditorElement {
                 &[TextRun {
                     len: column,
                     font: style.text.font(),
-                    color: Hsla::default(),
-                    background_color: None,
-                    underline: None,
-                    strikethrough: None,
+                    ..Default::default()
                 }],
             )
             .unwrap();
@@ -2839,6 +2830,7 @@ impl LineWithInvisibles {
                         background_color: text_style.background_color,
                         underline: text_style.underline,
                         strikethrough: text_style.strikethrough,
+                        ..Default::default()
                     });
 
                     if editor_mode == EditorMode::Full {
@@ -3350,9 +3342,7 @@ impl Element for EditorElement {
                             len: "→".len(),
                             font: self.style.text.font(),
                             color: cx.theme().colors().editor_invisible,
-                            background_color: None,
-                            underline: None,
-                            strikethrough: None,
+                            ..Default::default()
                         }],
                     )
                     .unwrap();
@@ -3365,9 +3355,7 @@ impl Element for EditorElement {
                             len: "•".len(),
                             font: self.style.text.font(),
                             color: cx.theme().colors().editor_invisible,
-                            background_color: None,
-                            underline: None,
-                            strikethrough: None,
+                            ..Default::default()
                         }],
                     )
                     .unwrap();
@@ -3647,10 +3635,7 @@ fn layout_line(
         &[TextRun {
             len: snapshot.line_len(row) as usize,
             font: style.text.font(),
-            color: Hsla::default(),
-            background_color: None,
-            underline: None,
-            strikethrough: None,
+            ..Default::default()
         }],
     )
 }
@@ -0,0 +1,38 @@
+//! Compares the cost of `BackgroundExecutor::is_main_thread`, which compares thread ids, with
+//! asking `NSThread` directly.
+//!
+//! Run with `cargo bench -p gpui --bench is_main_thread`.
+
+#[cfg(target_os = "macos")]
+fn main() {
+    use objc::{
+        class, msg_send,
+        runtime::{BOOL, YES},
+        sel, sel_impl,
+    };
+    use std::{hint::black_box, time::Instant};
+
+    const ITERATIONS: u32 = 10_000_000;
+
+    fn measure(name: &str, mut f: impl FnMut() -> bool) {
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            black_box(f());
+        }
+        println!("{name}: {:?} per call", start.elapsed() / ITERATIONS);
+    }
+
+    let executor = gpui::App::new().background_executor();
+    measure("BackgroundExecutor::is_main_thread", || {
+        executor.is_main_thread()
+    });
+    measure("NSThread isMainThread", || {
+        let is_main_thread: BOOL = unsafe { msg_send![class!(NSThread), isMainThread] };
+        is_main_thread == YES
+    });
+}
+
+#[cfg(not(target_os = "macos"))]
+fn main() {
+    println!("this benchmark compares against NSThread, so it only runs on macOS");
+}
@@ -31,6 +31,7 @@ fn generate_dispatch_bindings() {
         .header("src/platform/mac/dispatch.h")
         .allowlist_var("_dispatch_main_q")
         .allowlist_var("_dispatch_source_type_data_add")
+        .allowlist_var("_dispatch_source_type_timer")
         .allowlist_var("DISPATCH_QUEUE_PRIORITY_DEFAULT")
         .allowlist_var("DISPATCH_QUEUE_PRIORITY_HIGH")
         .allowlist_var("DISPATCH_TIME_NOW")
@@ -41,6 +42,9 @@ fn generate_dispatch_bindings() {
         .allowlist_function("dispatch_source_merge_data")
         .allowlist_function("dispatch_source_create")
         .allowlist_function("dispatch_source_set_event_handler_f")
+        .allowlist_function("dispatch_source_set_cancel_handler_f")
+        .allowlist_function("dispatch_source_set_timer")
+        .allowlist_function("dispatch_release")
         .allowlist_function("dispatch_resume")
         .allowlist_function("dispatch_suspend")
         .allowlist_function("dispatch_source_cancel")
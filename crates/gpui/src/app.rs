@@ -30,11 +30,11 @@ use util::{
 use crate::{
     current_platform, image_cache::ImageCache, init_app_menus, Action, ActionRegistry, Any,
     AnyView, AnyWindowHandle, AppMetadata, AssetSource, BackgroundExecutor, ClipboardItem, Context,
-    DispatchPhase, DisplayId, Entity, EventEmitter, ForegroundExecutor, Global, KeyBinding, Keymap,
-    Keystroke, LayoutId, Menu, PathPromptOptions, Pixels, Platform, PlatformDisplay, Point,
-    PromptBuilder, PromptHandle, PromptLevel, Render, RenderablePromptHandle, SharedString,
-    SubscriberSet, Subscription, SvgRenderer, Task, TextSystem, View, ViewContext, Window,
-    WindowAppearance, WindowContext, WindowHandle, WindowId,
+    DispatchPhase, DispatcherOptions, DisplayId, Entity, EventEmitter, ForegroundExecutor, Global,
+    KeyBinding, Keymap, Keystroke, LayoutId, Menu, PathPromptOptions, Pixels, Platform,
+    PlatformDisplay, Point, PromptBuilder, PromptHandle, PromptLevel, Render,
+    RenderablePromptHandle, SharedString, SubscriberSet, Subscription, SvgRenderer, Task,
+    TextSystem, View, ViewContext, Window, WindowAppearance, WindowContext, WindowHandle, WindowId,
 };
 
 mod async_context;
@@ -111,11 +111,16 @@ impl App {
     /// Builds an app with the given asset source.
     #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
+        Self::new_with_dispatcher_options(DispatcherOptions::default())
+    }
+
+    /// Builds an app whose tasks are run by a dispatcher configured with the given options.
+    pub fn new_with_dispatcher_options(dispatcher_options: DispatcherOptions) -> Self {
         #[cfg(any(test, feature = "test-support"))]
         log::info!("GPUI was compiled in test mode");
 
         Self(AppContext::new(
-            current_platform(),
+            current_platform(dispatcher_options),
             Arc::new(()),
             http::client(),
         ))
@@ -691,7 +696,11 @@ impl AppContext {
                     })
                     .collect::<Vec<_>>()
                 {
-                    self.update_window(window, |_, cx| cx.draw()).unwrap();
+                    self.update_window(window, |_, cx| {
+                        cx.draw();
+                        cx.present();
+                    })
+                    .unwrap();
                 }
 
                 if self.pending_effects.is_empty() {
@@ -2,8 +2,8 @@ use smallvec::SmallVec;
 use taffy::style::{Display, Position};
 
 use crate::{
-    point, AnyElement, Bounds, Element, ElementContext, IntoElement, LayoutId, ParentElement,
-    Pixels, Point, Size, Style,
+    point, AnyElement, Bounds, Edges, Element, ElementContext, IntoElement, LayoutId,
+    ParentElement, Pixels, Point, Size, Style,
 };
 
 /// The state that the overlay element uses to track its children.
@@ -20,6 +20,8 @@ pub struct Overlay {
     fit_mode: OverlayFitMode,
     anchor_position: Option<Point<Pixels>>,
     position_mode: OverlayPositionMode,
+    avoided_bounds: Option<Bounds<Pixels>>,
+    snap_margin: Edges<Option<Pixels>>,
 }
 
 /// overlay gives you a floating element that will avoid overflowing the window bounds.
@@ -31,6 +33,8 @@ pub fn overlay() -> Overlay {
         fit_mode: OverlayFitMode::SwitchAnchor,
         anchor_position: None,
         position_mode: OverlayPositionMode::Window,
+        avoided_bounds: None,
+        snap_margin: Edges::all(Some(Pixels::ZERO)),
     }
 }
 
@@ -61,6 +65,128 @@ impl Overlay {
         self.fit_mode = OverlayFitMode::SnapToWindow;
         self
     }
+
+    /// Snap to window edge like [`Overlay::snap_to_window`], keeping the given margin between
+    /// the overlay and each window edge. An edge set to `None` is not snapped at all, so the
+    /// overlay may extend past that side of the window.
+    pub fn snap_to_window_with_margin(mut self, margin: Edges<Option<Pixels>>) -> Self {
+        self.fit_mode = OverlayFitMode::SnapToWindow;
+        self.snap_margin = margin;
+        self
+    }
+
+    /// Moves `desired` back inside `limits`, inset by `margin`, on each edge it overflows.
+    /// Edges whose margin is `None` are left unsnapped.
+    fn snap_to_limits(
+        mut desired: Bounds<Pixels>,
+        limits: Bounds<Pixels>,
+        margin: &Edges<Option<Pixels>>,
+    ) -> Bounds<Pixels> {
+        // Snap the horizontal edges of the overlay to the horizontal edges of the window if
+        // its horizontal bounds overflow, aligning to the left if it is wider than the limits.
+        if let Some(right) = margin.right {
+            if desired.right() > limits.right() - right {
+                desired.origin.x -= desired.right() - (limits.right() - right);
+            }
+        }
+        if let Some(left) = margin.left {
+            if desired.left() < limits.left() + left {
+                desired.origin.x = limits.left() + left;
+            }
+        }
+
+        // Snap the vertical edges of the overlay to the vertical edges of the window if
+        // its vertical bounds overflow, aligning to the top if it is taller than the limits.
+        if let Some(bottom) = margin.bottom {
+            if desired.bottom() > limits.bottom() - bottom {
+                desired.origin.y -= desired.bottom() - (limits.bottom() - bottom);
+            }
+        }
+        if let Some(top) = margin.top {
+            if desired.top() < limits.top() + top {
+                desired.origin.y = limits.top() + top;
+            }
+        }
+
+        desired
+    }
+
+    /// If fitting the overlay inside `limits` moved it from `initial` to `desired` and made it
+    /// cover `avoided`, moves it to a side of `avoided` that has room for it instead: above or
+    /// below, or failing that to the left or right. The room on each side excludes `margin`.
+    fn move_off_avoided_bounds(
+        mut desired: Bounds<Pixels>,
+        initial: Bounds<Pixels>,
+        avoided: Bounds<Pixels>,
+        limits: Bounds<Pixels>,
+        margin: &Edges<Option<Pixels>>,
+    ) -> Bounds<Pixels> {
+        if !desired.intersects(&avoided) || initial.intersects(&avoided) {
+            return desired;
+        }
+
+        let size = desired.size;
+        let fits_above =
+            avoided.top() - (limits.top() + margin.top.unwrap_or_default()) >= size.height;
+        let fits_below =
+            limits.bottom() - margin.bottom.unwrap_or_default() - avoided.bottom() >= size.height;
+        if fits_above || fits_below {
+            let place_below = if fits_above && fits_below {
+                initial.top() >= avoided.top()
+            } else {
+                fits_below
+            };
+            desired.origin.y = if place_below {
+                avoided.bottom()
+            } else {
+                avoided.top() - size.height
+            };
+            return desired;
+        }
+
+        let fits_left =
+            avoided.left() - (limits.left() + margin.left.unwrap_or_default()) >= size.width;
+        let fits_right =
+            limits.right() - margin.right.unwrap_or_default() - avoided.right() >= size.width;
+        if fits_left || fits_right {
+            let place_right = if fits_left && fits_right {
+                initial.left() >= avoided.left()
+            } else {
+                fits_right
+            };
+            desired.origin.x = if place_right {
+                avoided.right()
+            } else {
+                avoided.left() - size.width
+            };
+        }
+
+        desired
+    }
+
+    /// Resolves where an overlay of the given size is placed when its `anchor` corner is
+    /// attached to the `attach` corner of `handle_bounds`, switching anchor corners to avoid
+    /// overflowing `window_bounds`. Returns the chosen anchor corner and the overlay's origin,
+    /// before any snapping to the window edges. This is how overlays that aren't snapped to the
+    /// window are placed.
+    pub fn resolve_position(
+        handle_bounds: Bounds<Pixels>,
+        menu_size: Size<Pixels>,
+        window_bounds: Bounds<Pixels>,
+        anchor: AnchorCorner,
+        attach: AnchorCorner,
+    ) -> (AnchorCorner, Point<Pixels>) {
+        let position = attach.corner(handle_bounds);
+        let anchor = anchor.fit(position, menu_size, window_bounds);
+        (anchor, anchor.get_bounds(position, menu_size).origin)
+    }
+
+    /// Keeps the given bounds (in window coordinates) visible when the overlay has to be moved
+    /// to fit inside the window. This is typically the element that opened the overlay.
+    pub fn avoid_covering(mut self, bounds: Bounds<Pixels>) -> Self {
+        self.avoided_bounds = Some(bounds);
+        self
+    }
 }
 
 impl ParentElement for Overlay {
@@ -122,6 +248,7 @@ impl Element for Overlay {
             size,
             bounds,
         );
+        let initial = desired;
 
         let limits = Bounds {
             origin: Point::default(),
@@ -129,44 +256,21 @@ impl Element for Overlay {
         };
 
         if self.fit_mode == OverlayFitMode::SwitchAnchor {
-            let mut anchor_corner = self.anchor_corner;
-
-            if desired.left() < limits.left() || desired.right() > limits.right() {
-                let switched = anchor_corner
-                    .switch_axis(Axis::Horizontal)
-                    .get_bounds(origin, size);
-                if !(switched.left() < limits.left() || switched.right() > limits.right()) {
-                    anchor_corner = anchor_corner.switch_axis(Axis::Horizontal);
-                    desired = switched
-                }
-            }
-
-            if desired.top() < limits.top() || desired.bottom() > limits.bottom() {
-                let switched = anchor_corner
-                    .switch_axis(Axis::Vertical)
-                    .get_bounds(origin, size);
-                if !(switched.top() < limits.top() || switched.bottom() > limits.bottom()) {
-                    desired = switched;
-                }
-            }
-        }
-
-        // Snap the horizontal edges of the overlay to the horizontal edges of the window if
-        // its horizontal bounds overflow, aligning to the left if it is wider than the limits.
-        if desired.right() > limits.right() {
-            desired.origin.x -= desired.right() - limits.right();
-        }
-        if desired.left() < limits.left() {
-            desired.origin.x = limits.origin.x;
+            // The overlay is anchored at a point, which is the corner of an empty handle.
+            let (_, fitted_origin) = Self::resolve_position(
+                Bounds::new(origin, Size::default()),
+                size,
+                limits,
+                self.anchor_corner,
+                AnchorCorner::TopLeft,
+            );
+            desired = Bounds::new(fitted_origin, size);
         }
 
-        // Snap the vertical edges of the overlay to the vertical edges of the window if
-        // its vertical bounds overflow, aligning to the top if it is taller than the limits.
-        if desired.bottom() > limits.bottom() {
-            desired.origin.y -= desired.bottom() - limits.bottom();
-        }
-        if desired.top() < limits.top() {
-            desired.origin.y = limits.origin.y;
+        desired = Self::snap_to_limits(desired, limits, &self.snap_margin);
+        if let Some(avoided) = self.avoided_bounds {
+            desired =
+                Self::move_off_avoided_bounds(desired, initial, avoided, limits, &self.snap_margin);
         }
 
         before_layout.offset = cx.element_offset() + desired.origin - bounds.origin;
@@ -245,7 +349,7 @@ impl OverlayPositionMode {
 }
 
 /// Which corner of the overlay should be considered the anchor.
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum AnchorCorner {
     /// The top left corner
     TopLeft,
@@ -288,6 +392,33 @@ impl AnchorCorner {
         }
     }
 
+    /// Switches to the opposite corner along each axis on which an overlay of the given size,
+    /// anchored at `position`, would overflow `limits`, unless it would overflow there too.
+    fn fit(self, position: Point<Pixels>, size: Size<Pixels>, limits: Bounds<Pixels>) -> Self {
+        let mut anchor_corner = self;
+        let desired = anchor_corner.get_bounds(position, size);
+        if desired.left() < limits.left() || desired.right() > limits.right() {
+            let switched = anchor_corner.switch_axis(Axis::Horizontal);
+            let switched_bounds = switched.get_bounds(position, size);
+            if !(switched_bounds.left() < limits.left() || switched_bounds.right() > limits.right())
+            {
+                anchor_corner = switched;
+            }
+        }
+
+        let desired = anchor_corner.get_bounds(position, size);
+        if desired.top() < limits.top() || desired.bottom() > limits.bottom() {
+            let switched = anchor_corner.switch_axis(Axis::Vertical);
+            let switched_bounds = switched.get_bounds(position, size);
+            if !(switched_bounds.top() < limits.top() || switched_bounds.bottom() > limits.bottom())
+            {
+                anchor_corner = switched;
+            }
+        }
+
+        anchor_corner
+    }
+
     fn switch_axis(self, axis: Axis) -> Self {
         match axis {
             Axis::Vertical => match self {
@@ -305,3 +436,125 @@ impl AnchorCorner {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{point, px, size};
+
+    fn window_bounds() -> Bounds<Pixels> {
+        Bounds::new(point(px(0.), px(0.)), size(px(800.), px(600.)))
+    }
+
+    #[test]
+    fn test_resolve_position_without_overflow() {
+        let handle_bounds = Bounds::new(point(px(100.), px(100.)), size(px(50.), px(20.)));
+        let (anchor, origin) = Overlay::resolve_position(
+            handle_bounds,
+            size(px(200.), px(300.)),
+            window_bounds(),
+            AnchorCorner::TopLeft,
+            AnchorCorner::BottomLeft,
+        );
+        assert_eq!(anchor, AnchorCorner::TopLeft);
+        assert_eq!(origin, point(px(100.), px(120.)));
+    }
+
+    #[test]
+    fn test_resolve_position_flips_on_overflow() {
+        let menu_size = size(px(200.), px(300.));
+
+        // Overflowing the bottom of the window flips the menu above the handle.
+        let handle_bounds = Bounds::new(point(px(100.), px(500.)), size(px(50.), px(20.)));
+        let (anchor, origin) = Overlay::resolve_position(
+            handle_bounds,
+            menu_size,
+            window_bounds(),
+            AnchorCorner::TopLeft,
+            AnchorCorner::BottomLeft,
+        );
+        assert_eq!(anchor, AnchorCorner::BottomLeft);
+        assert_eq!(origin, point(px(100.), px(220.)));
+
+        // Overflowing both the right and the bottom of the window flips both axes.
+        let handle_bounds = Bounds::new(point(px(700.), px(500.)), size(px(50.), px(20.)));
+        let (anchor, origin) = Overlay::resolve_position(
+            handle_bounds,
+            menu_size,
+            window_bounds(),
+            AnchorCorner::TopLeft,
+            AnchorCorner::BottomLeft,
+        );
+        assert_eq!(anchor, AnchorCorner::BottomRight);
+        assert_eq!(origin, point(px(500.), px(220.)));
+    }
+
+    #[test]
+    fn test_resolve_position_keeps_anchor_when_flipping_does_not_help() {
+        let handle_bounds = Bounds::new(point(px(100.), px(100.)), size(px(50.), px(20.)));
+        let (anchor, origin) = Overlay::resolve_position(
+            handle_bounds,
+            size(px(200.), px(700.)),
+            window_bounds(),
+            AnchorCorner::TopLeft,
+            AnchorCorner::BottomLeft,
+        );
+        assert_eq!(anchor, AnchorCorner::TopLeft);
+        assert_eq!(origin, point(px(100.), px(120.)));
+    }
+
+    #[test]
+    fn test_snap_to_limits_with_margin() {
+        let overflowing = Bounds::new(point(px(700.), px(-20.)), size(px(200.), px(100.)));
+
+        let snapped = Overlay::snap_to_limits(
+            overflowing,
+            window_bounds(),
+            &Edges::all(Some(Pixels::ZERO)),
+        );
+        assert_eq!(snapped.origin, point(px(600.), px(0.)));
+
+        let snapped =
+            Overlay::snap_to_limits(overflowing, window_bounds(), &Edges::all(Some(px(8.))));
+        assert_eq!(snapped.origin, point(px(592.), px(8.)));
+
+        // Edges without a margin are not snapped.
+        let snapped = Overlay::snap_to_limits(
+            overflowing,
+            window_bounds(),
+            &Edges {
+                top: None,
+                right: Some(px(8.)),
+                bottom: Some(px(8.)),
+                left: Some(px(8.)),
+            },
+        );
+        assert_eq!(snapped.origin, point(px(592.), px(-20.)));
+    }
+
+    #[test]
+    fn test_avoid_covering_after_snapping_with_margin() {
+        let margin = Edges::all(Some(px(8.)));
+        let place = |initial: Bounds<Pixels>, avoided: Bounds<Pixels>| {
+            let snapped = Overlay::snap_to_limits(initial, window_bounds(), &margin);
+            Overlay::move_off_avoided_bounds(snapped, initial, avoided, window_bounds(), &margin)
+                .origin
+        };
+
+        // Snapping a menu below its button back inside the window covers the button, so the
+        // menu is moved above it.
+        let button = Bounds::new(point(px(100.), px(500.)), size(px(50.), px(20.)));
+        let below_button = Bounds::new(point(px(100.), px(520.)), size(px(200.), px(300.)));
+        assert_eq!(place(below_button, button), point(px(100.), px(200.)));
+
+        // A full-height sidebar leaves no room above or below it, so the menu is moved to its
+        // left, where there's room for it inside the margin.
+        let sidebar = Bounds::new(point(px(700.), px(0.)), size(px(50.), px(600.)));
+        let right_of_sidebar = Bounds::new(point(px(750.), px(100.)), size(px(200.), px(100.)));
+        assert_eq!(place(right_of_sidebar, sidebar), point(px(500.), px(100.)));
+
+        // When the margin leaves too little room on either side, the snapped position is kept.
+        let wide_menu = Bounds::new(point(px(750.), px(100.)), size(px(695.), px(100.)));
+        assert_eq!(place(wide_menu, sidebar), point(px(97.), px(100.)));
+    }
+}
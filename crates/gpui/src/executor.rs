@@ -1,5 +1,8 @@
-use crate::{AppContext, PlatformDispatcher};
-use futures::{channel::mpsc, pin_mut, FutureExt};
+use crate::{AppContext, CoalescingKey, PlatformDispatcher};
+use futures::{
+    channel::{mpsc, oneshot},
+    pin_mut, FutureExt,
+};
 use smol::prelude::*;
 use std::{
     fmt::Debug,
@@ -370,6 +373,40 @@ impl BackgroundExecutor {
         self.dispatcher.is_main_thread()
     }
 
+    /// Runs `f` on the main thread and blocks the calling thread until it returns, handing back
+    /// its result. When called on the main thread, `f` runs immediately. Fails if `f` panics or
+    /// is dropped before it gets to run.
+    pub fn run_on_main_blocking<T: Send + 'static>(
+        &self,
+        f: impl FnOnce() -> T + Send + 'static,
+    ) -> anyhow::Result<T> {
+        let result = Arc::new(parking_lot::Mutex::new(None));
+        self.dispatcher.run_on_main_blocking(Box::new({
+            let result = result.clone();
+            move || *result.lock() = Some(f())
+        }))?;
+        let result = result.lock().take();
+        result.ok_or_else(|| anyhow::anyhow!("the closure didn't return a value"))
+    }
+
+    /// Whether the dispatcher has no pending work, including timers that haven't fired.
+    pub fn is_idle(&self) -> bool {
+        self.dispatcher.is_idle()
+    }
+
+    /// Returns a future that resolves the next time the dispatcher has no pending work, or
+    /// right away if it has none now. Useful for letting everything settle in integration
+    /// tests. See [`Self::is_idle`].
+    pub fn wait_until_idle(&self) -> impl Future<Output = ()> {
+        let (tx, rx) = oneshot::channel();
+        self.dispatcher.on_idle(Box::new(move || {
+            tx.send(()).ok();
+        }));
+        async move {
+            rx.await.ok();
+        }
+    }
+
     #[cfg(any(test, feature = "test-support"))]
     /// in tests, control the number of ticks that `block_with_timeout` will run before timing out.
     pub fn set_block_on_ticks(&self, range: std::ops::RangeInclusive<usize>) {
@@ -405,6 +442,36 @@ impl ForegroundExecutor {
         }
         inner::<R>(dispatcher, Box::pin(future))
     }
+
+    /// Enqueues the given future to run on the main thread, unless a future spawned with the
+    /// same key hasn't started running yet. Useful for notify-style work, where many requests
+    /// made in a row only need to be serviced once.
+    pub fn spawn_coalesced(&self, key: &CoalescingKey, future: impl Future<Output = ()> + 'static) {
+        let dispatcher = self.dispatcher.clone();
+        let (runnable, task) = async_task::spawn_local(future, move |runnable| {
+            dispatcher.dispatch_on_main_thread(runnable)
+        });
+        self.dispatcher
+            .dispatch_on_main_thread_coalesced(key.clone(), runnable);
+        task.detach();
+    }
+
+    /// Runs `f` on the main thread once a window next presents a frame, for work that has to
+    /// see the result of layout, such as measuring what was drawn.
+    pub fn after_next_frame(&self, f: impl FnOnce() + 'static) {
+        let dispatcher = self.dispatcher.clone();
+        let (runnable, task) = async_task::spawn_local(async move { f() }, move |runnable| {
+            dispatcher.dispatch_on_main_thread(runnable)
+        });
+        self.dispatcher.dispatch_after_next_frame(runnable);
+        task.detach();
+    }
+
+    /// Releases the work waiting in [`Self::after_next_frame`]. Called by windows once they've
+    /// presented a frame.
+    pub(crate) fn frame_presented(&self) {
+        self.dispatcher.frame_presented();
+    }
 }
 
 /// Scope manages a set of tasks that are enqueued and waited on together. See [`BackgroundExecutor::scoped`].
@@ -27,23 +27,28 @@ use crate::{
     LineLayout, Pixels, PlatformInput, Point, RenderGlyphParams, RenderImageParams,
     RenderSvgParams, Scene, SharedString, Size, Task, TaskLabel, WindowContext,
 };
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use async_task::Runnable;
 use futures::channel::oneshot;
 use parking::Unparker;
 use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
 use seahash::SeaHasher;
 use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
 use std::borrow::Cow;
 use std::hash::{Hash, Hasher};
 use std::time::Duration;
 use std::{
     any::Any,
+    collections::VecDeque,
     fmt::{self, Debug},
     ops::Range,
     path::{Path, PathBuf},
     rc::Rc,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst},
+        Arc,
+    },
 };
 use uuid::Uuid;
 
@@ -61,17 +66,17 @@ pub use util::SemanticVersion;
 pub(crate) use windows::*;
 
 #[cfg(target_os = "macos")]
-pub(crate) fn current_platform() -> Rc<dyn Platform> {
-    Rc::new(MacPlatform::new())
+pub(crate) fn current_platform(dispatcher_options: DispatcherOptions) -> Rc<dyn Platform> {
+    Rc::new(MacPlatform::new(dispatcher_options))
 }
 #[cfg(target_os = "linux")]
-pub(crate) fn current_platform() -> Rc<dyn Platform> {
-    Rc::new(LinuxPlatform::new())
+pub(crate) fn current_platform(dispatcher_options: DispatcherOptions) -> Rc<dyn Platform> {
+    Rc::new(LinuxPlatform::new(dispatcher_options))
 }
 // todo("windows")
 #[cfg(target_os = "windows")]
-pub(crate) fn current_platform() -> Rc<dyn Platform> {
-    Rc::new(WindowsPlatform::new())
+pub(crate) fn current_platform(dispatcher_options: DispatcherOptions) -> Rc<dyn Platform> {
+    Rc::new(WindowsPlatform::new(dispatcher_options))
 }
 
 pub(crate) trait Platform: 'static {
@@ -153,8 +158,17 @@ pub trait PlatformDisplay: Send + Sync + Debug {
 
     /// Get the bounds for this display
     fn bounds(&self) -> Bounds<DevicePixels>;
+
+    /// Get the refresh rate of this display in hertz, for pacing animations.
+    /// Returns 60 when the platform can't determine it.
+    fn refresh_rate(&self) -> f32 {
+        DEFAULT_REFRESH_RATE
+    }
 }
 
+/// The refresh rate assumed for displays that don't report one.
+pub(crate) const DEFAULT_REFRESH_RATE: f32 = 60.;
+
 /// An opaque identifier for a hardware display
 #[derive(PartialEq, Eq, Hash, Copy, Clone)]
 pub struct DisplayId(pub(crate) u32);
@@ -218,6 +232,20 @@ pub(crate) trait PlatformWindow: HasWindowHandle + HasDisplayHandle {
     }
 }
 
+/// Options for the dispatcher that runs an app's tasks. See [`App::new_with_dispatcher_options`].
+///
+/// [`App::new_with_dispatcher_options`]: crate::App::new_with_dispatcher_options
+#[derive(Clone, Debug, Default)]
+pub struct DispatcherOptions {
+    /// When set, background tasks are handed to the platform's thread pool in round-robin order
+    /// by [`TaskLabel`], with at most this many of them submitted at a time, instead of as soon
+    /// as they are spawned. Tasks spawned without a label share one queue. This keeps a burst of
+    /// tasks with one label from holding up tasks with other labels that were spawned after it,
+    /// at the cost of some throughput when the pool has idle threads. Off by default. The
+    /// number of CPUs is a reasonable limit, and limits of zero are treated as one.
+    pub fair_scheduling: Option<usize>,
+}
+
 /// This type is public so that our test macro can generate and use it, but it should not
 /// be considered part of our public API.
 #[doc(hidden)]
@@ -225,8 +253,58 @@ pub trait PlatformDispatcher: Send + Sync {
     fn is_main_thread(&self) -> bool;
     fn dispatch(&self, runnable: Runnable, label: Option<TaskLabel>);
     fn dispatch_on_main_thread(&self, runnable: Runnable);
+    /// Dispatches the runnable to the main thread, unless a runnable dispatched with the same
+    /// key hasn't started running yet, in which case the runnable is dropped.
+    fn dispatch_on_main_thread_coalesced(&self, key: CoalescingKey, runnable: Runnable);
     fn dispatch_after(&self, duration: Duration, runnable: Runnable);
+    /// Dispatches the runnable to the main thread once `duration` has elapsed, without first
+    /// waking a background thread.
+    fn dispatch_after_on_main_thread(&self, duration: Duration, runnable: Runnable);
+    /// Dispatches the runnable to the main thread once a window next presents a frame.
+    /// Runnables dispatched before the same frame all run together, in the order they were
+    /// dispatched. Until a frame is presented, for example because nothing needs redrawing,
+    /// the runnable keeps waiting.
+    fn dispatch_after_next_frame(&self, runnable: Runnable);
+    /// Called on the main thread each time a window has presented a frame, to release the
+    /// runnables waiting in [`Self::dispatch_after_next_frame`].
+    fn frame_presented(&self);
+    /// Calls `callback` on a background thread every `interval` until the returned timer is
+    /// dropped. Each tick is due one interval after the previous one was due, rather than after
+    /// it finished running, so the timer doesn't drift. Ticks missed entirely, because the
+    /// callback ran for longer than the interval, are skipped.
+    fn dispatch_repeating(
+        &self,
+        interval: Duration,
+        callback: Box<dyn FnMut() + Send>,
+    ) -> RepeatingTimer;
     fn tick(&self, background_only: bool) -> bool;
+    /// Runs at most `max` of the runnables waiting for the main thread, in the order they were
+    /// dispatched, and returns how many ran, so that callers can bound the work done per frame.
+    /// Never blocks, and does nothing when called off the main thread.
+    fn try_tick(&self, max: usize) -> usize;
+    /// Returns true when no dispatched work is waiting to run, including delayed work.
+    fn is_idle(&self) -> bool;
+    /// Calls `callback` the next time the dispatcher becomes idle, or right away if it is idle
+    /// now. The callback may be called on any thread.
+    fn on_idle(&self, callback: Box<dyn FnOnce() + Send>);
+    /// Returns false while the main thread is blocked in a loop that doesn't run dispatched
+    /// work, such as the modal loop of a native panel, so that runnables dispatched to it will
+    /// wait until that loop ends.
+    fn is_main_run_loop_in_common_mode(&self) -> bool {
+        true
+    }
+    /// Runs `f` on the main thread and blocks the calling thread until it returns, for
+    /// integrations that must synchronously read state owned by the main thread. When called
+    /// on the main thread, `f` runs immediately instead of waiting on itself. Returns an error
+    /// instead of blocking forever if `f` is dropped before it finishes, either because the
+    /// runnable was dropped without running or because `f` panicked.
+    fn run_on_main_blocking(&self, f: Box<dyn FnOnce() + Send>) -> Result<()> {
+        if self.is_main_thread() {
+            f();
+            return Ok(());
+        }
+        run_blocking(|runnable| self.dispatch_on_main_thread(runnable), f)
+    }
     fn park(&self);
     fn unparker(&self) -> Unparker;
 
@@ -236,6 +314,340 @@ pub trait PlatformDispatcher: Send + Sync {
     }
 }
 
+/// Hands a runnable that calls `f` to `dispatch`, then blocks until `f` has returned, or until
+/// the runnable was dropped without finishing.
+fn run_blocking(dispatch: impl FnOnce(Runnable), f: Box<dyn FnOnce() + Send>) -> Result<()> {
+    // The sender is dropped along with the future, so `recv` fails instead of waiting forever
+    // when `f` doesn't get to finish.
+    let (done_tx, done_rx) = std::sync::mpsc::channel();
+    let (runnable, task) = async_task::spawn(
+        async move {
+            f();
+            done_tx.send(()).ok();
+        },
+        |_| {},
+    );
+    task.detach();
+    dispatch(runnable);
+    done_rx
+        .recv()
+        .map_err(|_| anyhow!("the main thread dropped the closure before it finished running"))
+}
+
+/// Identifies work that only needs to run once, no matter how many times it was dispatched
+/// before getting a chance to run. See [`ForegroundExecutor::spawn_coalesced`].
+#[derive(Clone, Debug, Default)]
+pub struct CoalescingKey {
+    pending: Arc<AtomicBool>,
+}
+
+impl CoalescingKey {
+    /// Creates a new key, with no work pending.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns whether work dispatched with this key is waiting to run.
+    pub fn is_pending(&self) -> bool {
+        self.pending.load(SeqCst)
+    }
+
+    /// Marks the key as pending, returning false if it already was.
+    pub(crate) fn begin(&self) -> bool {
+        !self.pending.swap(true, SeqCst)
+    }
+
+    /// Clears the pending flag. Called right before the coalesced work starts running.
+    pub(crate) fn finish(&self) {
+        self.pending.store(false, SeqCst);
+    }
+
+    /// Schedules the runnable with the given function, unless work dispatched with this key is
+    /// still pending. For dispatchers that can't clear the flag from their own trampoline.
+    pub(crate) fn schedule(
+        self,
+        runnable: Runnable,
+        schedule: impl Fn(Runnable) + Send + Sync + 'static,
+    ) {
+        if !self.begin() {
+            return;
+        }
+        let (runnable, task) = async_task::spawn(
+            async move {
+                self.finish();
+                runnable.run();
+            },
+            schedule,
+        );
+        runnable.schedule();
+        task.detach();
+    }
+}
+
+/// A timer scheduled with [`PlatformDispatcher::dispatch_repeating`], which stops firing as soon
+/// as it's cancelled or dropped. A tick that's already running finishes, but no further ones
+/// start.
+#[must_use]
+pub struct RepeatingTimer {
+    cancel: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl RepeatingTimer {
+    /// Creates a timer that calls `cancel` once, when the timer is cancelled or dropped.
+    pub(crate) fn new(cancel: impl FnOnce() + Send + 'static) -> Self {
+        Self {
+            cancel: Some(Box::new(cancel)),
+        }
+    }
+
+    /// Stops the timer. This is the same as dropping it.
+    pub fn cancel(self) {}
+}
+
+impl Drop for RepeatingTimer {
+    fn drop(&mut self) {
+        if let Some(cancel) = self.cancel.take() {
+            cancel();
+        }
+    }
+}
+
+/// Returns when a repeating timer is next due, given when its last tick was due, skipping the
+/// ticks that are already in the past.
+pub(crate) fn next_repeating_deadline<T>(last_deadline: T, interval: Duration, now: T) -> T
+where
+    T: Copy + Ord + std::ops::Add<Duration, Output = T>,
+{
+    let mut deadline = last_deadline + interval;
+    if interval.is_zero() {
+        return deadline.max(now);
+    }
+    while deadline < now {
+        deadline = deadline + interval;
+    }
+    deadline
+}
+
+/// Counts the runnables a dispatcher has been handed but hasn't finished with, so that it can
+/// tell when it's idle. Dispatchers count each runnable when it's dispatched and run it with
+/// [`Self::run`], which stops counting it. A runnable that's dropped without running, which
+/// only happens once the dispatcher is shutting down, stays counted.
+#[derive(Default)]
+pub(crate) struct PendingRunnables {
+    count: AtomicUsize,
+    idle_callbacks: parking_lot::Mutex<Vec<Box<dyn FnOnce() + Send>>>,
+}
+
+impl PendingRunnables {
+    /// Counts a runnable that's being dispatched as pending.
+    pub(crate) fn add(&self) {
+        self.count.fetch_add(1, SeqCst);
+    }
+
+    /// Runs a runnable that was counted with [`Self::add`], and stops counting it once it has
+    /// run, even if it panicked.
+    pub(crate) fn run(&self, runnable: Runnable) {
+        let _guard = PendingRunnableGuard(self);
+        runnable.run();
+    }
+
+    pub(crate) fn is_idle(&self) -> bool {
+        self.count.load(SeqCst) == 0
+    }
+
+    /// Calls `callback` once no runnables are pending, right away if none are.
+    pub(crate) fn on_idle(&self, callback: Box<dyn FnOnce() + Send>) {
+        let mut idle_callbacks = self.idle_callbacks.lock();
+        if self.is_idle() {
+            drop(idle_callbacks);
+            callback();
+        } else {
+            idle_callbacks.push(callback);
+        }
+    }
+
+    fn finish(&self) {
+        if self.count.fetch_sub(1, SeqCst) != 1 {
+            return;
+        }
+        // Another runnable may have been dispatched since, in which case the callbacks wait
+        // for that one to finish instead.
+        let idle_callbacks = {
+            let mut idle_callbacks = self.idle_callbacks.lock();
+            if !self.is_idle() {
+                return;
+            }
+            std::mem::take(&mut *idle_callbacks)
+        };
+        for callback in idle_callbacks {
+            callback();
+        }
+    }
+}
+
+struct PendingRunnableGuard<'a>(&'a PendingRunnables);
+
+impl Drop for PendingRunnableGuard<'_> {
+    fn drop(&mut self) {
+        self.0.finish();
+    }
+}
+
+/// Hands background runnables to a thread pool in round-robin order by [`TaskLabel`], keeping at
+/// most `max_in_flight` of them submitted at a time. The rest wait in a queue per label, so a
+/// burst of runnables with one label can't hold up runnables with other labels that were
+/// dispatched after it. See [`DispatcherOptions::fair_scheduling`].
+pub(crate) struct FairScheduler {
+    state: parking_lot::Mutex<FairSchedulerState>,
+    max_in_flight: usize,
+    submit: Box<dyn Fn(Runnable) + Send + Sync>,
+}
+
+#[derive(Default)]
+struct FairSchedulerState {
+    /// The queues that have runnables waiting, in the order they'll next be drained from.
+    queues: VecDeque<(Option<TaskLabel>, VecDeque<Runnable>)>,
+    in_flight: usize,
+}
+
+impl FairScheduler {
+    /// Creates a scheduler that passes runnables to `submit` once it's their turn.
+    pub(crate) fn new(
+        max_in_flight: usize,
+        submit: impl Fn(Runnable) + Send + Sync + 'static,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            state: Default::default(),
+            max_in_flight: max_in_flight.max(1),
+            submit: Box::new(submit),
+        })
+    }
+
+    pub(crate) fn dispatch(self: &Arc<Self>, label: Option<TaskLabel>, runnable: Runnable) {
+        {
+            let mut state = self.state.lock();
+            if let Some((_, queue)) = state.queues.iter_mut().find(|(l, _)| *l == label) {
+                queue.push_back(runnable);
+            } else {
+                state.queues.push_back((label, VecDeque::from([runnable])));
+            }
+        }
+        self.submit_ready();
+    }
+
+    /// Submits waiting runnables, taking one from each label's queue in turn, until
+    /// `max_in_flight` of them are in flight or none are left.
+    fn submit_ready(self: &Arc<Self>) {
+        let mut ready = SmallVec::<[Runnable; 4]>::new();
+        {
+            let mut state = self.state.lock();
+            while state.in_flight < self.max_in_flight {
+                let Some((label, mut queue)) = state.queues.pop_front() else {
+                    break;
+                };
+                if let Some(runnable) = queue.pop_front() {
+                    ready.push(runnable);
+                    state.in_flight += 1;
+                }
+                if !queue.is_empty() {
+                    state.queues.push_back((label, queue));
+                }
+            }
+        }
+
+        for runnable in ready {
+            let slot = FairSchedulerSlot(self.clone());
+            let (wrapped, task) = async_task::spawn(
+                async move {
+                    runnable.run();
+                    drop(slot);
+                },
+                |_| {},
+            );
+            task.detach();
+            (self.submit)(wrapped);
+        }
+    }
+}
+
+/// Frees a slot in the scheduler once the runnable holding it has run or been dropped.
+struct FairSchedulerSlot(Arc<FairScheduler>);
+
+impl Drop for FairSchedulerSlot {
+    fn drop(&mut self) {
+        self.0.state.lock().in_flight -= 1;
+        self.0.submit_ready();
+    }
+}
+
+/// The runnables dispatched to the main thread, in the order they were dispatched. Dispatchers
+/// wake the platform's main loop once for each runnable they queue, and each wake-up runs the
+/// runnable at the front of the queue, which lets [`PlatformDispatcher::try_tick`] run queued
+/// runnables early without changing their order.
+pub(crate) struct MainThreadQueue {
+    runnables: parking_lot::Mutex<VecDeque<Runnable>>,
+    next_frame: parking_lot::Mutex<Vec<Runnable>>,
+    /// The dispatcher's pending runnables. Dispatchers count the runnables they queue here, and
+    /// the queue stops counting them once they have run.
+    pending: Arc<PendingRunnables>,
+}
+
+impl MainThreadQueue {
+    pub(crate) fn new(pending: Arc<PendingRunnables>) -> Self {
+        Self {
+            runnables: Default::default(),
+            next_frame: Default::default(),
+            pending,
+        }
+    }
+
+    pub(crate) fn push(&self, runnable: Runnable) {
+        self.runnables.lock().push_back(runnable);
+    }
+
+    /// Runs the runnable at the front of the queue, returning false if the queue was empty.
+    pub(crate) fn run_next(&self) -> bool {
+        let Some(runnable) = self.runnables.lock().pop_front() else {
+            return false;
+        };
+        self.pending.run(runnable);
+        true
+    }
+
+    /// Holds the runnable back until [`Self::release_next_frame`] is called.
+    pub(crate) fn push_after_next_frame(&self, runnable: Runnable) {
+        self.next_frame.lock().push(runnable);
+    }
+
+    /// Moves the runnables held back for the next frame to the end of the queue and returns
+    /// how many there were, so that the dispatcher can wake the main loop for each of them.
+    pub(crate) fn release_next_frame(&self) -> usize {
+        let next_frame = std::mem::take(&mut *self.next_frame.lock());
+        let count = next_frame.len();
+        self.runnables.lock().extend(next_frame);
+        count
+    }
+
+    /// Runs up to `max` queued runnables, returning how many ran.
+    pub(crate) fn run_at_most(&self, max: usize) -> usize {
+        (0..max).take_while(|_| self.run_next()).count()
+    }
+
+    /// Returns a runnable that runs the next queued one, for platforms whose main loop is woken
+    /// by sending it a runnable.
+    pub(crate) fn wake_up_runnable(self: &Arc<Self>) -> Runnable {
+        let queue = self.clone();
+        let (runnable, task) = async_task::spawn(
+            async move {
+                queue.run_next();
+            },
+            |_| {},
+        );
+        task.detach();
+        runnable
+    }
+}
+
 pub(crate) trait PlatformTextSystem: Send + Sync {
     fn add_fonts(&self, fonts: Vec<Cow<'static, [u8]>>) -> Result<()>;
     fn all_font_names(&self) -> Vec<String>;
@@ -781,3 +1193,59 @@ impl ClipboardItem {
         hasher.finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fair_scheduler_round_robin() {
+        let submitted = Arc::new(parking_lot::Mutex::new(VecDeque::<Runnable>::new()));
+        let scheduler = FairScheduler::new(1, {
+            let submitted = submitted.clone();
+            move |runnable| submitted.lock().push_back(runnable)
+        });
+
+        let ran = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let (a, b) = (TaskLabel::new(), TaskLabel::new());
+        for (label, name) in [(a, "a1"), (a, "a2"), (a, "a3"), (b, "b1")] {
+            let ran = ran.clone();
+            let (runnable, task) = async_task::spawn(async move { ran.lock().push(name) }, |_| {});
+            task.detach();
+            scheduler.dispatch(Some(label), runnable);
+        }
+
+        loop {
+            assert!(submitted.lock().len() <= 1);
+            let Some(runnable) = submitted.lock().pop_front() else {
+                break;
+            };
+            runnable.run();
+        }
+        assert_eq!(*ran.lock(), ["a1", "b1", "a2", "a3"]);
+    }
+
+    #[test]
+    fn test_run_blocking() {
+        let (result_tx, result_rx) = std::sync::mpsc::channel();
+        run_blocking(
+            |runnable| {
+                std::thread::spawn(move || runnable.run());
+            },
+            Box::new(move || result_tx.send(42).unwrap()),
+        )
+        .unwrap();
+        assert_eq!(result_rx.try_recv(), Ok(42));
+
+        // Dropping the runnable without running it must not block the caller forever.
+        assert!(run_blocking(drop, Box::new(|| unreachable!())).is_err());
+
+        assert!(run_blocking(
+            |runnable| {
+                std::thread::spawn(move || runnable.run()).join().ok();
+            },
+            Box::new(|| panic!("closure panicked")),
+        )
+        .is_err());
+    }
+}
@@ -427,6 +427,8 @@ struct Underline {
     content_mask: Bounds,
     color: Hsla,
     thickness: f32,
+    wavy_amplitude: f32,
+    wavy_wavelength: f32,
     wavy: u32,
 }
 var<storage, read> b_underlines: array<Underline>;
@@ -467,8 +469,8 @@ fn fs_underline(input: UnderlineVarying) -> @location(0) vec4<f32> {
 
     let half_thickness = underline.thickness * 0.5;
     let st = (input.position.xy - underline.bounds.origin) / underline.bounds.size.y - vec2<f32>(0.0, 0.5);
-    let frequency = M_PI_F * 3.0 * underline.thickness / 8.0;
-    let amplitude = 1.0 / (2.0 * underline.thickness);
+    let frequency = 2.0 * M_PI_F * underline.bounds.size.y / underline.wavy_wavelength;
+    let amplitude = underline.wavy_amplitude / underline.bounds.size.y;
     let sine = sin(st.x * frequency) * amplitude;
     let dSine = cos(st.x * frequency) * amplitude * frequency;
     let distance = (st.y - sine) / sqrt(1.0 + dSine * dSine);
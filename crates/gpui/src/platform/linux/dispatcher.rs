@@ -4,7 +4,10 @@
 // todo(linux): remove
 #![allow(unused_variables)]
 
-use crate::{PlatformDispatcher, TaskLabel};
+use crate::{
+    next_repeating_deadline, CoalescingKey, DispatcherOptions, FairScheduler, MainThreadQueue,
+    PendingRunnables, PlatformDispatcher, RepeatingTimer, TaskLabel,
+};
 use async_task::Runnable;
 use calloop::{
     channel::{self, Sender},
@@ -13,65 +16,136 @@ use calloop::{
 };
 use parking::{Parker, Unparker};
 use parking_lot::Mutex;
-use std::{thread, time::Duration};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering::SeqCst},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 use util::ResultExt;
 
-struct TimerAfter {
-    duration: Duration,
-    runnable: Runnable,
+enum TimerRequest {
+    After {
+        duration: Duration,
+        runnable: Runnable,
+        on_main_thread: bool,
+    },
+    /// Calls the callback every interval until `cancelled` is set.
+    Repeating {
+        interval: Duration,
+        callback: Box<dyn FnMut() + Send>,
+        cancelled: Arc<AtomicBool>,
+    },
 }
 
 pub(crate) struct LinuxDispatcher {
     parker: Mutex<Parker>,
     main_sender: Sender<Runnable>,
-    timer_sender: Sender<TimerAfter>,
+    timer_sender: Sender<TimerRequest>,
     background_sender: flume::Sender<Runnable>,
+    /// When set, background runnables go through this before being sent to the background
+    /// threads.
+    fair_scheduler: Option<Arc<FairScheduler>>,
     _background_threads: Vec<thread::JoinHandle<()>>,
     main_thread_id: thread::ThreadId,
+    pending: Arc<PendingRunnables>,
+    main_queue: Arc<MainThreadQueue>,
 }
 
 impl LinuxDispatcher {
-    pub fn new(main_sender: Sender<Runnable>) -> Self {
+    pub fn new(main_sender: Sender<Runnable>, options: &DispatcherOptions) -> Self {
         let (background_sender, background_receiver) = flume::unbounded::<Runnable>();
         let thread_count = std::thread::available_parallelism()
             .map(|i| i.get())
             .unwrap_or(1);
+        let pending = Arc::<PendingRunnables>::default();
 
         let mut background_threads = (0..thread_count)
             .map(|_| {
                 let receiver = background_receiver.clone();
+                let pending = pending.clone();
                 std::thread::spawn(move || {
                     for runnable in receiver {
-                        runnable.run();
+                        pending.run(runnable);
                     }
                 })
             })
             .collect::<Vec<_>>();
 
-        let (timer_sender, timer_channel) = calloop::channel::channel::<TimerAfter>();
-        let timer_thread = std::thread::spawn(|| {
+        let (timer_sender, timer_channel) = calloop::channel::channel::<TimerRequest>();
+        let timer_main_sender = main_sender.clone();
+        let main_queue = Arc::new(MainThreadQueue::new(pending.clone()));
+        let timer_main_queue = main_queue.clone();
+        let timer_pending = pending.clone();
+        let timer_thread = std::thread::spawn(move || {
             let mut event_loop: EventLoop<()> =
                 EventLoop::try_new().expect("Failed to initialize timer loop!");
 
             let handle = event_loop.handle();
             let timer_handle = event_loop.handle();
             handle
-                .insert_source(timer_channel, move |e, _, _| {
-                    if let channel::Event::Msg(timer) = e {
+                .insert_source(timer_channel, move |e, _, _| match e {
+                    channel::Event::Msg(TimerRequest::After {
+                        duration,
+                        runnable,
+                        on_main_thread,
+                    }) => {
                         // This has to be in an option to satisfy the borrow checker. The callback below should only be scheduled once.
-                        let mut runnable = Some(timer.runnable);
+                        let mut runnable = Some(runnable);
+                        let main_thread = on_main_thread
+                            .then(|| (timer_main_sender.clone(), timer_main_queue.clone()));
+                        let pending = timer_pending.clone();
                         timer_handle
                             .insert_source(
-                                calloop::timer::Timer::from_duration(timer.duration),
+                                calloop::timer::Timer::from_duration(duration),
                                 move |e, _, _| {
                                     if let Some(runnable) = runnable.take() {
-                                        runnable.run();
+                                        match &main_thread {
+                                            // Queued like any other main thread runnable, so
+                                            // that it runs in order and try_tick can run it.
+                                            Some((main_sender, main_queue)) => {
+                                                main_queue.push(runnable);
+                                                main_sender
+                                                    .send(main_queue.wake_up_runnable())
+                                                    .log_err();
+                                            }
+                                            None => {
+                                                pending.run(runnable);
+                                            }
+                                        }
                                     }
                                     TimeoutAction::Drop
                                 },
                             )
                             .expect("Failed to start timer");
                     }
+                    channel::Event::Msg(TimerRequest::Repeating {
+                        interval,
+                        mut callback,
+                        cancelled,
+                    }) => {
+                        timer_handle
+                            .insert_source(
+                                calloop::timer::Timer::from_duration(interval),
+                                move |deadline, _, _| {
+                                    if cancelled.load(SeqCst) {
+                                        return TimeoutAction::Drop;
+                                    }
+                                    callback();
+                                    // Re-arm from when this tick was due, not from now, so the
+                                    // timer doesn't drift.
+                                    TimeoutAction::ToInstant(next_repeating_deadline(
+                                        deadline,
+                                        interval,
+                                        Instant::now(),
+                                    ))
+                                },
+                            )
+                            .expect("Failed to start timer");
+                    }
+                    channel::Event::Closed => {}
                 })
                 .expect("Failed to start timer thread");
 
@@ -80,13 +154,23 @@ impl LinuxDispatcher {
 
         background_threads.push(timer_thread);
 
+        let fair_scheduler = options.fair_scheduling.map(|max_in_flight| {
+            let background_sender = background_sender.clone();
+            FairScheduler::new(max_in_flight, move |runnable| {
+                background_sender.send(runnable).unwrap();
+            })
+        });
+
         Self {
             parker: Mutex::new(Parker::new()),
             main_sender,
             timer_sender,
             background_sender,
+            fair_scheduler,
             _background_threads: background_threads,
             main_thread_id: thread::current().id(),
+            pending,
+            main_queue,
         }
     }
 }
@@ -96,26 +180,107 @@ impl PlatformDispatcher for LinuxDispatcher {
         thread::current().id() == self.main_thread_id
     }
 
-    fn dispatch(&self, runnable: Runnable, _: Option<TaskLabel>) {
-        self.background_sender.send(runnable).unwrap();
+    fn dispatch(&self, runnable: Runnable, label: Option<TaskLabel>) {
+        self.pending.add();
+        match &self.fair_scheduler {
+            Some(scheduler) => scheduler.dispatch(label, runnable),
+            None => self.background_sender.send(runnable).unwrap(),
+        }
     }
 
     fn dispatch_on_main_thread(&self, runnable: Runnable) {
+        self.pending.add();
+        self.main_queue.push(runnable);
         self.main_sender
-            .send(runnable)
+            .send(self.main_queue.wake_up_runnable())
             .expect("Main thread is gone");
     }
 
+    fn dispatch_on_main_thread_coalesced(&self, key: CoalescingKey, runnable: Runnable) {
+        let main_sender = self.main_sender.clone();
+        let main_queue = self.main_queue.clone();
+        let pending = self.pending.clone();
+        // Counted once it's scheduled, as the key drops runnables dispatched while it's pending.
+        key.schedule(runnable, move |runnable| {
+            pending.add();
+            main_queue.push(runnable);
+            main_sender
+                .send(main_queue.wake_up_runnable())
+                .expect("Main thread is gone");
+        });
+    }
+
+    fn dispatch_after_next_frame(&self, runnable: Runnable) {
+        self.pending.add();
+        self.main_queue.push_after_next_frame(runnable);
+    }
+
+    fn frame_presented(&self) {
+        for _ in 0..self.main_queue.release_next_frame() {
+            self.main_sender
+                .send(self.main_queue.wake_up_runnable())
+                .expect("Main thread is gone");
+        }
+    }
+
     fn dispatch_after(&self, duration: Duration, runnable: Runnable) {
+        self.pending.add();
+        self.timer_sender
+            .send(TimerRequest::After {
+                duration,
+                runnable,
+                on_main_thread: false,
+            })
+            .expect("Timer thread has died");
+    }
+
+    fn dispatch_after_on_main_thread(&self, duration: Duration, runnable: Runnable) {
+        self.pending.add();
         self.timer_sender
-            .send(TimerAfter { duration, runnable })
+            .send(TimerRequest::After {
+                duration,
+                runnable,
+                on_main_thread: true,
+            })
             .expect("Timer thread has died");
     }
 
+    /// Runs the timer on the timer thread, which checks for cancellation before each tick.
+    fn dispatch_repeating(
+        &self,
+        interval: Duration,
+        callback: Box<dyn FnMut() + Send>,
+    ) -> RepeatingTimer {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.timer_sender
+            .send(TimerRequest::Repeating {
+                interval,
+                callback,
+                cancelled: cancelled.clone(),
+            })
+            .expect("Timer thread has died");
+        RepeatingTimer::new(move || cancelled.store(true, SeqCst))
+    }
+
     fn tick(&self, background_only: bool) -> bool {
         false
     }
 
+    fn try_tick(&self, max: usize) -> usize {
+        if !self.is_main_thread() {
+            return 0;
+        }
+        self.main_queue.run_at_most(max)
+    }
+
+    fn is_idle(&self) -> bool {
+        self.pending.is_idle()
+    }
+
+    fn on_idle(&self, callback: Box<dyn FnOnce() + Send>) {
+        self.pending.on_idle(callback)
+    }
+
     fn park(&self) {
         self.parker.lock().park()
     }
@@ -23,10 +23,10 @@ use wayland_client::Connection;
 use crate::platform::linux::client::Client;
 use crate::platform::linux::wayland::WaylandClient;
 use crate::{
-    px, Action, AnyWindowHandle, BackgroundExecutor, ClipboardItem, CursorStyle, DisplayId,
-    ForegroundExecutor, Keymap, LinuxDispatcher, LinuxTextSystem, Menu, PathPromptOptions, Pixels,
-    Platform, PlatformDisplay, PlatformInput, PlatformTextSystem, PlatformWindow, Result,
-    SemanticVersion, Task, WindowOptions, WindowParams,
+    px, Action, AnyWindowHandle, BackgroundExecutor, ClipboardItem, CursorStyle, DispatcherOptions,
+    DisplayId, ForegroundExecutor, Keymap, LinuxDispatcher, LinuxTextSystem, Menu,
+    PathPromptOptions, Pixels, Platform, PlatformDisplay, PlatformInput, PlatformTextSystem,
+    PlatformWindow, Result, SemanticVersion, Task, WindowOptions, WindowParams,
 };
 
 use super::x11::X11Client;
@@ -68,12 +68,12 @@ pub(crate) struct LinuxPlatform {
 
 impl Default for LinuxPlatform {
     fn default() -> Self {
-        Self::new()
+        Self::new(DispatcherOptions::default())
     }
 }
 
 impl LinuxPlatform {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(dispatcher_options: DispatcherOptions) -> Self {
         let wayland_display = env::var_os("WAYLAND_DISPLAY");
         let use_wayland = wayland_display.is_some_and(|display| !display.is_empty());
 
@@ -90,7 +90,7 @@ impl LinuxPlatform {
                 }
             });
 
-        let dispatcher = Arc::new(LinuxDispatcher::new(main_sender));
+        let dispatcher = Arc::new(LinuxDispatcher::new(main_sender, &dispatcher_options));
 
         let inner = Rc::new(LinuxPlatformInner {
             loop_handle: Rc::new(event_loop.handle()),
@@ -494,7 +494,7 @@ mod tests {
     use super::*;
 
     fn build_platform() -> LinuxPlatform {
-        let platform = LinuxPlatform::new();
+        let platform = LinuxPlatform::default();
         platform
     }
 }
@@ -1,7 +1,7 @@
 use crate::{
-    point, size, Bounds, DevicePixels, Font, FontFeatures, FontId, FontMetrics, FontRun, FontStyle,
-    FontWeight, GlyphId, LineLayout, Pixels, PlatformTextSystem, Point, RenderGlyphParams,
-    ShapedGlyph, SharedString, Size,
+    point, px, size, Bounds, DevicePixels, Font, FontFeatures, FontId, FontMetrics, FontRun,
+    FontStyle, FontWeight, GlyphId, LineLayout, Pixels, PlatformTextSystem, Point,
+    RenderGlyphParams, ShapedGlyph, SharedString, Size,
 };
 use anyhow::{anyhow, Context, Ok, Result};
 use collections::HashMap;
@@ -358,55 +358,98 @@ impl LinuxTextSystemState {
     // todo(linux) This is all a quick first pass, maybe we should be using cosmic_text::Buffer
     #[profiling::function]
     fn layout_line(&mut self, text: &str, font_size: Pixels, font_runs: &[FontRun]) -> LineLayout {
-        let mut attrs_list = AttrsList::new(Attrs::new());
-        let mut offs = 0;
-        for run in font_runs {
-            // todo(linux) We need to check we are doing utf properly
-            let font = &self.loaded_fonts_store[run.font_id.0];
-            let font = self.font_system.db().face(font.id()).unwrap();
-            attrs_list.add_span(
-                offs..(offs + run.len),
-                Attrs::new()
-                    .family(Family::Name(&font.families.first().unwrap().0))
-                    .stretch(font.stretch)
-                    .style(font.style)
-                    .weight(font.weight),
-            );
-            offs += run.len;
-        }
-        let mut line = BufferLine::new(text, attrs_list, cosmic_text::Shaping::Advanced);
-
-        let layout = line.layout(
-            &mut self.font_system,
-            font_size.0,
-            f32::MAX, // We do our own wrapping
-            cosmic_text::Wrap::None,
-        );
         let mut runs = Vec::new();
-
-        let layout = layout.first().unwrap();
-        for glyph in &layout.glyphs {
-            let font_id = glyph.font_id;
-            let font_id = self.font_id_for_cosmic_id(font_id);
-            let mut glyphs = SmallVec::new();
-            // todo(linux) this is definitely wrong, each glyph in glyphs from cosmic-text is a cluster with one glyph, ShapedRun takes a run of glyphs with the same font and direction
-            glyphs.push(ShapedGlyph {
-                id: GlyphId(glyph.glyph_id as u32),
-                position: point((glyph.x).into(), glyph.y.into()),
-                index: glyph.start,
-                is_emoji: self.is_emoji(font_id),
-            });
-
-            runs.push(crate::ShapedRun { font_id, glyphs });
+        let mut width = Pixels::ZERO;
+        let mut ascent = Pixels::ZERO;
+        let mut descent = Pixels::ZERO;
+
+        // cosmic-text lays out a line at a single size, so each stretch of runs that share a font
+        // size is laid out on its own and placed after the previous one on the common baseline.
+        let mut segment_start = 0;
+        let mut segment_runs = font_runs;
+        loop {
+            let segment_font_size = segment_runs
+                .first()
+                .and_then(|run| run.font_size)
+                .unwrap_or(font_size);
+            let segment_run_count = segment_runs
+                .iter()
+                .position(|run| run.font_size.unwrap_or(font_size) != segment_font_size)
+                .unwrap_or(segment_runs.len());
+            let (current_runs, remaining_runs) = segment_runs.split_at(segment_run_count);
+            let segment_len = current_runs.iter().map(|run| run.len).sum::<usize>();
+            let segment_text = &text[segment_start..segment_start + segment_len];
+
+            let mut attrs_list = AttrsList::new(Attrs::new());
+            let mut offs = 0;
+            for run in current_runs {
+                // todo(linux) We need to check we are doing utf properly
+                let font = &self.loaded_fonts_store[run.font_id.0];
+                let font = self.font_system.db().face(font.id()).unwrap();
+                attrs_list.add_span(
+                    offs..(offs + run.len),
+                    Attrs::new()
+                        .family(Family::Name(&font.families.first().unwrap().0))
+                        .stretch(font.stretch)
+                        .style(font.style)
+                        .weight(font.weight),
+                );
+                offs += run.len;
+            }
+            let mut line =
+                BufferLine::new(segment_text, attrs_list, cosmic_text::Shaping::Advanced);
+            let layout = line.layout(
+                &mut self.font_system,
+                segment_font_size.0,
+                f32::MAX, // We do our own wrapping
+                cosmic_text::Wrap::None,
+            );
+            let layout = layout.first().unwrap();
+            // cosmic-text applies the Unicode bidirectional algorithm to each segment, including
+            // isolates like LRI, RLI and PDI, and lays its glyphs out in visual order. Segments are
+            // placed one after the other, so text is only reordered within a segment.
+            let segment_run_start = runs.len();
+            for glyph in &layout.glyphs {
+                let font_id = self.font_id_for_cosmic_id(glyph.font_id);
+                let mut glyphs = SmallVec::new();
+                // todo(linux) this is definitely wrong, each glyph in glyphs from cosmic-text is a cluster with one glyph, ShapedRun takes a run of glyphs with the same font and direction
+                glyphs.push(ShapedGlyph {
+                    id: GlyphId(glyph.glyph_id as u32),
+                    position: point(width + px(glyph.x), glyph.y.into()),
+                    index: segment_start + glyph.start,
+                    is_emoji: self.is_emoji(font_id),
+                });
+                runs.push(crate::ShapedRun {
+                    font_id,
+                    font_size: segment_font_size,
+                    is_rtl: glyph.level.is_rtl(),
+                    glyphs,
+                });
+            }
+            // Put the glyphs back in the order of the text. The sort is stable, which keeps the
+            // glyphs of each character together.
+            runs[segment_run_start..].sort_by_key(|run| run.glyphs[0].index);
+
+            width += px(layout.w);
+            ascent = ascent.max(px(layout.max_ascent));
+            descent = descent.max(px(layout.max_descent));
+            segment_start += segment_len;
+            segment_runs = remaining_runs;
+            if segment_runs.is_empty() {
+                break;
+            }
         }
 
+        let is_rtl = runs.iter().any(|run| run.is_rtl);
         LineLayout {
             font_size,
-            width: layout.w.into(),
-            ascent: layout.max_ascent.into(),
-            descent: layout.max_descent.into(),
+            width,
+            ascent,
+            descent,
             runs,
             len: text.len(),
+            ligature_carets: Vec::new(),
+            is_rtl,
         }
     }
 }
@@ -2,8 +2,12 @@
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 
-use crate::{PlatformDispatcher, TaskLabel};
+use crate::{
+    CoalescingKey, DispatcherOptions, FairScheduler, MainThreadQueue, PendingRunnables,
+    PlatformDispatcher, RepeatingTimer, TaskLabel,
+};
 use async_task::Runnable;
+use core_foundation::runloop::CFRunLoop;
 use objc::{
     class, msg_send,
     runtime::{BOOL, YES},
@@ -14,7 +18,11 @@ use parking_lot::Mutex;
 use std::{
     ffi::c_void,
     ptr::{addr_of, NonNull},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering::SeqCst},
+        Arc, OnceLock,
+    },
+    thread::{self, ThreadId},
     time::Duration,
 };
 
@@ -29,51 +37,116 @@ pub(crate) fn dispatch_get_main_queue() -> dispatch_queue_t {
     unsafe { addr_of!(_dispatch_main_q) as *const _ as dispatch_queue_t }
 }
 
+/// The run loop modes in `NSRunLoopCommonModes`, which are the modes in which the main dispatch
+/// queue is drained.
+const COMMON_RUN_LOOP_MODES: [&str; 3] = [
+    "kCFRunLoopDefaultMode",
+    "NSModalPanelRunLoopMode",
+    "NSEventTrackingRunLoopMode",
+];
+
 pub(crate) struct MacDispatcher {
     parker: Arc<Mutex<Parker>>,
+    /// The main thread, when the dispatcher was created on it. Comparing thread ids is much
+    /// cheaper than asking `NSThread` on every call to `is_main_thread`.
+    main_thread_id: Option<ThreadId>,
+    /// When set, background runnables are queued per label and handed to libdispatch in
+    /// round-robin order instead of being submitted as soon as they are dispatched.
+    fair_scheduler: Option<Arc<FairScheduler>>,
+    /// The runnables that have been dispatched but haven't finished running. See
+    /// [`pending_runnables`].
+    pending: Arc<PendingRunnables>,
+    main_queue: Arc<MainThreadQueue>,
 }
 
 impl Default for MacDispatcher {
     fn default() -> Self {
-        Self::new()
+        Self::new(&DispatcherOptions::default())
     }
 }
 
 impl MacDispatcher {
-    pub fn new() -> Self {
+    pub fn new(options: &DispatcherOptions) -> Self {
+        let pending = pending_runnables().clone();
         MacDispatcher {
             parker: Arc::new(Mutex::new(Parker::new())),
+            main_thread_id: is_main_thread().then(|| thread::current().id()),
+            fair_scheduler: options
+                .fair_scheduling
+                .map(|max_in_flight| FairScheduler::new(max_in_flight, dispatch_to_global_queue)),
+            main_queue: Arc::new(MainThreadQueue::new(pending.clone())),
+            pending,
         }
     }
 }
 
+/// Libdispatch only hands the trampolines the runnable, so the runnables dispatched to the
+/// global queues are counted by the whole process rather than by each dispatcher. There's only
+/// one dispatcher per platform, anyway.
+fn pending_runnables() -> &'static Arc<PendingRunnables> {
+    static PENDING_RUNNABLES: OnceLock<Arc<PendingRunnables>> = OnceLock::new();
+    PENDING_RUNNABLES.get_or_init(Default::default)
+}
+
+fn is_main_thread() -> bool {
+    let is_main_thread: BOOL = unsafe { msg_send![class!(NSThread), isMainThread] };
+    is_main_thread == YES
+}
+
 impl PlatformDispatcher for MacDispatcher {
     fn is_main_thread(&self) -> bool {
-        let is_main_thread: BOOL = unsafe { msg_send![class!(NSThread), isMainThread] };
-        is_main_thread == YES
+        match self.main_thread_id {
+            Some(main_thread_id) => thread::current().id() == main_thread_id,
+            None => is_main_thread(),
+        }
     }
 
-    fn dispatch(&self, runnable: Runnable, _: Option<TaskLabel>) {
-        unsafe {
-            dispatch_async_f(
-                dispatch_get_global_queue(DISPATCH_QUEUE_PRIORITY_HIGH.try_into().unwrap(), 0),
-                runnable.into_raw().as_ptr() as *mut c_void,
-                Some(trampoline),
-            );
+    fn dispatch(&self, runnable: Runnable, label: Option<TaskLabel>) {
+        self.pending.add();
+        match &self.fair_scheduler {
+            Some(scheduler) => scheduler.dispatch(label, runnable),
+            None => dispatch_to_global_queue(runnable),
         }
     }
 
     fn dispatch_on_main_thread(&self, runnable: Runnable) {
+        self.pending.add();
+        post_to_main_queue(&self.main_queue, runnable);
+    }
+
+    fn dispatch_on_main_thread_coalesced(&self, key: CoalescingKey, runnable: Runnable) {
+        let main_queue = self.main_queue.clone();
+        let pending = self.pending.clone();
+        // Counted once it's scheduled, as the key drops runnables dispatched while it's pending.
+        key.schedule(runnable, move |runnable| {
+            pending.add();
+            post_to_main_queue(&main_queue, runnable)
+        });
+    }
+
+    fn dispatch_after(&self, duration: Duration, runnable: Runnable) {
+        self.pending.add();
         unsafe {
-            dispatch_async_f(
-                dispatch_get_main_queue(),
+            let queue =
+                dispatch_get_global_queue(DISPATCH_QUEUE_PRIORITY_DEFAULT.try_into().unwrap(), 0);
+            let when = dispatch_time(DISPATCH_TIME_NOW as u64, duration.as_nanos() as i64);
+            dispatch_after_f(
+                when,
+                queue,
                 runnable.into_raw().as_ptr() as *mut c_void,
                 Some(trampoline),
             );
         }
     }
 
-    fn dispatch_after(&self, duration: Duration, runnable: Runnable) {
+    /// Waits on a global queue, then queues the runnable like any other main thread runnable,
+    /// so that it runs in order and [`PlatformDispatcher::try_tick`] can run it.
+    fn dispatch_after_on_main_thread(&self, duration: Duration, runnable: Runnable) {
+        self.pending.add();
+        let context = Box::new(DelayedMainThreadRunnable {
+            main_queue: self.main_queue.clone(),
+            runnable,
+        });
         unsafe {
             let queue =
                 dispatch_get_global_queue(DISPATCH_QUEUE_PRIORITY_DEFAULT.try_into().unwrap(), 0);
@@ -81,16 +154,93 @@ impl PlatformDispatcher for MacDispatcher {
             dispatch_after_f(
                 when,
                 queue,
-                runnable.into_raw().as_ptr() as *mut c_void,
-                Some(trampoline),
+                Box::into_raw(context) as *mut c_void,
+                Some(delayed_main_thread_trampoline),
             );
         }
     }
 
+    fn dispatch_after_next_frame(&self, runnable: Runnable) {
+        self.pending.add();
+        self.main_queue.push_after_next_frame(runnable);
+    }
+
+    fn frame_presented(&self) {
+        for _ in 0..self.main_queue.release_next_frame() {
+            wake_main_queue(&self.main_queue);
+        }
+    }
+
+    /// Uses a dispatch timer source, which libdispatch keeps on its original schedule.
+    fn dispatch_repeating(
+        &self,
+        interval: Duration,
+        callback: Box<dyn FnMut() + Send>,
+    ) -> RepeatingTimer {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let context = Box::new(RepeatingTimerContext {
+            callback,
+            cancelled: cancelled.clone(),
+        });
+        let interval = interval.as_nanos().min(i64::MAX as u128) as u64;
+        let timer = unsafe {
+            let queue =
+                dispatch_get_global_queue(DISPATCH_QUEUE_PRIORITY_DEFAULT.try_into().unwrap(), 0);
+            let timer = dispatch_source_create(&_dispatch_source_type_timer, 0, 0, queue);
+            dispatch_set_context(
+                dispatch_object_t { _ds: timer },
+                Box::into_raw(context) as *mut c_void,
+            );
+            dispatch_source_set_event_handler_f(timer, Some(repeating_timer_trampoline));
+            dispatch_source_set_cancel_handler_f(timer, Some(repeating_timer_cancel_trampoline));
+            dispatch_source_set_timer(
+                timer,
+                dispatch_time(DISPATCH_TIME_NOW as u64, interval as i64),
+                interval,
+                0,
+            );
+            dispatch_resume(dispatch_object_t { _ds: timer });
+            timer as usize
+        };
+        RepeatingTimer::new(move || {
+            // A tick that is already queued checks the flag, as cancelling the source only
+            // stops new ones from being scheduled.
+            cancelled.store(true, SeqCst);
+            unsafe {
+                let timer = timer as dispatch_source_t;
+                dispatch_source_cancel(timer);
+                dispatch_release(dispatch_object_t { _ds: timer });
+            }
+        })
+    }
+
     fn tick(&self, _background_only: bool) -> bool {
         false
     }
 
+    fn try_tick(&self, max: usize) -> usize {
+        if !self.is_main_thread() {
+            return 0;
+        }
+        self.main_queue.run_at_most(max)
+    }
+
+    fn is_idle(&self) -> bool {
+        self.pending.is_idle()
+    }
+
+    fn on_idle(&self, callback: Box<dyn FnOnce() + Send>) {
+        self.pending.on_idle(callback)
+    }
+
+    /// Checks whether the main run loop is in one of the common modes, in which the main
+    /// dispatch queue is drained. This is false before the run loop has started, too.
+    fn is_main_run_loop_in_common_mode(&self) -> bool {
+        CFRunLoop::get_main()
+            .current_mode()
+            .map_or(false, |mode| COMMON_RUN_LOOP_MODES.contains(&mode.as_str()))
+    }
+
     fn park(&self) {
         self.parker.lock().park()
     }
@@ -100,7 +250,71 @@ impl PlatformDispatcher for MacDispatcher {
     }
 }
 
+/// Runs a runnable from a global queue, all of which were counted as pending.
 extern "C" fn trampoline(runnable: *mut c_void) {
     let task = unsafe { Runnable::<()>::from_raw(NonNull::new_unchecked(runnable as *mut ())) };
-    task.run();
+    pending_runnables().run(task);
+}
+
+struct DelayedMainThreadRunnable {
+    main_queue: Arc<MainThreadQueue>,
+    runnable: Runnable,
+}
+
+extern "C" fn delayed_main_thread_trampoline(context: *mut c_void) {
+    let DelayedMainThreadRunnable {
+        main_queue,
+        runnable,
+    } = *unsafe { Box::from_raw(context as *mut DelayedMainThreadRunnable) };
+    post_to_main_queue(&main_queue, runnable);
+}
+
+fn dispatch_to_global_queue(runnable: Runnable) {
+    unsafe {
+        dispatch_async_f(
+            dispatch_get_global_queue(DISPATCH_QUEUE_PRIORITY_HIGH.try_into().unwrap(), 0),
+            runnable.into_raw().as_ptr() as *mut c_void,
+            Some(trampoline),
+        );
+    }
+}
+
+/// Queues the runnable and has the main dispatch queue run the next queued runnable.
+fn post_to_main_queue(main_queue: &Arc<MainThreadQueue>, runnable: Runnable) {
+    main_queue.push(runnable);
+    wake_main_queue(main_queue);
+}
+
+fn wake_main_queue(main_queue: &Arc<MainThreadQueue>) {
+    unsafe {
+        dispatch_async_f(
+            dispatch_get_main_queue(),
+            Arc::into_raw(main_queue.clone()) as *mut c_void,
+            Some(main_queue_trampoline),
+        );
+    }
+}
+
+extern "C" fn main_queue_trampoline(context: *mut c_void) {
+    let main_queue = unsafe { Arc::from_raw(context as *const MainThreadQueue) };
+    main_queue.run_next();
+}
+
+struct RepeatingTimerContext {
+    callback: Box<dyn FnMut() + Send>,
+    cancelled: Arc<AtomicBool>,
+}
+
+/// Libdispatch never runs a source's handlers concurrently, so the callback can be borrowed
+/// mutably here.
+extern "C" fn repeating_timer_trampoline(context: *mut c_void) {
+    let context = unsafe { &mut *(context as *mut RepeatingTimerContext) };
+    if !context.cancelled.load(SeqCst) {
+        (context.callback)();
+    }
+}
+
+/// Runs after the last tick of a cancelled timer has finished.
+extern "C" fn repeating_timer_cancel_trampoline(context: *mut c_void) {
+    drop(unsafe { Box::from_raw(context as *mut RepeatingTimerContext) });
 }
@@ -1,4 +1,4 @@
-use crate::{point, size, Bounds, DevicePixels, DisplayId, PlatformDisplay};
+use crate::{point, size, Bounds, DevicePixels, DisplayId, PlatformDisplay, DEFAULT_REFRESH_RATE};
 use anyhow::Result;
 use cocoa::{
     appkit::NSScreen,
@@ -6,7 +6,9 @@ use cocoa::{
     foundation::{NSDictionary, NSString},
 };
 use core_foundation::uuid::{CFUUIDGetUUIDBytes, CFUUIDRef};
-use core_graphics::display::{CGDirectDisplayID, CGDisplayBounds, CGGetActiveDisplayList};
+use core_graphics::display::{
+    CGDirectDisplayID, CGDisplay, CGDisplayBounds, CGGetActiveDisplayList,
+};
 use objc::{msg_send, sel, sel_impl};
 use uuid::Uuid;
 
@@ -117,4 +119,13 @@ impl PlatformDisplay for MacDisplay {
             }
         }
     }
+    fn refresh_rate(&self) -> f32 {
+        // Built-in and some external displays report a refresh rate of 0 for
+        // their current mode, in which case we fall back to the default.
+        CGDisplay::new(self.0)
+            .display_mode()
+            .map(|mode| mode.refresh_rate() as f32)
+            .filter(|rate| *rate > 0.)
+            .unwrap_or(DEFAULT_REFRESH_RATE)
+    }
 }
@@ -1,9 +1,10 @@
 use super::{events::key_to_native, BoolExt};
 use crate::{
-    Action, AnyWindowHandle, BackgroundExecutor, ClipboardItem, CursorStyle, DisplayId,
-    ForegroundExecutor, Keymap, MacDispatcher, MacDisplay, MacTextSystem, MacWindow, Menu,
-    MenuItem, PathPromptOptions, Platform, PlatformDisplay, PlatformInput, PlatformTextSystem,
-    PlatformWindow, Result, SemanticVersion, Task, WindowAppearance, WindowParams,
+    Action, AnyWindowHandle, BackgroundExecutor, ClipboardItem, CursorStyle, DispatcherOptions,
+    DisplayId, ForegroundExecutor, Keymap, MacDispatcher, MacDisplay, MacTextSystem, MacWindow,
+    Menu, MenuItem, PathPromptOptions, Platform, PlatformDisplay, PlatformInput,
+    PlatformTextSystem, PlatformWindow, Result, SemanticVersion, Task, WindowAppearance,
+    WindowParams,
 };
 use anyhow::{anyhow, bail};
 use block::ConcreteBlock;
@@ -166,13 +167,13 @@ pub(crate) struct MacPlatformState {
 
 impl Default for MacPlatform {
     fn default() -> Self {
-        Self::new()
+        Self::new(DispatcherOptions::default())
     }
 }
 
 impl MacPlatform {
-    pub(crate) fn new() -> Self {
-        let dispatcher = Arc::new(MacDispatcher::new());
+    pub(crate) fn new(dispatcher_options: DispatcherOptions) -> Self {
+        let dispatcher = Arc::new(MacDispatcher::new(&dispatcher_options));
         Self(Mutex::new(MacPlatformState {
             background_executor: BackgroundExecutor::new(dispatcher.clone()),
             foreground_executor: ForegroundExecutor::new(dispatcher),
@@ -1291,7 +1292,7 @@ mod tests {
     }
 
     fn build_platform() -> MacPlatform {
-        let platform = MacPlatform::new();
+        let platform = MacPlatform::default();
         platform.0.lock().pasteboard = unsafe { NSPasteboard::pasteboardWithUniqueName(nil) };
         platform
     }
@@ -265,8 +265,9 @@ fragment float4 underline_fragment(UnderlineFragmentInput input [[stage_in]],
         float2(underline.bounds.origin.x, underline.bounds.origin.y);
     float2 st = ((input.position.xy - origin) / underline.bounds.size.height) -
                 float2(0., 0.5);
-    float frequency = (M_PI_F * (3. * underline.thickness)) / 8.;
-    float amplitude = 1. / (2. * underline.thickness);
+    float frequency = (2. * M_PI_F * underline.bounds.size.height) /
+                      underline.wavy_wavelength;
+    float amplitude = underline.wavy_amplitude / underline.bounds.size.height;
     float sine = sin(st.x * frequency) * amplitude;
     float dSine = cos(st.x * frequency) * amplitude * frequency;
     float distance = (st.y - sine) / sqrt(1. + dSine * dSine);
@@ -24,6 +24,7 @@ use core_text::{
         kCTFontSlantTrait, kCTFontSymbolicTrait, kCTFontWeightTrait, kCTFontWidthTrait,
     },
     line::CTLine,
+    run::CTRunRef,
     string_attributes::kCTFontAttributeName,
 };
 use font_kit::{
@@ -49,6 +50,9 @@ use super::open_type;
 #[allow(non_upper_case_globals)]
 const kCGImageAlphaOnly: u32 = 7;
 
+#[allow(non_upper_case_globals)]
+const kCTRunStatusRightToLeft: u32 = 1 << 0;
+
 pub(crate) struct MacTextSystem(RwLock<MacTextSystemState>);
 
 #[derive(Clone, PartialEq, Eq, Hash)]
@@ -466,7 +470,9 @@ impl MacTextSystemState {
                     string.set_attribute(
                         cf_range,
                         kCTFontAttributeName,
-                        &font.native_font().clone_with_font_size(font_size.into()),
+                        &font
+                            .native_font()
+                            .clone_with_font_size(run.font_size.unwrap_or(font_size).into()),
                     );
                 }
 
@@ -477,9 +483,11 @@ impl MacTextSystemState {
         }
 
         // Retrieve the glyphs from the shaped line, converting UTF16 offsets to UTF8 offsets.
+        // CoreText applies the Unicode bidirectional algorithm, including isolates like LRI, RLI
+        // and PDI, taking the paragraph's direction from its first strong character.
         let line = CTLine::new_with_attributed_string(string.as_concrete_TypeRef());
 
-        let mut runs = Vec::new();
+        let mut shaped_runs = Vec::new();
         for run in line.glyph_runs().into_iter() {
             let attributes = run.attributes().unwrap();
             let font = unsafe {
@@ -488,29 +496,61 @@ impl MacTextSystemState {
                     .downcast::<CTFont>()
                     .unwrap()
             };
+            let run_font_size = px(font.pt_size() as f32);
             let font_id = self.id_for_native_font(font);
+            let status = unsafe { CTRunGetStatus(run.as_concrete_TypeRef()) };
 
-            let mut ix_converter = StringIndexConverter::new(text);
-            let mut glyphs = SmallVec::new();
-            for ((glyph_id, position), glyph_utf16_ix) in run
+            // The glyphs of right-to-left runs are in visual order, so sort them back into the
+            // order of the text. The sort is stable, which keeps the glyphs of each character
+            // together.
+            let mut glyphs = run
                 .glyphs()
                 .iter()
                 .zip(run.positions().iter())
                 .zip(run.string_indices().iter())
-            {
-                let glyph_utf16_ix = usize::try_from(*glyph_utf16_ix).unwrap();
-                ix_converter.advance_to_utf16_ix(glyph_utf16_ix);
-                glyphs.push(ShapedGlyph {
-                    id: GlyphId(*glyph_id as u32),
-                    position: point(position.x as f32, position.y as f32).map(px),
-                    index: ix_converter.utf8_ix,
-                    is_emoji: self.is_emoji(font_id),
-                });
-            }
-
-            runs.push(ShapedRun { font_id, glyphs })
+                .map(|((glyph_id, position), glyph_utf16_ix)| {
+                    (
+                        usize::try_from(*glyph_utf16_ix).unwrap(),
+                        *glyph_id,
+                        *position,
+                    )
+                })
+                .collect::<Vec<_>>();
+            glyphs.sort_by_key(|(glyph_utf16_ix, _, _)| *glyph_utf16_ix);
+            shaped_runs.push((
+                font_id,
+                run_font_size,
+                status & kCTRunStatusRightToLeft != 0,
+                glyphs,
+            ));
         }
 
+        // The runs are in visual order too. Once they're in the order of the text, the glyph
+        // indices only grow, so a single converter can walk through the whole line.
+        shaped_runs.sort_by_key(|(_, _, _, glyphs)| glyphs.first().map(|glyph| glyph.0));
+        let is_rtl = shaped_runs.iter().any(|(_, _, is_rtl, _)| *is_rtl);
+        let mut ix_converter = StringIndexConverter::new(text);
+        let runs = shaped_runs
+            .into_iter()
+            .map(|(font_id, font_size, is_rtl, glyphs)| ShapedRun {
+                font_id,
+                font_size,
+                is_rtl,
+                glyphs: glyphs
+                    .into_iter()
+                    .map(|(glyph_utf16_ix, glyph_id, position)| {
+                        ix_converter.advance_to_utf16_ix(glyph_utf16_ix);
+                        ShapedGlyph {
+                            id: GlyphId(glyph_id as u32),
+                            position: point(position.x as f32, position.y as f32).map(px),
+                            index: ix_converter.utf8_ix,
+                            is_emoji: self.is_emoji(font_id),
+                        }
+                    })
+                    .collect(),
+            })
+            .collect();
+
         let typographic_bounds = line.get_typographic_bounds();
         LineLayout {
             runs,
@@ -519,6 +559,8 @@ impl MacTextSystemState {
             ascent: typographic_bounds.ascent.into(),
             descent: typographic_bounds.descent.into(),
             len: text.len(),
+            ligature_carets: Vec::new(),
+            is_rtl,
         }
     }
 
@@ -608,6 +650,8 @@ type CTTypesetterRef = *const __CFTypesetter;
 extern "C" {
     fn CTTypesetterCreateWithAttributedString(string: CFAttributedStringRef) -> CTTypesetterRef;
 
+    fn CTRunGetStatus(run: CTRunRef) -> u32;
+
     fn CTTypesetterSuggestLineBreak(
         typesetter: CTTypesetterRef,
         start_index: CFIndex,
@@ -760,6 +804,7 @@ mod tests {
         let mut style = FontRun {
             font_id,
             len: line.len(),
+            font_size: None,
         };
 
         let layout = fonts.layout_line(line, px(16.), &[style]);
@@ -776,4 +821,76 @@ mod tests {
                                                                  // There's no glyph for \u{feff}
         assert_eq!(layout.runs[0].glyphs[1].id, GlyphId(69u32)); // b
     }
+
+    #[test]
+    fn test_layout_line_ligature_carets() {
+        let fonts = MacTextSystem::new();
+        let font_id = fonts.font_id(&font("Helvetica")).unwrap();
+        let line = "fit";
+        let style = FontRun {
+            font_id,
+            len: line.len(),
+            font_size: None,
+        };
+
+        // Helvetica shapes "fi" as a single ligature glyph.
+        let mut layout = fonts.layout_line(line, px(16.), &[style]);
+        assert_eq!(layout.runs[0].glyphs.len(), 2);
+        layout.find_ligature_carets(line);
+        assert_eq!(layout.ligature_carets, &[1]);
+
+        let ligature_width = layout.x_for_index(2);
+        assert!(ligature_width > px(0.));
+        assert_eq!(layout.x_for_index(1), ligature_width * 0.5);
+        assert_eq!(layout.index_for_x(ligature_width * 0.6), Some(1));
+    }
+
+    #[test]
+    fn test_layout_line_bidi_isolates() {
+        let fonts = MacTextSystem::new();
+        let font_id = fonts.font_id(&font("Helvetica")).unwrap();
+        // An English label with an isolated Hebrew file name.
+        let line = "open \u{2067}שלום.txt\u{2069} now";
+        let style = FontRun {
+            font_id,
+            len: line.len(),
+            font_size: None,
+        };
+
+        let layout = fonts.layout_line(line, px(16.), &[style]);
+        let glyphs = layout
+            .runs
+            .iter()
+            .flat_map(|run| run.glyphs.iter())
+            .collect::<Vec<_>>();
+        // The runs and their glyphs are in the order of the text, and the Hebrew is in a
+        // right-to-left run.
+        assert!(glyphs.windows(2).all(|pair| pair[0].index <= pair[1].index));
+        assert!(glyphs
+            .iter()
+            .all(|glyph| line.is_char_boundary(glyph.index)));
+        let rtl_text = layout
+            .runs
+            .iter()
+            .filter(|run| run.is_rtl)
+            .flat_map(|run| run.glyphs.iter())
+            .map(|glyph| line[glyph.index..].chars().next().unwrap())
+            .collect::<String>();
+        assert!(rtl_text.starts_with("שלום"));
+        assert!(layout.is_rtl());
+
+        // The Hebrew letters are laid out from right to left, between the English words.
+        let x_of = |ch: char| {
+            let index = line.find(ch).unwrap();
+            glyphs
+                .iter()
+                .find(|glyph| glyph.index == index)
+                .unwrap()
+                .position
+                .x
+        };
+        assert!(x_of('n') < x_of('ם'));
+        assert!(x_of('ם') < x_of('ש'));
+        assert!(x_of('ש') < x_of('w'));
+    }
 }
@@ -1,4 +1,6 @@
-use crate::{PlatformDispatcher, TaskLabel};
+use crate::{
+    next_repeating_deadline, CoalescingKey, PlatformDispatcher, RepeatingTimer, TaskLabel,
+};
 use async_task::Runnable;
 use backtrace::Backtrace;
 use collections::{HashMap, HashSet, VecDeque};
@@ -7,9 +9,13 @@ use parking_lot::Mutex;
 use rand::prelude::*;
 use std::{
     future::Future,
+    mem,
     ops::RangeInclusive,
     pin::Pin,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering::SeqCst},
+        Arc,
+    },
     task::{Context, Poll},
     time::Duration,
 };
@@ -31,7 +37,8 @@ struct TestDispatcherState {
     foreground: HashMap<TestDispatcherId, VecDeque<Runnable>>,
     background: Vec<Runnable>,
     deprioritized_background: Vec<Runnable>,
-    delayed: Vec<(Duration, Runnable)>,
+    delayed: Vec<(Duration, Option<TestDispatcherId>, Runnable)>,
+    next_frame: Vec<(TestDispatcherId, Runnable)>,
     time: Duration,
     is_main_thread: bool,
     next_id: TestDispatcherId,
@@ -39,6 +46,7 @@ struct TestDispatcherState {
     waiting_backtrace: Option<Backtrace>,
     deprioritized_task_labels: HashSet<TaskLabel>,
     block_on_ticks: RangeInclusive<usize>,
+    idle_callbacks: Vec<Box<dyn FnOnce() + Send>>,
 }
 
 impl TestDispatcher {
@@ -50,6 +58,7 @@ impl TestDispatcher {
             background: Vec::new(),
             deprioritized_background: Vec::new(),
             delayed: Vec::new(),
+            next_frame: Vec::new(),
             time: Duration::ZERO,
             is_main_thread: true,
             next_id: TestDispatcherId(1),
@@ -57,6 +66,7 @@ impl TestDispatcher {
             waiting_backtrace: None,
             deprioritized_task_labels: Default::default(),
             block_on_ticks: 0..=1000,
+            idle_callbacks: Vec::new(),
         };
 
         TestDispatcher {
@@ -72,7 +82,7 @@ impl TestDispatcher {
         loop {
             self.run_until_parked();
             let state = self.state.lock();
-            let next_due_time = state.delayed.first().map(|(time, _)| *time);
+            let next_due_time = state.delayed.first().map(|(time, _, _)| *time);
             drop(state);
             if let Some(due_time) = next_due_time {
                 if due_time <= new_now {
@@ -162,6 +172,74 @@ impl TestDispatcher {
     }
 }
 
+impl TestDispatcherState {
+    fn move_due_delayed_runnables(&mut self) {
+        while let Some((deadline, _, _)) = self.delayed.first() {
+            if *deadline > self.time {
+                break;
+            }
+            let (_, foreground_id, runnable) = self.delayed.remove(0);
+            match foreground_id {
+                Some(id) => self.foreground.entry(id).or_default().push_back(runnable),
+                None => self.background.push(runnable),
+            }
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.foreground
+            .values()
+            .all(|runnables| runnables.is_empty())
+            && self.background.is_empty()
+            && self.deprioritized_background.is_empty()
+            && self.delayed.is_empty()
+            && self.next_frame.is_empty()
+    }
+
+    fn push_delayed(
+        &mut self,
+        duration: Duration,
+        foreground_id: Option<TestDispatcherId>,
+        runnable: Runnable,
+    ) {
+        let next_time = self.time + duration;
+        // Runnables due at the same time run in the order they were dispatched.
+        let ix = self.delayed.partition_point(|e| e.0 <= next_time);
+        self.delayed
+            .insert(ix, (next_time, foreground_id, runnable));
+    }
+}
+
+/// Queues the next tick of a repeating timer as delayed background work, so that it fires as the
+/// clock is advanced.
+fn schedule_repeating_tick(
+    state: Arc<Mutex<TestDispatcherState>>,
+    deadline: Duration,
+    interval: Duration,
+    mut callback: Box<dyn FnMut() + Send>,
+    cancelled: Arc<AtomicBool>,
+) {
+    let (runnable, task) = async_task::spawn(
+        {
+            let state = state.clone();
+            async move {
+                if cancelled.load(SeqCst) {
+                    return;
+                }
+                callback();
+                let now = state.lock().time;
+                let next_deadline = next_repeating_deadline(deadline, interval, now);
+                schedule_repeating_tick(state, next_deadline, interval, callback, cancelled);
+            }
+        },
+        |_| {},
+    );
+    task.detach();
+    let mut state = state.lock();
+    let now = state.time;
+    state.push_delayed(deadline.saturating_sub(now), None, runnable);
+}
+
 impl Clone for TestDispatcher {
     fn clone(&self) -> Self {
         let id = post_inc(&mut self.state.lock().next_id.0);
@@ -203,25 +281,65 @@ impl PlatformDispatcher for TestDispatcher {
         self.unparker.unpark();
     }
 
-    fn dispatch_after(&self, duration: std::time::Duration, runnable: Runnable) {
+    fn dispatch_on_main_thread_coalesced(&self, key: CoalescingKey, runnable: Runnable) {
+        let id = self.id;
+        let state = self.state.clone();
+        let unparker = self.unparker.clone();
+        key.schedule(runnable, move |runnable| {
+            state
+                .lock()
+                .foreground
+                .entry(id)
+                .or_default()
+                .push_back(runnable);
+            unparker.unpark();
+        });
+    }
+
+    fn dispatch_after_next_frame(&self, runnable: Runnable) {
+        self.state.lock().next_frame.push((self.id, runnable));
+    }
+
+    fn frame_presented(&self) {
         let mut state = self.state.lock();
-        let next_time = state.time + duration;
-        let ix = match state.delayed.binary_search_by_key(&next_time, |e| e.0) {
-            Ok(ix) | Err(ix) => ix,
-        };
-        state.delayed.insert(ix, (next_time, runnable));
+        for (id, runnable) in mem::take(&mut state.next_frame) {
+            state.foreground.entry(id).or_default().push_back(runnable);
+        }
+        drop(state);
+        self.unparker.unpark();
+    }
+
+    fn dispatch_after(&self, duration: std::time::Duration, runnable: Runnable) {
+        self.state.lock().push_delayed(duration, None, runnable);
+    }
+
+    fn dispatch_after_on_main_thread(&self, duration: Duration, runnable: Runnable) {
+        self.state
+            .lock()
+            .push_delayed(duration, Some(self.id), runnable);
+    }
+
+    fn dispatch_repeating(
+        &self,
+        interval: Duration,
+        callback: Box<dyn FnMut() + Send>,
+    ) -> RepeatingTimer {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let deadline = self.state.lock().time + interval;
+        schedule_repeating_tick(
+            self.state.clone(),
+            deadline,
+            interval,
+            callback,
+            cancelled.clone(),
+        );
+        // The pending tick still runs when its deadline comes, but doesn't call the callback.
+        RepeatingTimer::new(move || cancelled.store(true, SeqCst))
     }
 
     fn tick(&self, background_only: bool) -> bool {
         let mut state = self.state.lock();
-
-        while let Some((deadline, _)) = state.delayed.first() {
-            if *deadline > state.time {
-                break;
-            }
-            let (_, runnable) = state.delayed.remove(0);
-            state.background.push(runnable);
-        }
+        state.move_due_delayed_runnables();
 
         let foreground_len: usize = if background_only {
             0
@@ -239,7 +357,15 @@ impl PlatformDispatcher for TestDispatcher {
         if foreground_len == 0 && background_len == 0 {
             let deprioritized_background_len = state.deprioritized_background.len();
             if deprioritized_background_len == 0 {
-                return false;
+                if !state.is_idle() || state.idle_callbacks.is_empty() {
+                    return false;
+                }
+                let idle_callbacks = mem::take(&mut state.idle_callbacks);
+                drop(state);
+                for callback in idle_callbacks {
+                    callback();
+                }
+                return true;
             }
             let ix = state.random.gen_range(0..deprioritized_background_len);
             main_thread = false;
@@ -274,6 +400,45 @@ impl PlatformDispatcher for TestDispatcher {
         true
     }
 
+    /// Unlike `tick`, only runs this dispatcher's foreground runnables, in the order they were
+    /// dispatched.
+    fn try_tick(&self, max: usize) -> usize {
+        let mut ran = 0;
+        while ran < max {
+            let mut state = self.state.lock();
+            if !state.is_main_thread {
+                break;
+            }
+            state.move_due_delayed_runnables();
+            let Some(runnable) = state
+                .foreground
+                .get_mut(&self.id)
+                .and_then(|runnables| runnables.pop_front())
+            else {
+                break;
+            };
+            drop(state);
+            runnable.run();
+            ran += 1;
+        }
+        ran
+    }
+
+    fn is_idle(&self) -> bool {
+        self.state.lock().is_idle()
+    }
+
+    /// The callbacks are called from `tick`, once everything else has run.
+    fn on_idle(&self, callback: Box<dyn FnOnce() + Send>) {
+        let mut state = self.state.lock();
+        if state.is_idle() {
+            drop(state);
+            callback();
+        } else {
+            state.idle_callbacks.push(callback);
+        }
+    }
+
     fn park(&self) {
         self.parker.lock().park();
     }
@@ -286,3 +451,173 @@ impl PlatformDispatcher for TestDispatcher {
         Some(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BackgroundExecutor;
+
+    fn runnable(f: impl FnOnce() + Send + 'static) -> Runnable {
+        let (runnable, task) = async_task::spawn(async move { f() }, |_| {});
+        task.detach();
+        runnable
+    }
+
+    #[test]
+    fn test_wait_until_idle() {
+        let dispatcher = TestDispatcher::new(StdRng::seed_from_u64(0));
+        let executor = BackgroundExecutor::new(Arc::new(dispatcher.clone()));
+        let ran = Arc::new(AtomicBool::new(false));
+        executor
+            .spawn({
+                let ran = ran.clone();
+                async move { ran.store(true, SeqCst) }
+            })
+            .detach();
+
+        let idle = executor.wait_until_idle();
+        assert!(!dispatcher.is_idle());
+        executor.block_test(idle);
+        assert!(ran.load(SeqCst));
+        assert!(dispatcher.is_idle());
+    }
+
+    #[test]
+    fn test_on_idle_waits_for_delayed_runnables() {
+        let dispatcher = TestDispatcher::new(StdRng::seed_from_u64(0));
+        dispatcher.dispatch_after(Duration::from_millis(10), runnable(|| {}));
+
+        let idle = Arc::new(AtomicBool::new(false));
+        dispatcher.on_idle(Box::new({
+            let idle = idle.clone();
+            move || idle.store(true, SeqCst)
+        }));
+        dispatcher.run_until_parked();
+        assert!(!idle.load(SeqCst));
+
+        dispatcher.advance_clock(Duration::from_millis(10));
+        assert!(idle.load(SeqCst));
+    }
+
+    #[test]
+    fn test_try_tick_runs_at_most_max_main_thread_runnables() {
+        let dispatcher = TestDispatcher::new(StdRng::seed_from_u64(0));
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        for i in 0..3 {
+            let ran = ran.clone();
+            dispatcher.dispatch_on_main_thread(runnable(move || ran.lock().push(i)));
+        }
+        dispatcher.dispatch(runnable(|| unreachable!()), None);
+
+        assert_eq!(dispatcher.try_tick(2), 2);
+        assert_eq!(*ran.lock(), [0, 1]);
+        assert_eq!(dispatcher.try_tick(2), 1);
+        assert_eq!(*ran.lock(), [0, 1, 2]);
+        assert_eq!(dispatcher.try_tick(2), 0);
+    }
+
+    #[test]
+    fn test_run_on_main_blocking_on_the_main_thread() {
+        let dispatcher = TestDispatcher::new(StdRng::seed_from_u64(0));
+        let executor = BackgroundExecutor::new(Arc::new(dispatcher));
+        assert_eq!(executor.run_on_main_blocking(|| 42).unwrap(), 42);
+    }
+
+    #[test]
+    fn test_repeating_timer_rearms_from_scheduled_time() {
+        let dispatcher = TestDispatcher::new(StdRng::seed_from_u64(0));
+        let ticks = Arc::new(Mutex::new(Vec::new()));
+        let timer = dispatcher.dispatch_repeating(
+            Duration::from_millis(10),
+            Box::new({
+                let state = dispatcher.state.clone();
+                let ticks = ticks.clone();
+                move || {
+                    let mut state = state.lock();
+                    ticks.lock().push(state.time);
+                    // Simulate a tick that takes a while to run.
+                    state.time += Duration::from_millis(4);
+                }
+            }),
+        );
+
+        dispatcher.advance_clock(Duration::from_millis(25));
+        assert_eq!(
+            *ticks.lock(),
+            [Duration::from_millis(10), Duration::from_millis(20)]
+        );
+
+        drop(timer);
+        dispatcher.advance_clock(Duration::from_millis(100));
+        assert_eq!(ticks.lock().len(), 2);
+    }
+
+    #[test]
+    fn test_after_next_frame() {
+        let dispatcher = TestDispatcher::new(StdRng::seed_from_u64(0));
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        for i in 0..2 {
+            let ran = ran.clone();
+            dispatcher.dispatch_after_next_frame(runnable(move || ran.lock().push(i)));
+        }
+        dispatcher.run_until_parked();
+        assert!(ran.lock().is_empty());
+
+        dispatcher.frame_presented();
+        let ran_after_frame = ran.clone();
+        dispatcher.dispatch_after_next_frame(runnable(move || ran_after_frame.lock().push(2)));
+        dispatcher.run_until_parked();
+        assert_eq!(*ran.lock(), [0, 1]);
+
+        dispatcher.frame_presented();
+        dispatcher.run_until_parked();
+        assert_eq!(*ran.lock(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn test_coalesced_dispatch_runs_once_per_key() {
+        let dispatcher = TestDispatcher::new(StdRng::seed_from_u64(0));
+        let key = CoalescingKey::new();
+        let runs = Arc::new(Mutex::new(0));
+        for _ in 0..3 {
+            let runs = runs.clone();
+            dispatcher.dispatch_on_main_thread_coalesced(
+                key.clone(),
+                runnable(move || *runs.lock() += 1),
+            );
+        }
+        assert!(key.is_pending());
+        dispatcher.run_until_parked();
+        assert_eq!(*runs.lock(), 1);
+        assert!(!key.is_pending());
+
+        // Once the coalesced work has started, the key can be used again.
+        let runs_again = runs.clone();
+        dispatcher
+            .dispatch_on_main_thread_coalesced(key, runnable(move || *runs_again.lock() += 1));
+        dispatcher.run_until_parked();
+        assert_eq!(*runs.lock(), 2);
+    }
+
+    #[test]
+    fn test_main_thread_delays_fire_in_order() {
+        let dispatcher = TestDispatcher::new(StdRng::seed_from_u64(0));
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        for (name, delay) in [("c", 30), ("a", 10), ("b", 20), ("a2", 10)] {
+            let fired = fired.clone();
+            let state = dispatcher.state.clone();
+            dispatcher.dispatch_after_on_main_thread(
+                Duration::from_millis(delay),
+                runnable(move || {
+                    assert!(state.lock().is_main_thread);
+                    fired.lock().push(name);
+                }),
+            );
+        }
+
+        dispatcher.advance_clock(Duration::from_millis(15));
+        assert_eq!(*fired.lock(), ["a", "a2"]);
+        dispatcher.advance_clock(Duration::from_millis(15));
+        assert_eq!(*fired.lock(), ["a", "a2", "b", "c"]);
+    }
+}
@@ -1,9 +1,10 @@
 use std::{
     sync::{
-        atomic::{AtomicIsize, Ordering},
+        atomic::{AtomicBool, AtomicIsize, Ordering},
         Arc,
     },
     thread::{current, ThreadId},
+    time::{Duration, Instant},
 };
 
 use async_task::Runnable;
@@ -12,7 +13,10 @@ use parking::Parker;
 use parking_lot::Mutex;
 use windows::Win32::{Foundation::*, System::Threading::*};
 
-use crate::{PlatformDispatcher, TaskLabel};
+use crate::{
+    next_repeating_deadline, CoalescingKey, DispatcherOptions, FairScheduler, MainThreadQueue,
+    PendingRunnables, PlatformDispatcher, RepeatingTimer, TaskLabel,
+};
 
 pub(crate) struct WindowsDispatcher {
     threadpool: PTP_POOL,
@@ -20,10 +24,19 @@ pub(crate) struct WindowsDispatcher {
     parker: Mutex<Parker>,
     main_thread_id: ThreadId,
     dispatch_event: HANDLE,
+    /// When set, background runnables go through this before being submitted to the thread
+    /// pool.
+    fair_scheduler: Option<Arc<FairScheduler>>,
+    pending: Arc<PendingRunnables>,
+    main_queue: Arc<MainThreadQueue>,
 }
 
 impl WindowsDispatcher {
-    pub(crate) fn new(main_sender: Sender<Runnable>, dispatch_event: HANDLE) -> Self {
+    pub(crate) fn new(
+        main_sender: Sender<Runnable>,
+        dispatch_event: HANDLE,
+        options: &DispatcherOptions,
+    ) -> Self {
         let parker = Mutex::new(Parker::new());
         let threadpool = unsafe {
             let ret = CreateThreadpool(None);
@@ -40,31 +53,22 @@ impl WindowsDispatcher {
             ret
         };
         let main_thread_id = current().id();
+        let pending = Arc::<PendingRunnables>::default();
+        let fair_scheduler = options.fair_scheduling.map(|max_in_flight| {
+            let pending = pending.clone();
+            FairScheduler::new(max_in_flight, move |runnable| {
+                dispatch_on_threadpool(threadpool, &pending, runnable)
+            })
+        });
         WindowsDispatcher {
             threadpool,
             main_sender,
             parker,
             main_thread_id,
             dispatch_event,
-        }
-    }
-
-    fn dispatch_on_threadpool(&self, runnable: Runnable) {
-        unsafe {
-            let ptr = Box::into_raw(Box::new(runnable));
-            let environment = get_threadpool_environment(self.threadpool);
-            let Ok(work) =
-                CreateThreadpoolWork(Some(threadpool_runner), Some(ptr as _), Some(&environment))
-                    .inspect_err(|_| {
-                        log::error!(
-                            "unable to dispatch work on thread pool: {}",
-                            std::io::Error::last_os_error()
-                        )
-                    })
-            else {
-                return;
-            };
-            SubmitThreadpoolWork(work);
+            fair_scheduler,
+            main_queue: Arc::new(MainThreadQueue::new(pending.clone())),
+            pending,
         }
     }
 }
@@ -75,51 +79,128 @@ impl PlatformDispatcher for WindowsDispatcher {
     }
 
     fn dispatch(&self, runnable: Runnable, label: Option<TaskLabel>) {
-        self.dispatch_on_threadpool(runnable);
+        self.pending.add();
+        match &self.fair_scheduler {
+            Some(scheduler) => scheduler.dispatch(label, runnable),
+            None => dispatch_on_threadpool(self.threadpool, &self.pending, runnable),
+        }
         if let Some(label) = label {
             log::debug!("TaskLabel: {label:?}");
         }
     }
 
     fn dispatch_on_main_thread(&self, runnable: Runnable) {
+        self.pending.add();
+        self.main_queue.push(runnable);
         self.main_sender
-            .send(runnable)
+            .send(self.main_queue.wake_up_runnable())
             .inspect_err(|e| log::error!("Dispatch failed: {e}"))
             .ok();
         unsafe { SetEvent(self.dispatch_event) }.ok();
     }
 
+    fn dispatch_on_main_thread_coalesced(&self, key: CoalescingKey, runnable: Runnable) {
+        let main_sender = self.main_sender.clone();
+        let main_queue = self.main_queue.clone();
+        let dispatch_event = self.dispatch_event;
+        let pending = self.pending.clone();
+        // Counted once it's scheduled, as the key drops runnables dispatched while it's pending.
+        key.schedule(runnable, move |runnable| {
+            pending.add();
+            main_queue.push(runnable);
+            main_sender
+                .send(main_queue.wake_up_runnable())
+                .inspect_err(|e| log::error!("Dispatch failed: {e}"))
+                .ok();
+            unsafe { SetEvent(dispatch_event) }.ok();
+        });
+    }
+
+    fn dispatch_after_next_frame(&self, runnable: Runnable) {
+        self.pending.add();
+        self.main_queue.push_after_next_frame(runnable);
+    }
+
+    fn frame_presented(&self) {
+        let released = self.main_queue.release_next_frame();
+        if released == 0 {
+            return;
+        }
+        for _ in 0..released {
+            self.main_sender
+                .send(self.main_queue.wake_up_runnable())
+                .inspect_err(|e| log::error!("Dispatch failed: {e}"))
+                .ok();
+        }
+        unsafe { SetEvent(self.dispatch_event) }.ok();
+    }
+
     fn dispatch_after(&self, duration: std::time::Duration, runnable: Runnable) {
+        self.pending.add();
         if duration.as_millis() == 0 {
-            self.dispatch_on_threadpool(runnable);
+            dispatch_on_threadpool(self.threadpool, &self.pending, runnable);
             return;
         }
-        unsafe {
-            let mut handle = std::mem::zeroed();
-            let task = Arc::new(DelayedTask::new(runnable));
-            let _ = CreateTimerQueueTimer(
-                &mut handle,
-                None,
-                Some(timer_queue_runner),
-                Some(Arc::into_raw(task.clone()) as _),
-                duration.as_millis() as u32,
-                0,
-                WT_EXECUTEONLYONCE,
-            )
-            .inspect_err(|_| {
-                log::error!(
-                    "unable to dispatch delayed task: {}",
-                    std::io::Error::last_os_error()
-                )
-            });
-            task.raw_timer_handle.store(handle.0, Ordering::SeqCst);
+        dispatch_on_timer_queue(
+            duration,
+            DelayedTask::new(runnable, Some(self.pending.clone()), None),
+        );
+    }
+
+    fn dispatch_after_on_main_thread(&self, duration: std::time::Duration, runnable: Runnable) {
+        if duration.as_millis() == 0 {
+            self.dispatch_on_main_thread(runnable);
+            return;
         }
+        self.pending.add();
+        let main_thread = (
+            self.main_sender.clone(),
+            self.main_queue.clone(),
+            self.dispatch_event,
+        );
+        dispatch_on_timer_queue(
+            duration,
+            DelayedTask::new(runnable, None, Some(main_thread)),
+        );
+    }
+
+    /// Arms a one-shot timer on the timer queue for each tick, aimed at when the tick is due
+    /// rather than when the previous one finished, so the timer doesn't drift.
+    fn dispatch_repeating(
+        &self,
+        interval: Duration,
+        callback: Box<dyn FnMut() + Send>,
+    ) -> RepeatingTimer {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        schedule_repeating_tick(
+            Instant::now() + interval,
+            interval,
+            callback,
+            cancelled.clone(),
+        );
+        // The tick that's already armed still fires, but doesn't call the callback.
+        RepeatingTimer::new(move || cancelled.store(true, Ordering::SeqCst))
     }
 
     fn tick(&self, _background_only: bool) -> bool {
         false
     }
 
+    fn try_tick(&self, max: usize) -> usize {
+        if !self.is_main_thread() {
+            return 0;
+        }
+        self.main_queue.run_at_most(max)
+    }
+
+    fn is_idle(&self) -> bool {
+        self.pending.is_idle()
+    }
+
+    fn on_idle(&self, callback: Box<dyn FnOnce() + Send>) {
+        self.pending.on_idle(callback)
+    }
+
     fn park(&self) {
         self.parker.lock().park();
     }
@@ -129,20 +210,108 @@ impl PlatformDispatcher for WindowsDispatcher {
     }
 }
 
+/// Submits a runnable that was counted as pending to the thread pool.
+fn dispatch_on_threadpool(
+    threadpool: PTP_POOL,
+    pending: &Arc<PendingRunnables>,
+    runnable: Runnable,
+) {
+    unsafe {
+        let ptr = Box::into_raw(Box::new((runnable, pending.clone())));
+        let environment = get_threadpool_environment(threadpool);
+        let Ok(work) =
+            CreateThreadpoolWork(Some(threadpool_runner), Some(ptr as _), Some(&environment))
+                .inspect_err(|_| {
+                    log::error!(
+                        "unable to dispatch work on thread pool: {}",
+                        std::io::Error::last_os_error()
+                    )
+                })
+        else {
+            return;
+        };
+        SubmitThreadpoolWork(work);
+    }
+}
+
+fn dispatch_on_timer_queue(duration: Duration, task: DelayedTask) {
+    unsafe {
+        let mut handle = std::mem::zeroed();
+        let task = Arc::new(task);
+        let _ = CreateTimerQueueTimer(
+            &mut handle,
+            None,
+            Some(timer_queue_runner),
+            Some(Arc::into_raw(task.clone()) as _),
+            duration.as_millis() as u32,
+            0,
+            WT_EXECUTEONLYONCE,
+        )
+        .inspect_err(|_| {
+            log::error!(
+                "unable to dispatch delayed task: {}",
+                std::io::Error::last_os_error()
+            )
+        });
+        task.raw_timer_handle.store(handle.0, Ordering::SeqCst);
+    }
+}
+
+/// Queues the next tick of a repeating timer as a one-shot timer, due at `deadline`.
+fn schedule_repeating_tick(
+    deadline: Instant,
+    interval: Duration,
+    mut callback: Box<dyn FnMut() + Send>,
+    cancelled: Arc<AtomicBool>,
+) {
+    let (runnable, task) = async_task::spawn(
+        async move {
+            if cancelled.load(Ordering::SeqCst) {
+                return;
+            }
+            callback();
+            let next_deadline = next_repeating_deadline(deadline, interval, Instant::now());
+            schedule_repeating_tick(next_deadline, interval, callback, cancelled);
+        },
+        |_| {},
+    );
+    task.detach();
+    dispatch_on_timer_queue(
+        deadline.saturating_duration_since(Instant::now()),
+        DelayedTask::new(runnable, None, None),
+    );
+}
+
 extern "system" fn threadpool_runner(
     _: PTP_CALLBACK_INSTANCE,
     ptr: *mut std::ffi::c_void,
     _: PTP_WORK,
 ) {
     unsafe {
-        let runnable = Box::from_raw(ptr as *mut Runnable);
-        runnable.run();
+        let (runnable, pending) = *Box::from_raw(ptr as *mut (Runnable, Arc<PendingRunnables>));
+        pending.run(runnable);
     }
 }
 
 unsafe extern "system" fn timer_queue_runner(ptr: *mut std::ffi::c_void, _: BOOLEAN) {
     let task = Arc::from_raw(ptr as *mut DelayedTask);
-    task.runnable.lock().take().unwrap().run();
+    let runnable = task.runnable.lock().take().unwrap();
+    match &task.main_thread {
+        // Queued like any other main thread runnable, so that it runs in order and try_tick can
+        // run it.
+        Some((main_sender, main_queue, dispatch_event)) => {
+            main_queue.push(runnable);
+            main_sender
+                .send(main_queue.wake_up_runnable())
+                .inspect_err(|e| log::error!("Dispatch failed: {e}"))
+                .ok();
+            unsafe { SetEvent(*dispatch_event) }.ok();
+        }
+        None => match &task.pending {
+            Some(pending) => pending.run(runnable),
+            None => runnable.run(),
+        },
+    }
     unsafe {
         let timer = task.raw_timer_handle.load(Ordering::SeqCst);
         let _ = DeleteTimerQueueTimer(None, HANDLE(timer), None);
@@ -152,13 +321,26 @@ unsafe extern "system" fn timer_queue_runner(ptr: *mut std::ffi::c_void, _: BOOL
 struct DelayedTask {
     runnable: Mutex<Option<Runnable>>,
     raw_timer_handle: AtomicIsize,
+    /// The pending runnables to stop counting the runnable in once it has run on the timer
+    /// thread. Unset for repeating timer ticks, which aren't counted, and for runnables that
+    /// are forwarded to the main thread, whose queue stops counting them instead.
+    pending: Option<Arc<PendingRunnables>>,
+    /// When set, the runnable is forwarded to the main thread instead of running on the timer
+    /// thread.
+    main_thread: Option<(Sender<Runnable>, Arc<MainThreadQueue>, HANDLE)>,
 }
 
 impl DelayedTask {
-    pub fn new(runnable: Runnable) -> Self {
+    pub fn new(
+        runnable: Runnable,
+        pending: Option<Arc<PendingRunnables>>,
+        main_thread: Option<(Sender<Runnable>, Arc<MainThreadQueue>, HANDLE)>,
+    ) -> Self {
         DelayedTask {
             runnable: Mutex::new(Some(runnable)),
             raw_timer_handle: AtomicIsize::new(0),
+            pending,
+            main_thread,
         }
     }
 }
@@ -454,6 +454,8 @@ pub(crate) struct Underline {
     pub content_mask: ContentMask<ScaledPixels>,
     pub color: Hsla,
     pub thickness: ScaledPixels,
+    pub wavy_amplitude: ScaledPixels,
+    pub wavy_wavelength: ScaledPixels,
     pub wavy: bool,
 }
 
@@ -1,10 +1,14 @@
-use std::{iter, mem, ops::Range};
+use std::{
+    hash::{Hash, Hasher},
+    iter, mem,
+    ops::Range,
+};
 
 use crate::{
-    black, phi, point, quad, rems, AbsoluteLength, Bounds, ContentMask, Corners, CornersRefinement,
-    CursorStyle, DefiniteLength, Edges, EdgesRefinement, ElementContext, Font, FontFeatures,
-    FontStyle, FontWeight, Hsla, Length, Pixels, Point, PointRefinement, Rgba, SharedString, Size,
-    SizeRefinement, Styled, TextRun,
+    black, phi, point, px, quad, rems, AbsoluteLength, Axis, Bounds, ContentMask, Corners,
+    CornersRefinement, CursorStyle, DefiniteLength, Edges, EdgesRefinement, ElementContext, Font,
+    FontFeatures, FontStyle, FontWeight, Hsla, Length, Pixels, Point, PointRefinement, Rgba,
+    SharedString, Size, SizeRefinement, Styled, TextRun,
 };
 use collections::HashSet;
 use refineable::Refineable;
@@ -287,6 +291,7 @@ impl TextStyle {
             background_color: self.background_color,
             underline: self.underline,
             strikethrough: self.strikethrough,
+            ..Default::default()
         }
     }
 }
@@ -549,21 +554,60 @@ impl Default for Style {
 }
 
 /// The properties that can be applied to an underline.
-#[derive(Refineable, Copy, Clone, Default, Debug, PartialEq, Eq)]
+#[derive(Refineable, Copy, Clone, Default, Debug, PartialEq)]
 #[refineable(Debug)]
 pub struct UnderlineStyle {
     /// The thickness of the underline.
     pub thickness: Pixels,
 
+    /// The thickness of the underline as a fraction of the font size. Takes precedence over
+    /// `thickness` when set, keeping the underline balanced at any text size.
+    pub relative_thickness: Option<f32>,
+
     /// The color of the underline.
     pub color: Option<Hsla>,
 
     /// Whether the underline should be wavy, like in a spell checker.
     pub wavy: bool,
+
+    /// How far a wavy underline rises and falls around its center. When painting text, this
+    /// defaults to a fraction of the font size.
+    pub wavy_amplitude: Option<Pixels>,
+
+    /// The distance between the peaks of a wavy underline. When painting text, this defaults
+    /// to a fraction of the font size.
+    pub wavy_wavelength: Option<Pixels>,
+
+    /// Whether the underline should leave gaps where glyphs descend through it, like the
+    /// descenders of 'g', 'y' and 'p'.
+    pub skip_descenders: bool,
+}
+
+impl Eq for UnderlineStyle {}
+
+impl Hash for UnderlineStyle {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.thickness.hash(state);
+        self.relative_thickness.map(f32::to_bits).hash(state);
+        self.color.hash(state);
+        self.wavy.hash(state);
+        self.wavy_amplitude.hash(state);
+        self.wavy_wavelength.hash(state);
+        self.skip_descenders.hash(state);
+    }
+}
+
+impl UnderlineStyle {
+    /// The thickness the underline is painted with at the given scale factor: its `thickness`,
+    /// but never thinner than one device pixel, so that it can't round away to nothing on
+    /// low-density displays.
+    pub(crate) fn painted_thickness(&self, scale_factor: f32) -> Pixels {
+        self.thickness.max(px(1. / scale_factor))
+    }
 }
 
 /// The properties that can be applied to a strikethrough.
-#[derive(Refineable, Copy, Clone, Default, Debug, PartialEq, Eq)]
+#[derive(Refineable, Copy, Clone, Default, Debug, PartialEq, Eq, Hash)]
 #[refineable(Debug)]
 pub struct StrikethroughStyle {
     /// The thickness of the strikethrough.
@@ -578,13 +622,54 @@ pub struct StrikethroughStyle {
 pub enum Fill {
     /// A solid color fill.
     Color(Hsla),
+    /// A gradient from one color to another along an axis, starting at the left or top edge of
+    /// the filled bounds. Only solid colors can be used as element backgrounds so far; see
+    /// [`ElementContext::paint_fill`] for painting a gradient.
+    LinearGradient {
+        /// The axis the colors change along.
+        axis: Axis,
+        /// The color at the left or top edge.
+        from: Hsla,
+        /// The color at the right or bottom edge.
+        to: Hsla,
+    },
 }
 
 impl Fill {
+    /// Create a gradient from `from` at the left or top edge to `to` at the opposite edge.
+    /// Swap the colors to run the gradient the other way.
+    pub fn linear_gradient(axis: Axis, from: impl Into<Hsla>, to: impl Into<Hsla>) -> Self {
+        Self::LinearGradient {
+            axis,
+            from: from.into(),
+            to: to.into(),
+        }
+    }
+
     /// Unwrap this fill into a solid color, if it is one.
     pub fn color(&self) -> Option<Hsla> {
         match self {
             Fill::Color(color) => Some(*color),
+            Fill::LinearGradient { .. } => None,
+        }
+    }
+
+    /// The color of this fill at `t` along its gradient, from 0 at the start to 1 at the end.
+    /// Colors are mixed in RGB, which doesn't pass through unrelated hues as mixing in HSL can.
+    pub fn color_at(&self, t: f32) -> Hsla {
+        match self {
+            Fill::Color(color) => *color,
+            Fill::LinearGradient { from, to, .. } => {
+                let t = t.clamp(0., 1.);
+                let (from, to) = (Rgba::from(*from), Rgba::from(*to));
+                Rgba {
+                    r: from.r + (to.r - from.r) * t,
+                    g: from.g + (to.g - from.g) * t,
+                    b: from.b + (to.b - from.b) * t,
+                    a: from.a + (to.a - from.a) * t,
+                }
+                .into()
+            }
         }
     }
 }
@@ -755,6 +840,36 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_underline_painted_thickness() {
+        let underline = |thickness| UnderlineStyle {
+            thickness,
+            ..Default::default()
+        };
+
+        // A hairline underline is widened to a whole device pixel.
+        assert_eq!(underline(px(0.5)).painted_thickness(1.), px(1.));
+        assert_eq!(underline(px(0.)).painted_thickness(1.), px(1.));
+        // On a denser display, a device pixel is thinner than a logical one.
+        assert_eq!(underline(px(0.5)).painted_thickness(2.), px(0.5));
+        assert_eq!(underline(px(0.25)).painted_thickness(2.), px(0.5));
+        // Thicker underlines are left as they are.
+        assert_eq!(underline(px(2.)).painted_thickness(1.), px(2.));
+    }
+
+    #[test]
+    fn test_linear_gradient_color_at() {
+        let gradient = Fill::linear_gradient(Axis::Horizontal, black(), crate::white());
+        let middle = gradient.color_at(0.5);
+        assert!((middle.l - 0.5).abs() < 0.001, "{middle:?}");
+        assert_eq!(middle.a, 1.);
+        // Positions past either end take the color at that end.
+        assert_eq!(gradient.color_at(-1.).l, 0.);
+        assert!((gradient.color_at(2.).l - 1.).abs() < 0.001);
+
+        assert_eq!(Fill::Color(red()).color_at(0.7), red());
+    }
+
     #[test]
     fn test_combine_highlights() {
         assert_eq!(
@@ -2,15 +2,17 @@ mod font_features;
 mod line;
 mod line_layout;
 mod line_wrapper;
+mod text_transform;
 
 pub use font_features::*;
 pub use line::*;
 pub use line_layout::*;
 pub use line_wrapper::*;
+pub use text_transform::*;
 
 use crate::{
     px, Bounds, DevicePixels, Hsla, Pixels, PlatformTextSystem, Point, Result, SharedString, Size,
-    StrikethroughStyle, UnderlineStyle,
+    StrikethroughStyle, UnderlineStyle, WindowContext, SUBPIXEL_VARIANTS,
 };
 use anyhow::anyhow;
 use collections::{BTreeSet, FxHashMap};
@@ -45,6 +47,7 @@ pub struct TextSystem {
     font_ids_by_font: RwLock<FxHashMap<Font, Result<FontId>>>,
     font_metrics: RwLock<FxHashMap<FontId, FontMetrics>>,
     raster_bounds: RwLock<FxHashMap<RenderGlyphParams, Bounds<DevicePixels>>>,
+    ellipsis_glyphs: RwLock<FxHashMap<FontIdWithSize, Option<EllipsisGlyph>>>,
     wrapper_pool: Mutex<FxHashMap<FontIdWithSize, Vec<LineWrapper>>>,
     font_runs_pool: Mutex<Vec<Vec<FontRun>>>,
     fallback_font_stack: SmallVec<[Font; 2]>,
@@ -56,6 +59,7 @@ impl TextSystem {
             platform_text_system,
             font_metrics: RwLock::default(),
             raster_bounds: RwLock::default(),
+            ellipsis_glyphs: RwLock::default(),
             font_ids_by_font: RwLock::default(),
             wrapper_pool: Mutex::default(),
             font_runs_pool: Mutex::default(),
@@ -89,6 +93,8 @@ impl TextSystem {
 
     /// Add a font's data to the text system.
     pub fn add_fonts(&self, fonts: Vec<Cow<'static, [u8]>>) -> Result<()> {
+        // New fonts can change which glyph a font resolves the ellipsis to.
+        self.ellipsis_glyphs.write().clear();
         self.platform_text_system.add_fonts(fonts)
     }
 
@@ -143,6 +149,35 @@ impl TextSystem {
         );
     }
 
+    /// The fonts the shaper chose for each cluster of glyphs in `text`, in order, when it's
+    /// laid out in `font` at `font_size`. Characters the font can't draw fall back to other
+    /// fonts, so this answers questions like why some CJK text is drawn in an unexpected font.
+    /// Each call lays the text out again without caching it, so avoid calling it every frame.
+    pub fn resolve_font_chain(&self, text: &str, font: &Font, font_size: Pixels) -> Vec<FontId> {
+        let font_run = FontRun {
+            len: text.len(),
+            font_id: self.resolve_font(font),
+            font_size: None,
+        };
+        let mut layout = self
+            .platform_text_system
+            .layout_line(text, font_size, &[font_run]);
+        layout.collapse_invisible_glyphs(text);
+        layout.snap_glyph_indices_to_graphemes(text);
+
+        let mut chain = Vec::new();
+        let mut previous_index = None;
+        for run in &layout.runs {
+            for glyph in &run.glyphs {
+                if previous_index != Some(glyph.index) {
+                    previous_index = Some(glyph.index);
+                    chain.push(run.font_id);
+                }
+            }
+        }
+        chain
+    }
+
     /// Get the bounding box for the given font and font size.
     /// A font's bounding box is the smallest rectangle that could enclose all glyphs
     /// in the font. superimposed over one another.
@@ -181,6 +216,27 @@ impl TextSystem {
         Ok(result * font_size)
     }
 
+    /// Get the glyph for an ellipsis ("…") in the given font and size, for appending to
+    /// truncated text. The glyph is only looked up the first time it's requested for a font
+    /// and size. Returns `None` if the font has no glyph for the ellipsis.
+    pub fn ellipsis(&self, font_id: FontId, font_size: Pixels) -> Option<EllipsisGlyph> {
+        let key = FontIdWithSize { font_id, font_size };
+        if let Some(ellipsis) = self.ellipsis_glyphs.read().get(&key) {
+            return *ellipsis;
+        }
+
+        let ellipsis = self
+            .platform_text_system
+            .glyph_for_char(font_id, '…')
+            .zip(self.advance(font_id, font_size, '…').ok())
+            .map(|(id, advance)| EllipsisGlyph {
+                id,
+                advance: advance.width,
+            });
+        self.ellipsis_glyphs.write().insert(key, ellipsis);
+        ellipsis
+    }
+
     /// Get the number of font size units per 'em square',
     /// Per MDN: "an abstract square whose height is the intended distance between
     /// lines of type in the same type size"
@@ -300,6 +356,40 @@ impl WindowTextSystem {
         self.line_layout_cache.reuse_layouts(index)
     }
 
+    /// Rasterize the glyphs of a shaped line into the window's glyph atlas without painting them,
+    /// so that a large amount of text can be warmed up before it first appears. Glyphs that are
+    /// already cached are skipped.
+    ///
+    /// Glyphs are rasterized for the horizontal positions they'd have when the line is painted at
+    /// a whole pixel, on the baseline's pixel. Painting it elsewhere may rasterize some of them
+    /// again for other sub-pixel positions.
+    pub fn rasterize_line(&self, line: &ShapedLine, cx: &mut WindowContext) -> Result<()> {
+        let scale_factor = cx.scale_factor();
+        let snap_to_pixel_grid = cx.snap_glyphs_to_pixel_grid() && scale_factor.fract() == 0.;
+        for run in &line.runs {
+            for glyph in &run.glyphs {
+                let subpixel_x = if glyph.is_emoji || snap_to_pixel_grid {
+                    0
+                } else {
+                    let x = glyph.position.x.scale(scale_factor);
+                    (x.0.fract() * SUBPIXEL_VARIANTS as f32).floor() as u8
+                };
+                cx.rasterize_glyph(&RenderGlyphParams {
+                    font_id: run.font_id,
+                    glyph_id: glyph.id,
+                    font_size: run.font_size,
+                    subpixel_variant: Point {
+                        x: subpixel_x,
+                        y: 0,
+                    },
+                    scale_factor,
+                    is_emoji: glyph.is_emoji,
+                })?;
+            }
+        }
+        Ok(())
+    }
+
     /// Shape the given line, at the given font_size, for painting to the screen.
     /// Subsets of the line can be styled independently with the `runs` parameter.
     ///
@@ -324,6 +414,8 @@ impl WindowTextSystem {
                     && last_run.underline == run.underline
                     && last_run.strikethrough == run.strikethrough
                     && last_run.background_color == run.background_color
+                    && last_run.background_radius == run.background_radius
+                    && last_run.opacity == run.opacity
                 {
                     last_run.len += run.len as u32;
                     continue;
@@ -333,8 +425,11 @@ impl WindowTextSystem {
                 len: run.len as u32,
                 color: run.color,
                 background_color: run.background_color,
+                background_radius: run.background_radius,
                 underline: run.underline,
                 strikethrough: run.strikethrough,
+                baseline_shift: run.baseline_shift,
+                opacity: run.opacity,
             });
         }
 
@@ -344,9 +439,114 @@ impl WindowTextSystem {
             layout,
             text,
             decoration_runs,
+            glyph_colors: Vec::new(),
+            inline_boxes: Vec::new(),
+            trailing_background_x: None,
+            composition_underlines: Vec::new(),
         })
     }
 
+    /// Shape the given line like [`Self::shape_line`], for text that must line up in columns,
+    /// such as code. Every character is placed in a cell as wide as the advance of "m" in the
+    /// first run's font, or in two cells if it's a wide character, regardless of the kerning
+    /// and ligatures the shaper applied. See [`ShapedLine::align_to_cells`].
+    pub fn shape_line_monospace(
+        &self,
+        text: SharedString,
+        font_size: Pixels,
+        runs: &[TextRun],
+    ) -> Result<ShapedLine> {
+        let mut line = self.shape_line(text, font_size, runs)?;
+        if let Some(run) = runs.first() {
+            let font_id = self.resolve_font(&run.font);
+            let font_size = run.font_size.unwrap_or(font_size);
+            let cell_width = self.advance(font_id, font_size, 'm')?.width;
+            line.align_to_cells(cell_width);
+        }
+        Ok(line)
+    }
+
+    /// Shape text that may contain newlines into one line per `\n`-separated segment, at the
+    /// given font_size, splitting the styles in `runs` between the lines. The newlines
+    /// themselves are not part of any line. An empty segment yields an empty line with the
+    /// ascent and descent of the font it would have been written in, so it takes up the same
+    /// height as the lines around it.
+    pub fn shape_paragraph(
+        &self,
+        text: SharedString,
+        font_size: Pixels,
+        runs: &[TextRun],
+    ) -> Result<Vec<ShapedLine>> {
+        let mut runs = runs.iter().cloned().peekable();
+        let mut last_run: Option<TextRun> = None;
+        let mut lines = Vec::new();
+        let mut line_start = 0;
+        for line_text in text.split('\n') {
+            let line_end = line_start + line_text.len();
+            let mut line_runs = SmallVec::<[TextRun; 8]>::new();
+            let mut run_start = line_start;
+            while run_start < line_end {
+                let Some(run) = runs.peek_mut() else {
+                    break;
+                };
+
+                let run_len_within_line = cmp::min(line_end, run_start + run.len) - run_start;
+                line_runs.push(TextRun {
+                    len: run_len_within_line,
+                    ..run.clone()
+                });
+                if run_len_within_line == run.len {
+                    last_run = runs.next();
+                } else {
+                    run.len -= run_len_within_line;
+                }
+                run_start += run_len_within_line;
+            }
+
+            let mut line = self.shape_line(line_text.to_string().into(), font_size, &line_runs)?;
+            if line_text.is_empty() {
+                if let Some(run) = runs.peek().or(last_run.as_ref()) {
+                    let font_id = self.resolve_font(&run.font);
+                    let font_size = run.font_size.unwrap_or(font_size);
+                    line.layout = Arc::new(LineLayout {
+                        font_size,
+                        ascent: self.ascent(font_id, font_size),
+                        descent: self.descent(font_id, font_size),
+                        ..Default::default()
+                    });
+                }
+            }
+            lines.push(line);
+
+            // Skip the `\n` character.
+            line_start = line_end + 1;
+            if let Some(run) = runs.peek_mut() {
+                run.len = run.len.saturating_sub(1);
+                if run.len == 0 {
+                    last_run = runs.next();
+                }
+            }
+        }
+
+        Ok(lines)
+    }
+
+    /// Shape a multi line string of text like [`Self::shape_text`], for text that shares its
+    /// rows with an element at their end, such as a scrollbar or a fold indicator. Lines are
+    /// wrapped to `wrap_width` minus the element's `gutter` width, so that no text is drawn
+    /// under it, and the returned lines report that reduced width as their wrap width.
+    pub fn shape_text_with_gutter(
+        &self,
+        text: SharedString,
+        font_size: Pixels,
+        runs: &[TextRun],
+        wrap_width: Option<Pixels>,
+        gutter: Pixels,
+    ) -> Result<SmallVec<[WrappedLine; 1]>> {
+        let wrap_width = wrap_width.map(|wrap_width| (wrap_width - gutter).max(px(0.)));
+        self.shape_text(text, font_size, runs, wrap_width)
+    }
+
     /// Shape a multi line string of text, at the given font_size, for painting to the screen.
     /// Subsets of the text can be styled independently with the `runs` parameter.
     /// If `wrap_width` is provided, the line breaks will be adjusted to fit within the given width.
@@ -366,8 +566,9 @@ impl WindowTextSystem {
         let mut process_line = |line_text: SharedString| {
             let line_end = line_start + line_text.len();
 
-            let mut last_font: Option<Font> = None;
+            let mut last_font: Option<(Font, Option<Pixels>)> = None;
             let mut decoration_runs = SmallVec::<[DecorationRun; 32]>::new();
+            let mut text_transforms = SmallVec::<[(usize, TextTransform); 8]>::new();
             let mut run_start = line_start;
             while run_start < line_end {
                 let Some(run) = runs.peek_mut() else {
@@ -375,14 +576,16 @@ impl WindowTextSystem {
                 };
 
                 let run_len_within_line = cmp::min(line_end, run_start + run.len) - run_start;
+                text_transforms.push((run_len_within_line, run.text_transform));
 
-                if last_font == Some(run.font.clone()) {
+                if last_font == Some((run.font.clone(), run.font_size)) {
                     font_runs.last_mut().unwrap().len += run_len_within_line;
                 } else {
-                    last_font = Some(run.font.clone());
+                    last_font = Some((run.font.clone(), run.font_size));
                     font_runs.push(FontRun {
                         len: run_len_within_line,
                         font_id: self.resolve_font(&run.font),
+                        font_size: run.font_size,
                     });
                 }
 
@@ -391,6 +594,8 @@ impl WindowTextSystem {
                         && last_run.underline == run.underline
                         && last_run.strikethrough == run.strikethrough
                         && last_run.background_color == run.background_color
+                        && last_run.background_radius == run.background_radius
+                        && last_run.opacity == run.opacity
                 }) {
                     decoration_runs.last_mut().unwrap().len += run_len_within_line as u32;
                 } else {
@@ -398,8 +603,11 @@ impl WindowTextSystem {
                         len: run_len_within_line as u32,
                         color: run.color,
                         background_color: run.background_color,
+                        background_radius: run.background_radius,
                         underline: run.underline,
                         strikethrough: run.strikethrough,
+                        baseline_shift: run.baseline_shift,
+                        opacity: run.opacity,
                     });
                 }
 
@@ -412,13 +620,33 @@ impl WindowTextSystem {
                 run_start += run_len_within_line;
             }
 
-            let layout = self
-                .line_layout_cache
-                .layout_wrapped_line(&line_text, font_size, &font_runs, wrap_width);
+            let layout = match TransformedText::new(&line_text, text_transforms) {
+                Some(transformed) => {
+                    transformed.transform_font_runs(&mut font_runs);
+                    let layout = self.line_layout_cache.layout_wrapped_line(
+                        &transformed.text,
+                        font_size,
+                        &font_runs,
+                        wrap_width,
+                    );
+                    Arc::new(WrappedLineLayout {
+                        unwrapped_layout: Arc::new(
+                            transformed.restore_indices(&layout.unwrapped_layout),
+                        ),
+                        wrap_boundaries: layout.wrap_boundaries.clone(),
+                        wrap_width: layout.wrap_width,
+                    })
+                }
+                None => self
+                    .line_layout_cache
+                    .layout_wrapped_line(&line_text, font_size, &font_runs, wrap_width),
+            };
 
             lines.push(WrappedLine {
                 layout,
                 decoration_runs,
+                glyph_colors: Vec::new(),
+                composition_underlines: Vec::new(),
                 text: line_text,
             });
 
@@ -475,7 +703,7 @@ impl WindowTextSystem {
         for run in runs.iter() {
             let font_id = self.resolve_font(&run.font);
             if let Some(last_run) = font_runs.last_mut() {
-                if last_run.font_id == font_id {
+                if last_run.font_id == font_id && last_run.font_size == run.font_size {
                     last_run.len += run.len;
                     continue;
                 }
@@ -483,12 +711,24 @@ impl WindowTextSystem {
             font_runs.push(FontRun {
                 len: run.len,
                 font_id,
+                font_size: run.font_size,
             });
         }
 
-        let layout = self
-            .line_layout_cache
-            .layout_line(text, font_size, &font_runs);
+        let transformed =
+            TransformedText::new(text, runs.iter().map(|run| (run.len, run.text_transform)));
+        let layout = match transformed {
+            Some(transformed) => {
+                transformed.transform_font_runs(&mut font_runs);
+                let layout =
+                    self.line_layout_cache
+                        .layout_line(&transformed.text, font_size, &font_runs);
+                Arc::new(transformed.restore_indices(&layout))
+            }
+            None => self
+                .line_layout_cache
+                .layout_line(text, font_size, &font_runs),
+        };
 
         font_runs.clear();
         self.font_runs_pool.lock().push(font_runs);
@@ -497,6 +737,15 @@ impl WindowTextSystem {
     }
 }
 
+/// The glyph used to draw an ellipsis in a given font and size, see [`TextSystem::ellipsis`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EllipsisGlyph {
+    /// The ID of the ellipsis glyph.
+    pub id: GlyphId,
+    /// How far the ellipsis advances the position of the glyph that follows it.
+    pub advance: Pixels,
+}
+
 #[derive(Hash, Eq, PartialEq)]
 struct FontIdWithSize {
     font_id: FontId,
@@ -597,7 +846,7 @@ impl Display for FontStyle {
 }
 
 /// A styled run of text, for use in [`TextLayout`].
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct TextRun {
     /// A number of utf8 bytes
     pub len: usize,
@@ -607,10 +856,42 @@ pub struct TextRun {
     pub color: Hsla,
     /// The background color (if any)
     pub background_color: Option<Hsla>,
+    /// The corner radius of the background, e.g. for inline tags drawn as pills
+    pub background_radius: Pixels,
     /// The underline style (if any)
     pub underline: Option<UnderlineStyle>,
     /// The strikethrough style (if any)
     pub strikethrough: Option<StrikethroughStyle>,
+    /// The font size for this run, if it differs from the font size of the line
+    pub font_size: Option<Pixels>,
+    /// How far to raise this run above the baseline, or lower it when negative,
+    /// e.g. for superscripts and subscripts
+    pub baseline_shift: Pixels,
+    /// How opaque this run is painted, from 0 to 1, e.g. for ghost text
+    pub opacity: f32,
+    /// How the letters of this run are cased when laid out, e.g. uppercase for section headers.
+    /// The text's byte indices still refer to the original text.
+    pub text_transform: TextTransform,
+}
+
+impl Eq for TextRun {}
+
+impl Default for TextRun {
+    fn default() -> Self {
+        Self {
+            len: 0,
+            font: font(SharedString::default()),
+            color: Hsla::default(),
+            background_color: None,
+            background_radius: px(0.),
+            underline: None,
+            strikethrough: None,
+            font_size: None,
+            baseline_shift: px(0.),
+            opacity: 1.,
+            text_transform: TextTransform::None,
+        }
+    }
 }
 
 /// An identifier for a specific glyph, as returned by [`TextSystem::layout_line`].
@@ -1,13 +1,21 @@
 use crate::{
-    black, fill, point, px, size, Bounds, ElementContext, Hsla, LineLayout, Pixels, Point, Result,
-    SharedString, StrikethroughStyle, UnderlineStyle, WrapBoundary, WrappedLineLayout,
+    black, fill, point, px, size, Bounds, Corners, ElementContext, Fill, FontId, Hsla, LineLayout,
+    Pixels, Point, RenderGlyphParams, Result, ShapedGlyph, ShapedRun, SharedString, Size,
+    StrikethroughStyle, TextSystem, UnderlineStyle, WrapBoundary, WrappedLineLayout,
 };
 use derive_more::{Deref, DerefMut};
+use itertools::Either;
 use smallvec::SmallVec;
-use std::sync::Arc;
+use std::{
+    hash::{Hash, Hasher},
+    iter,
+    ops::Range,
+    sync::Arc,
+};
+use unicode_segmentation::UnicodeSegmentation as _;
 
 /// Set the text decoration for a run of text.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct DecorationRun {
     /// The length of the run in utf-8 bytes.
     pub len: u32,
@@ -18,14 +26,102 @@ pub struct DecorationRun {
     /// The background color for this run
     pub background_color: Option<Hsla>,
 
+    /// The corner radius of this run's background, for chip-style highlights. Only the ends of
+    /// a background are rounded, not where it wraps onto another row.
+    pub background_radius: Pixels,
+
     /// The underline style for this run
     pub underline: Option<UnderlineStyle>,
 
     /// The strikethrough style for this run
     pub strikethrough: Option<StrikethroughStyle>,
+
+    /// How far this run is raised above the baseline, or lowered when negative
+    pub baseline_shift: Pixels,
+
+    /// How opaque this run is painted, from 0 to 1, scaling the alpha of its glyphs and
+    /// decorations without changing their hue
+    pub opacity: f32,
+}
+
+impl DecorationRun {
+    /// The given color of this run, with its alpha scaled by the run's opacity.
+    fn faded(&self, color: Hsla) -> Hsla {
+        Hsla {
+            a: color.a * self.opacity.clamp(0., 1.),
+            ..color
+        }
+    }
+}
+
+impl Eq for DecorationRun {}
+
+impl Hash for DecorationRun {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.len.hash(state);
+        self.color.hash(state);
+        self.background_color.hash(state);
+        self.background_radius.hash(state);
+        self.underline.hash(state);
+        self.strikethrough.hash(state);
+        self.baseline_shift.hash(state);
+        self.opacity.to_bits().hash(state);
+    }
+}
+
+/// Where text sits vertically within the height of its line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum VerticalAlign {
+    /// Place the top of the text's ascent at the top of the line.
+    Top,
+    /// Split the space the text doesn't use evenly above and below it.
+    #[default]
+    Center,
+    /// Place the bottom of the text's descent at the bottom of the line.
+    Bottom,
+}
+
+impl VerticalAlign {
+    /// The distance from the top of a line to the text's baseline.
+    fn baseline_offset(self, line_height: Pixels, ascent: Pixels, descent: Pixels) -> Pixels {
+        let padding_top = match self {
+            VerticalAlign::Top => px(0.),
+            VerticalAlign::Center => (line_height - ascent - descent) / 2.,
+            VerticalAlign::Bottom => line_height - ascent - descent,
+        };
+        padding_top + ascent
+    }
+}
+
+/// Space reserved within a [`ShapedLine`] for something the caller paints itself, such as an
+/// inline image, in place of the glyphs shaped from a range of the text.
+#[derive(Clone, Debug, PartialEq, Hash)]
+pub struct InlineBox {
+    /// The byte range of the placeholder text that the box replaces.
+    pub range: Range<usize>,
+    /// Where the box starts, relative to the start of the line.
+    pub x: Pixels,
+    /// The size of the box, which sits on the line's baseline.
+    pub size: Size<Pixels>,
+}
+
+/// An underline beneath part of the text being composed with an input method, such as the
+/// clause being converted while typing Japanese. These are painted above the line's own
+/// decorations.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CompositionUnderline {
+    /// The byte range of the underlined text.
+    pub range: Range<usize>,
+    /// How the underline is drawn. Without a color, it takes the color of the text it starts
+    /// under.
+    pub style: UnderlineStyle,
 }
 
 /// A line of text that has been shaped and decorated.
+///
+/// Two shaped lines are equal when they were shaped from the same text, fonts and size and carry
+/// the same decorations, even if their layouts live in different allocations. This makes shaped
+/// lines usable as cache keys.
 #[derive(Clone, Default, Debug, Deref, DerefMut)]
 pub struct ShapedLine {
     #[deref]
@@ -34,6 +130,10 @@ pub struct ShapedLine {
     /// The text that was shaped for this line.
     pub text: SharedString,
     pub(crate) decoration_runs: SmallVec<[DecorationRun; 32]>,
+    pub(crate) glyph_colors: Vec<(usize, Hsla)>,
+    pub(crate) inline_boxes: Vec<InlineBox>,
+    pub(crate) trailing_background_x: Option<Pixels>,
+    pub(crate) composition_underlines: Vec<CompositionUnderline>,
 }
 
 impl ShapedLine {
@@ -43,6 +143,78 @@ impl ShapedLine {
         self.layout.len
     }
 
+    /// Highlight the background of the given byte ranges, for example to show every match of
+    /// a search query. The ranges must be sorted and must not overlap.
+    pub fn highlight_ranges(&mut self, ranges: &[Range<usize>], background_color: Hsla) {
+        self.decoration_runs =
+            highlight_decoration_runs(&self.decoration_runs, ranges, background_color);
+    }
+
+    /// Override the color of individual glyphs, keyed by the byte index of the character they
+    /// were shaped from. Glyphs without an override keep the color of their decoration run.
+    pub fn set_glyph_colors(&mut self, colors: impl IntoIterator<Item = (usize, Hsla)>) {
+        self.glyph_colors = sorted_glyph_colors(colors);
+    }
+
+    /// Underline the parts of the text being composed with an input method. Each range is
+    /// widened to the grapheme boundaries around it, so an underline never ends within a
+    /// character. Setting underlines replaces the previous ones.
+    pub fn set_composition_underlines(
+        &mut self,
+        underlines: impl IntoIterator<Item = CompositionUnderline>,
+    ) {
+        self.composition_underlines = snapped_composition_underlines(&self.text, underlines);
+    }
+
+    /// The byte range of the word around the given byte index, for example to select the word
+    /// under a double click after finding its index with [`LineLayout::index_for_x`]. Words are
+    /// split at Unicode word boundaries, so runs of whitespace and punctuation marks form ranges
+    /// of their own, and each CJK ideograph is a word by itself. An index at the end of the
+    /// line gives the last word.
+    pub fn word_range_at(&self, index: usize) -> Range<usize> {
+        let mut words = self
+            .text
+            .split_word_bound_indices()
+            .map(|(start, word)| start..start + word.len());
+        let mut last_word = None;
+        for word in &mut words {
+            if index < word.end {
+                return word;
+            }
+            last_word = Some(word);
+        }
+        last_word.unwrap_or(index..index)
+    }
+
+    /// The display column at the given byte index, counted in monospace cells from the start of
+    /// the line, as in a "Ln 4, Col 12" status. Unlike [`LineLayout::x_for_index`], this
+    /// doesn't depend on the font: tabs advance to the next multiple of `tab_size` columns,
+    /// wide characters such as CJK ideographs take two columns, and a character combined with
+    /// marks takes the columns of its base character. An index inside a grapheme gives the
+    /// column the grapheme starts at.
+    pub fn column_for_index(&self, index: usize, tab_size: usize) -> usize {
+        let tab_size = tab_size.max(1);
+        let mut column = 0;
+        for (start, grapheme) in self.text.grapheme_indices(true) {
+            if start + grapheme.len() > index {
+                break;
+            }
+            if grapheme == "\t" {
+                column = (column / tab_size + 1) * tab_size;
+            } else {
+                column += grapheme.chars().next().map_or(0, char_cell_width);
+            }
+        }
+        column
+    }
+
+    /// Extend the background of the text at the end of the line to `x`, relative to the start of
+    /// the line, even where there are no glyphs, for example to show that a selection includes
+    /// the newline. An `x` before the end of the text doesn't shorten the background.
+    pub fn extend_trailing_background(&mut self, x: Pixels) {
+        self.trailing_background_x = Some(x);
+    }
+
     /// Paint the line of text to the window.
     pub fn paint(
         &self,
@@ -50,16 +222,408 @@ impl ShapedLine {
         line_height: Pixels,
         cx: &mut ElementContext,
     ) -> Result<()> {
-        paint_line(
-            origin,
-            &self.layout,
-            line_height,
-            &self.decoration_runs,
-            &[],
-            cx,
-        )?;
+        self.paint_aligned(origin, line_height, VerticalAlign::Center, cx)
+    }
 
-        Ok(())
+    /// Paint the line of text to the window, placed vertically within the line as given.
+    pub fn paint_aligned(
+        &self,
+        origin: Point<Pixels>,
+        line_height: Pixels,
+        align: VerticalAlign,
+        cx: &mut ElementContext,
+    ) -> Result<()> {
+        LinePainter::new(&self.layout, &self.decoration_runs)
+            .align(align)
+            .glyph_colors(&self.glyph_colors)
+            .inline_boxes(&self.inline_boxes)
+            .trailing_background_x(self.trailing_background_x)
+            .composition_underlines(&self.composition_underlines)
+            .paint(origin, line_height, cx)
+    }
+
+    /// Paint the line of text to the window over `background_fill`, such as a gradient behind
+    /// a header. The fill covers the line's bounds, and the backgrounds of its runs are painted
+    /// on top of it.
+    pub fn paint_with_background_fill(
+        &self,
+        origin: Point<Pixels>,
+        line_height: Pixels,
+        background_fill: Option<Fill>,
+        cx: &mut ElementContext,
+    ) -> Result<()> {
+        LinePainter::new(&self.layout, &self.decoration_runs)
+            .glyph_colors(&self.glyph_colors)
+            .inline_boxes(&self.inline_boxes)
+            .trailing_background_x(self.trailing_background_x)
+            .composition_underlines(&self.composition_underlines)
+            .background_fill(background_fill.as_ref())
+            .paint(origin, line_height, cx)
+    }
+
+    /// Reserve space for inline objects, such as image placeholders, each given as the byte
+    /// index of the placeholder character it stands in for and the size of the object. The glyphs
+    /// of the placeholder aren't painted, and the glyphs after it move over to fit the object's
+    /// width, leaving a blank box for the caller to paint into; see [`Self::inline_box_bounds`].
+    /// Carets, selections and decorations treat each box like a single character. Reserving a
+    /// box at an index that already has one resizes it.
+    pub fn reserve_inline_boxes(&mut self, boxes: impl IntoIterator<Item = (usize, Size<Pixels>)>) {
+        let mut layout = LineLayout {
+            font_size: self.layout.font_size,
+            width: self.layout.width,
+            ascent: self.layout.ascent,
+            descent: self.layout.descent,
+            runs: self
+                .layout
+                .runs
+                .iter()
+                .map(|run| ShapedRun {
+                    font_id: run.font_id,
+                    font_size: run.font_size,
+                    is_rtl: run.is_rtl,
+                    glyphs: run.glyphs.clone(),
+                })
+                .collect(),
+            len: self.layout.len,
+            ligature_carets: self.layout.ligature_carets.clone(),
+            is_rtl: self.layout.is_rtl,
+        };
+
+        for (index, box_size) in boxes {
+            let glyph_indices = || {
+                layout
+                    .runs
+                    .iter()
+                    .flat_map(|run| run.glyphs.iter().map(|glyph| glyph.index))
+            };
+            let start = glyph_indices()
+                .take_while(|glyph_index| *glyph_index <= index)
+                .last()
+                .unwrap_or(0);
+            let end = glyph_indices()
+                .find(|glyph_index| *glyph_index > start)
+                .unwrap_or(layout.len);
+            let x = layout.x_for_index(start);
+            let delta = box_size.width - (layout.x_for_index(end) - x);
+
+            for glyph in layout.runs.iter_mut().flat_map(|run| run.glyphs.iter_mut()) {
+                if glyph.index >= end {
+                    glyph.position.x += delta;
+                }
+            }
+            layout.width += delta;
+
+            self.inline_boxes
+                .retain(|inline_box| inline_box.range.start != start);
+            for inline_box in &mut self.inline_boxes {
+                if inline_box.range.start >= end {
+                    inline_box.x += delta;
+                }
+            }
+            self.inline_boxes.push(InlineBox {
+                range: start..end,
+                x,
+                size: box_size,
+            });
+        }
+
+        self.inline_boxes
+            .sort_by_key(|inline_box| inline_box.range.start);
+        self.layout = Arc::new(layout);
+    }
+
+    /// The bounds of each box reserved with [`Self::reserve_inline_boxes`], along with the range
+    /// of text it replaces, when the line is painted at `origin` with the given height and
+    /// alignment.
+    pub fn inline_box_bounds(
+        &self,
+        origin: Point<Pixels>,
+        line_height: Pixels,
+        align: VerticalAlign,
+    ) -> impl Iterator<Item = (Range<usize>, Bounds<Pixels>)> + '_ {
+        let baseline_y =
+            origin.y + align.baseline_offset(line_height, self.layout.ascent, self.layout.descent);
+        self.inline_boxes.iter().map(move |inline_box| {
+            let box_origin = point(origin.x + inline_box.x, baseline_y - inline_box.size.height);
+            (
+                inline_box.range.clone(),
+                Bounds::new(box_origin, inline_box.size),
+            )
+        })
+    }
+
+    /// Place every glyph on a grid of cells `cell_width` wide, discarding the advances and kerning
+    /// the shaper produced between clusters, so that the text lines up in columns as in a code
+    /// editor. Wide characters, such as CJK ideographs, take two cells, and a ligature takes one
+    /// cell for each character it was formed from. Glyphs within a cluster, such as combining
+    /// marks, keep their offsets from the cluster's first glyph.
+    pub fn align_to_cells(&mut self, cell_width: Pixels) {
+        let mut cell_starts = Vec::with_capacity(self.text.len() + 1);
+        let mut cells = 0;
+        for (ix, ch) in self.text.char_indices() {
+            cell_starts.resize(ix + 1, cells);
+            cells += char_cell_width(ch);
+        }
+        cell_starts.resize(self.text.len() + 1, cells);
+
+        let mut cluster: Option<(usize, Pixels)> = None;
+        let runs = self
+            .layout
+            .runs
+            .iter()
+            .map(|run| ShapedRun {
+                font_id: run.font_id,
+                font_size: run.font_size,
+                is_rtl: run.is_rtl,
+                glyphs: run
+                    .glyphs
+                    .iter()
+                    .map(|glyph| {
+                        let cluster_x = match cluster {
+                            Some((index, x)) if index == glyph.index => x,
+                            _ => {
+                                cluster = Some((glyph.index, glyph.position.x));
+                                glyph.position.x
+                            }
+                        };
+                        let cell_x = cell_width * cell_starts[glyph.index] as f32;
+                        ShapedGlyph {
+                            position: point(
+                                cell_x + glyph.position.x - cluster_x,
+                                glyph.position.y,
+                            ),
+                            ..glyph.clone()
+                        }
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        self.layout = Arc::new(LineLayout {
+            font_size: self.layout.font_size,
+            width: cell_width * cells as f32,
+            ascent: self.layout.ascent,
+            descent: self.layout.descent,
+            runs,
+            len: self.layout.len,
+            ligature_carets: self.layout.ligature_carets.clone(),
+            is_rtl: self.layout.is_rtl,
+        });
+    }
+
+    /// Split this line into the text before and after the given byte index, for example to
+    /// make room for an inline element. An index inside a cluster of glyphs, such as a
+    /// ligature, snaps to the nearest cluster boundary, so the glyphs on either side keep the
+    /// shapes they were laid out with and the line doesn't need to be shaped again. The
+    /// second line's positions are relative to the split point.
+    pub fn split_at(&self, index: usize) -> (ShapedLine, ShapedLine) {
+        let layout = &self.layout;
+        let index = layout
+            .runs
+            .iter()
+            .flat_map(|run| run.glyphs.iter().map(|glyph| glyph.index))
+            .chain([0, layout.len])
+            .min_by_key(|boundary| (boundary.abs_diff(index), *boundary))
+            .unwrap_or(0);
+        // Each half closes up the space of the other half's text. That's everything after the
+        // split point for the first half, but with right-to-left runs, the text on either side
+        // can be spread across the line.
+        let (left_extents, right_extents) = layout.extents_around(index);
+        let collapsed_width = |extents: &[Range<Pixels>], x: Pixels| {
+            extents
+                .iter()
+                .filter(|extent| extent.end <= x)
+                .fold(px(0.), |width, extent| width + (extent.end - extent.start))
+        };
+        let left_x = |x: Pixels| x - collapsed_width(&right_extents, x);
+        let right_x = |x: Pixels| x - collapsed_width(&left_extents, x);
+
+        let mut left_runs = Vec::new();
+        let mut right_runs = Vec::new();
+        for run in &layout.runs {
+            let split_ix = run.glyphs.partition_point(|glyph| glyph.index < index);
+            let (left, right) = run.glyphs.split_at(split_ix);
+            if !left.is_empty() {
+                left_runs.push(ShapedRun {
+                    font_id: run.font_id,
+                    font_size: run.font_size,
+                    is_rtl: run.is_rtl,
+                    glyphs: left
+                        .iter()
+                        .map(|glyph| ShapedGlyph {
+                            position: point(left_x(glyph.position.x), glyph.position.y),
+                            ..glyph.clone()
+                        })
+                        .collect(),
+                });
+            }
+            if !right.is_empty() {
+                right_runs.push(ShapedRun {
+                    font_id: run.font_id,
+                    font_size: run.font_size,
+                    is_rtl: run.is_rtl,
+                    glyphs: right
+                        .iter()
+                        .map(|glyph| ShapedGlyph {
+                            position: point(right_x(glyph.position.x), glyph.position.y),
+                            index: glyph.index - index,
+                            ..glyph.clone()
+                        })
+                        .collect(),
+                });
+            }
+        }
+
+        let left_is_rtl = left_runs.iter().any(|run| run.is_rtl);
+        let right_is_rtl = right_runs.iter().any(|run| run.is_rtl);
+        let (left_decoration_runs, right_decoration_runs) =
+            split_decoration_runs(&self.decoration_runs, index);
+        let (left_glyph_colors, right_glyph_colors) = self
+            .glyph_colors
+            .iter()
+            .copied()
+            .partition::<Vec<_>, _>(|(glyph_index, _)| *glyph_index < index);
+        let (left_inline_boxes, right_inline_boxes) = self
+            .inline_boxes
+            .iter()
+            .cloned()
+            .partition::<Vec<_>, _>(|inline_box| inline_box.range.start < index);
+        let left_composition_underlines = self
+            .composition_underlines
+            .iter()
+            .filter(|underline| underline.range.start < index)
+            .map(|underline| CompositionUnderline {
+                range: underline.range.start..underline.range.end.min(index),
+                style: underline.style,
+            })
+            .collect();
+        let right_composition_underlines = self
+            .composition_underlines
+            .iter()
+            .filter(|underline| underline.range.end > index)
+            .map(|underline| CompositionUnderline {
+                range: underline.range.start.max(index) - index..underline.range.end - index,
+                style: underline.style,
+            })
+            .collect();
+
+        let left = ShapedLine {
+            layout: Arc::new(LineLayout {
+                font_size: layout.font_size,
+                width: left_x(layout.width),
+                ascent: layout.ascent,
+                descent: layout.descent,
+                runs: left_runs,
+                len: index,
+                ligature_carets: layout
+                    .ligature_carets
+                    .iter()
+                    .copied()
+                    .filter(|caret| *caret < index)
+                    .collect(),
+                is_rtl: left_is_rtl,
+            }),
+            text: self.text[..index].to_string().into(),
+            decoration_runs: left_decoration_runs,
+            glyph_colors: left_glyph_colors,
+            inline_boxes: left_inline_boxes
+                .into_iter()
+                .map(|inline_box| InlineBox {
+                    x: left_x(inline_box.x),
+                    ..inline_box
+                })
+                .collect(),
+            trailing_background_x: None,
+            composition_underlines: left_composition_underlines,
+        };
+        let right = ShapedLine {
+            layout: Arc::new(LineLayout {
+                font_size: layout.font_size,
+                width: right_x(layout.width),
+                ascent: layout.ascent,
+                descent: layout.descent,
+                runs: right_runs,
+                len: layout.len - index,
+                ligature_carets: layout
+                    .ligature_carets
+                    .iter()
+                    .filter(|caret| **caret > index)
+                    .map(|caret| caret - index)
+                    .collect(),
+                is_rtl: right_is_rtl,
+            }),
+            text: self.text[index..].to_string().into(),
+            decoration_runs: right_decoration_runs,
+            glyph_colors: right_glyph_colors
+                .into_iter()
+                .map(|(glyph_index, color)| (glyph_index - index, color))
+                .collect(),
+            inline_boxes: right_inline_boxes
+                .into_iter()
+                .map(|inline_box| InlineBox {
+                    range: inline_box.range.start - index..inline_box.range.end - index,
+                    x: right_x(inline_box.x),
+                    size: inline_box.size,
+                })
+                .collect(),
+            trailing_background_x: self.trailing_background_x.map(right_x),
+            composition_underlines: right_composition_underlines,
+        };
+        (left, right)
+    }
+
+    /// The font of each shaped run, along with the byte index at which the run starts.
+    fn font_runs(&self) -> impl Iterator<Item = (FontId, Option<usize>)> + '_ {
+        self.layout
+            .runs
+            .iter()
+            .map(|run| (run.font_id, run.glyphs.first().map(|glyph| glyph.index)))
+    }
+
+    /// The position of every glyph, which differs between lines shaped from the same text and
+    /// runs when one of them was aligned to cells.
+    fn glyph_positions(&self) -> impl Iterator<Item = Point<Pixels>> + '_ {
+        self.layout
+            .runs
+            .iter()
+            .flat_map(|run| run.glyphs.iter().map(|glyph| glyph.position))
+    }
+}
+
+impl PartialEq for ShapedLine {
+    fn eq(&self, other: &Self) -> bool {
+        self.text == other.text
+            && self.layout.font_size == other.layout.font_size
+            && (Arc::ptr_eq(&self.layout, &other.layout)
+                || (self.layout.width == other.layout.width
+                    && self.font_runs().eq(other.font_runs())
+                    && self.glyph_positions().eq(other.glyph_positions())))
+            && self.decoration_runs == other.decoration_runs
+            && self.glyph_colors == other.glyph_colors
+            && self.inline_boxes == other.inline_boxes
+            && self.trailing_background_x == other.trailing_background_x
+            && self.composition_underlines == other.composition_underlines
+    }
+}
+
+impl Eq for ShapedLine {}
+
+impl Hash for ShapedLine {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.text.hash(state);
+        self.layout.font_size.hash(state);
+        self.layout.width.hash(state);
+        for font_run in self.font_runs() {
+            font_run.hash(state);
+        }
+        for position in self.glyph_positions() {
+            position.hash(state);
+        }
+        self.decoration_runs.hash(state);
+        self.glyph_colors.hash(state);
+        self.inline_boxes.hash(state);
+        self.trailing_background_x.hash(state);
+        self.composition_underlines.hash(state);
     }
 }
 
@@ -72,6 +636,8 @@ pub struct WrappedLine {
     /// The text that was shaped for this line.
     pub text: SharedString,
     pub(crate) decoration_runs: SmallVec<[DecorationRun; 32]>,
+    pub(crate) glyph_colors: Vec<(usize, Hsla)>,
+    pub(crate) composition_underlines: Vec<CompositionUnderline>,
 }
 
 impl WrappedLine {
@@ -81,209 +647,813 @@ impl WrappedLine {
         self.layout.len()
     }
 
+    /// Highlight the background of the given byte ranges, for example to show every match of
+    /// a search query. The ranges must be sorted and must not overlap.
+    pub fn highlight_ranges(&mut self, ranges: &[Range<usize>], background_color: Hsla) {
+        self.decoration_runs =
+            highlight_decoration_runs(&self.decoration_runs, ranges, background_color);
+    }
+
+    /// Override the color of individual glyphs, keyed by the byte index of the character they
+    /// were shaped from. Glyphs without an override keep the color of their decoration run.
+    pub fn set_glyph_colors(&mut self, colors: impl IntoIterator<Item = (usize, Hsla)>) {
+        self.glyph_colors = sorted_glyph_colors(colors);
+    }
+
+    /// Underline the parts of the text being composed with an input method. Each range is
+    /// widened to the grapheme boundaries around it, so an underline never ends within a
+    /// character. Setting underlines replaces the previous ones.
+    pub fn set_composition_underlines(
+        &mut self,
+        underlines: impl IntoIterator<Item = CompositionUnderline>,
+    ) {
+        self.composition_underlines = snapped_composition_underlines(&self.text, underlines);
+    }
+
+    /// Limit this line to its first `max_rows` rows (at least one), ending the last row with an
+    /// ellipsis when any text is cut off. The rows that are cut off are neither painted nor
+    /// counted in the size of the returned line.
+    pub fn clamp_rows(&self, max_rows: usize, text_system: &TextSystem) -> WrappedLine {
+        let max_rows = max_rows.max(1);
+        let Some(cut) = self.wrap_boundaries.get(max_rows - 1).copied() else {
+            return self.clone();
+        };
+
+        let layout = &self.layout.unwrapped_layout;
+        let row_start_x = max_rows.checked_sub(2).map_or(px(0.), |ix| {
+            let boundary = self.wrap_boundaries[ix];
+            let run = &layout.runs[boundary.run_ix];
+            run.glyphs[boundary.glyph_ix].position.x
+        });
+        let row_width = self.layout.wrap_width.unwrap_or(Pixels::MAX);
+
+        let cut_glyph = &layout.runs[cut.run_ix].glyphs[cut.glyph_ix];
+        let mut end_index = cut_glyph.index;
+        let mut end_x = cut_glyph.position.x;
+        let mut runs = layout.runs[..=cut.run_ix]
+            .iter()
+            .map(|run| ShapedRun {
+                font_id: run.font_id,
+                font_size: run.font_size,
+                is_rtl: run.is_rtl,
+                glyphs: run.glyphs.clone(),
+            })
+            .collect::<Vec<_>>();
+        runs[cut.run_ix].glyphs.truncate(cut.glyph_ix);
+        runs.retain(|run| !run.glyphs.is_empty());
+
+        // The ellipsis is drawn in the font of the last visible glyph, replacing trailing
+        // whitespace and as many glyphs as needed for it to fit in the row.
+        let ellipsis = runs.last().and_then(|run| {
+            let glyph = text_system.ellipsis(run.font_id, run.font_size)?;
+            Some((run.font_id, run.font_size, glyph))
+        });
+        let ellipsis_advance = ellipsis.map_or(px(0.), |(_, _, glyph)| glyph.advance);
+        while let Some(run) = runs.last_mut() {
+            let Some(glyph) = run.glyphs.last() else {
+                runs.pop();
+                continue;
+            };
+            let is_whitespace = self.text[glyph.index..].starts_with(char::is_whitespace);
+            let fits = end_x + ellipsis_advance - row_start_x <= row_width;
+            if glyph.position.x <= row_start_x || (fits && !is_whitespace) {
+                break;
+            }
+            end_index = glyph.index;
+            end_x = glyph.position.x;
+            run.glyphs.pop();
+        }
+
+        if let Some((font_id, font_size, glyph)) = ellipsis {
+            let ellipsis_glyph = ShapedGlyph {
+                id: glyph.id,
+                position: point(end_x, px(0.)),
+                index: end_index,
+                is_emoji: false,
+            };
+            match runs.last_mut() {
+                Some(run) if run.font_id == font_id && run.font_size == font_size => {
+                    run.glyphs.push(ellipsis_glyph)
+                }
+                _ => runs.push(ShapedRun {
+                    font_id,
+                    font_size,
+                    is_rtl: false,
+                    glyphs: SmallVec::from_iter([ellipsis_glyph]),
+                }),
+            }
+        }
+
+        let is_rtl = runs.iter().any(|run| run.is_rtl);
+        WrappedLine {
+            layout: Arc::new(WrappedLineLayout {
+                unwrapped_layout: Arc::new(LineLayout {
+                    font_size: layout.font_size,
+                    width: end_x + ellipsis_advance,
+                    ascent: layout.ascent,
+                    descent: layout.descent,
+                    runs,
+                    len: end_index,
+                    ligature_carets: layout
+                        .ligature_carets
+                        .iter()
+                        .copied()
+                        .filter(|caret| *caret < end_index)
+                        .collect(),
+                    is_rtl,
+                }),
+                wrap_boundaries: SmallVec::from_slice(&self.wrap_boundaries[..max_rows - 1]),
+                wrap_width: self.layout.wrap_width,
+            }),
+            text: self.text.clone(),
+            decoration_runs: self.decoration_runs.clone(),
+            glyph_colors: self.glyph_colors.clone(),
+            composition_underlines: self.composition_underlines.clone(),
+        }
+    }
+
     /// Paint this line of text to the window.
     pub fn paint(
         &self,
         origin: Point<Pixels>,
         line_height: Pixels,
         cx: &mut ElementContext,
+    ) -> Result<()> {
+        self.paint_aligned(origin, line_height, VerticalAlign::Center, cx)
+    }
+
+    /// Paint this line of text to the window, with each row placed vertically within its line
+    /// as given.
+    pub fn paint_aligned(
+        &self,
+        origin: Point<Pixels>,
+        line_height: Pixels,
+        align: VerticalAlign,
+        cx: &mut ElementContext,
+    ) -> Result<()> {
+        LinePainter::new(&self.layout.unwrapped_layout, &self.decoration_runs)
+            .align(align)
+            .glyph_colors(&self.glyph_colors)
+            .wrap_boundaries(&self.wrap_boundaries)
+            .composition_underlines(&self.composition_underlines)
+            .paint(origin, line_height, cx)
+    }
+}
+
+/// Split the given decoration runs so that every byte in `ranges` is painted with the given
+/// background color. The ranges must be sorted and must not overlap.
+pub fn highlight_decoration_runs(
+    runs: &[DecorationRun],
+    ranges: &[Range<usize>],
+    background_color: Hsla,
+) -> SmallVec<[DecorationRun; 32]> {
+    let mut highlighted_runs = SmallVec::new();
+    let mut ranges = ranges.iter().peekable();
+    let mut run_start = 0;
+    for run in runs {
+        let run_end = run_start + run.len as usize;
+        let mut segment_start = run_start;
+        while segment_start < run_end {
+            while ranges
+                .peek()
+                .map_or(false, |range| range.end <= segment_start)
+            {
+                ranges.next();
+            }
+
+            let (segment_end, highlighted) = match ranges.peek() {
+                Some(range) if range.start <= segment_start => (range.end.min(run_end), true),
+                Some(range) => (range.start.min(run_end), false),
+                None => (run_end, false),
+            };
+
+            let mut segment = run.clone();
+            segment.len = (segment_end - segment_start) as u32;
+            if highlighted {
+                segment.background_color = Some(background_color);
+            }
+            highlighted_runs.push(segment);
+            segment_start = segment_end;
+        }
+        run_start = run_end;
+    }
+    highlighted_runs
+}
+
+/// Split the given decoration runs into the runs covering the bytes before and after `index`.
+fn split_decoration_runs(
+    runs: &[DecorationRun],
+    index: usize,
+) -> (SmallVec<[DecorationRun; 32]>, SmallVec<[DecorationRun; 32]>) {
+    let mut left = SmallVec::new();
+    let mut right = SmallVec::new();
+    let mut run_start = 0;
+    for run in runs {
+        let run_end = run_start + run.len as usize;
+        if run_end <= index {
+            left.push(run.clone());
+        } else if run_start >= index {
+            right.push(run.clone());
+        } else {
+            let mut segment = run.clone();
+            segment.len = (index - run_start) as u32;
+            left.push(segment);
+            let mut segment = run.clone();
+            segment.len = (run_end - index) as u32;
+            right.push(segment);
+        }
+        run_start = run_end;
+    }
+    (left, right)
+}
+
+/// The number of monospace cells the given character occupies: two for East Asian wide and
+/// fullwidth characters, none for combining marks and invisible characters, and one otherwise.
+fn char_cell_width(ch: char) -> usize {
+    match ch as u32 {
+        0x00..=0x08 | 0x0a..=0x1f | 0x7f => 0,
+        0x0300..=0x036f | 0x200b..=0x200f | 0xfe00..=0xfe0f => 0,
+        0x1100..=0x115f
+        | 0x2e80..=0x303e
+        | 0x3041..=0x33ff
+        | 0x3400..=0x4dbf
+        | 0x4e00..=0x9fff
+        | 0xa000..=0xa4cf
+        | 0xac00..=0xd7a3
+        | 0xf900..=0xfaff
+        | 0xfe30..=0xfe4f
+        | 0xff00..=0xff60
+        | 0xffe0..=0xffe6
+        | 0x1f300..=0x1f64f
+        | 0x1f900..=0x1f9ff
+        | 0x20000..=0x3fffd => 2,
+        _ => 1,
+    }
+}
+
+fn sorted_glyph_colors(colors: impl IntoIterator<Item = (usize, Hsla)>) -> Vec<(usize, Hsla)> {
+    let mut colors = colors.into_iter().collect::<Vec<_>>();
+    colors.sort_by_key(|(index, _)| *index);
+    colors
+}
+
+fn snapped_composition_underlines(
+    text: &str,
+    underlines: impl IntoIterator<Item = CompositionUnderline>,
+) -> Vec<CompositionUnderline> {
+    let boundaries = text
+        .grapheme_indices(true)
+        .map(|(ix, _)| ix)
+        .chain([text.len()])
+        .collect::<Vec<_>>();
+    let mut underlines = underlines
+        .into_iter()
+        .filter_map(|underline| {
+            let start = boundaries[boundaries
+                .partition_point(|ix| *ix <= underline.range.start)
+                .saturating_sub(1)];
+            let end = boundaries[boundaries
+                .partition_point(|ix| *ix < underline.range.end)
+                .min(boundaries.len() - 1)];
+            (start < end).then_some(CompositionUnderline {
+                range: start..end,
+                style: underline.style,
+            })
+        })
+        .collect::<Vec<_>>();
+    underlines.sort_by_key(|underline| underline.range.start);
+    underlines
+}
+
+/// Where a glyph is painted, relative to the origin of its line.
+struct GlyphPlacement {
+    origin: Point<Pixels>,
+    /// When the glyph starts a wrapped row, the x at which the previous row ended.
+    wrapped_from_x: Option<Pixels>,
+}
+
+/// Places every glyph of the layout, in order, starting a new row at each wrap boundary.
+///
+/// Glyph positions are measured from the start of the line rather than from the start of their
+/// run, so each glyph is placed from its own position and the position of the glyph its row
+/// starts with. Nothing carries over from the previous glyph, which keeps runs shaped with
+/// different fonts from drifting relative to each other.
+fn glyph_placements<'a>(
+    layout: &'a LineLayout,
+    wrap_boundaries: &'a [WrapBoundary],
+    line_height: Pixels,
+) -> impl Iterator<Item = GlyphPlacement> + 'a {
+    let mut wraps = wrap_boundaries.iter().peekable();
+    let mut row_start_x = px(0.);
+    let mut row_y = px(0.);
+    layout
+        .runs
+        .iter()
+        .enumerate()
+        .flat_map(|(run_ix, run)| {
+            run.glyphs
+                .iter()
+                .enumerate()
+                .map(move |(glyph_ix, glyph)| (WrapBoundary { run_ix, glyph_ix }, glyph))
+        })
+        .map(move |(boundary, glyph)| {
+            let mut wrapped_from_x = None;
+            if wraps.peek() == Some(&&boundary) {
+                wraps.next();
+                wrapped_from_x = Some(glyph.position.x - row_start_x);
+                row_start_x = glyph.position.x;
+                row_y += line_height;
+            }
+            GlyphPlacement {
+                origin: point(glyph.position.x - row_start_x, row_y),
+                wrapped_from_x,
+            }
+        })
+}
+
+/// The bounds of each row the layout is painted over, from the start of the row's text to its end.
+fn row_bounds<'a>(
+    origin: Point<Pixels>,
+    layout: &'a LineLayout,
+    wrap_boundaries: &'a [WrapBoundary],
+    line_height: Pixels,
+) -> impl Iterator<Item = Bounds<Pixels>> + 'a {
+    let row_start_xs = iter::once(px(0.)).chain(wrap_boundaries.iter().map(|boundary| {
+        layout.runs[boundary.run_ix].glyphs[boundary.glyph_ix]
+            .position
+            .x
+    }));
+    let row_end_xs = row_start_xs.clone().skip(1).chain(iter::once(layout.width));
+    row_start_xs
+        .zip(row_end_xs)
+        .enumerate()
+        .map(move |(row_ix, (start_x, end_x))| {
+            Bounds::new(
+                origin + point(px(0.), line_height * row_ix as f32),
+                size(end_x - start_x, line_height),
+            )
+        })
+}
+
+/// Paints a [`LineLayout`] with its decorations, for elements that lay out text themselves
+/// instead of using a [`ShapedLine`] or [`WrappedLine`].
+///
+/// The decoration runs must cover the layout's text, in order. Everything else is optional:
+/// ```ignore
+/// LinePainter::new(&layout, &decoration_runs)
+///     .wrap_boundaries(&wrap_boundaries)
+///     .paint(origin, line_height, cx)?;
+/// ```
+#[derive(Clone, Copy)]
+pub struct LinePainter<'a> {
+    layout: &'a LineLayout,
+    decoration_runs: &'a [DecorationRun],
+    align: VerticalAlign,
+    glyph_colors: &'a [(usize, Hsla)],
+    inline_boxes: &'a [InlineBox],
+    trailing_background_x: Option<Pixels>,
+    wrap_boundaries: &'a [WrapBoundary],
+    composition_underlines: &'a [CompositionUnderline],
+    background_fill: Option<&'a Fill>,
+}
+
+impl<'a> LinePainter<'a> {
+    /// Create a painter for the given layout, decorated with the given runs.
+    pub fn new(layout: &'a LineLayout, decoration_runs: &'a [DecorationRun]) -> Self {
+        Self {
+            layout,
+            decoration_runs,
+            align: VerticalAlign::default(),
+            glyph_colors: &[],
+            inline_boxes: &[],
+            trailing_background_x: None,
+            wrap_boundaries: &[],
+            composition_underlines: &[],
+            background_fill: None,
+        }
+    }
+
+    /// Set where the text sits vertically within each line. Defaults to the center.
+    pub fn align(mut self, align: VerticalAlign) -> Self {
+        self.align = align;
+        self
+    }
+
+    /// Override the color of individual glyphs, each given by the byte index it starts at.
+    /// The indices must be sorted.
+    pub fn glyph_colors(mut self, glyph_colors: &'a [(usize, Hsla)]) -> Self {
+        self.glyph_colors = glyph_colors;
+        self
+    }
+
+    /// Leave the glyphs within these boxes unpainted, for the caller to paint into.
+    pub fn inline_boxes(mut self, inline_boxes: &'a [InlineBox]) -> Self {
+        self.inline_boxes = inline_boxes;
+        self
+    }
+
+    /// Extend the background of the last decoration run to the given x position, relative to
+    /// the start of the line.
+    pub fn trailing_background_x(mut self, trailing_background_x: Option<Pixels>) -> Self {
+        self.trailing_background_x = trailing_background_x;
+        self
+    }
+
+    /// Paint the layout over several rows, wrapping it at the given boundaries.
+    pub fn wrap_boundaries(mut self, wrap_boundaries: &'a [WrapBoundary]) -> Self {
+        self.wrap_boundaries = wrap_boundaries;
+        self
+    }
+
+    /// Underline the parts of the text being composed with an input method, above the other
+    /// decorations. The underlines must be sorted by where they start.
+    pub fn composition_underlines(
+        mut self,
+        composition_underlines: &'a [CompositionUnderline],
+    ) -> Self {
+        self.composition_underlines = composition_underlines;
+        self
+    }
+
+    /// Fill each row of the line before painting anything else, across the width of its text.
+    pub fn background_fill(mut self, background_fill: Option<&'a Fill>) -> Self {
+        self.background_fill = background_fill;
+        self
+    }
+
+    /// Paint the line to the window, with the top left of its first row at `origin`.
+    pub fn paint(
+        self,
+        origin: Point<Pixels>,
+        line_height: Pixels,
+        cx: &mut ElementContext,
     ) -> Result<()> {
         paint_line(
             origin,
-            &self.layout.unwrapped_layout,
+            self.layout,
             line_height,
-            &self.decoration_runs,
-            &self.wrap_boundaries,
+            self.align,
+            self.decoration_runs,
+            self.glyph_colors,
+            self.inline_boxes,
+            self.trailing_background_x,
+            self.wrap_boundaries,
+            self.composition_underlines,
+            self.background_fill,
             cx,
-        )?;
+        )
+    }
+}
 
-        Ok(())
+/// The amplitude of wavy underlines, relative to the font size, unless the style overrides it.
+const WAVY_UNDERLINE_AMPLITUDE: f32 = 0.055;
+/// The wavelength of wavy underlines, relative to the font size, unless the style overrides it.
+const WAVY_UNDERLINE_WAVELENGTH: f32 = 0.6;
+
+/// The space left at the end of each underline of composed text, so that adjacent ones read as
+/// separate segments.
+const COMPOSITION_UNDERLINE_GAP: Pixels = px(2.);
+
+/// The space left on either side of a descender that an underline skips, relative to the
+/// underline's thickness.
+const DESCENDER_CLEARANCE: f32 = 1.5;
+
+/// Returns the horizontal extent of a glyph's ink if it reaches below `underline_y`.
+///
+/// This approximates the glyph's outline by its rasterized bounds, so the whole width of a
+/// descending glyph is skipped rather than only the parts that cross the underline.
+fn descender_extent(
+    text_system: &TextSystem,
+    baseline_origin: Point<Pixels>,
+    font_id: FontId,
+    glyph: &ShapedGlyph,
+    font_size: Pixels,
+    scale_factor: f32,
+    underline_y: Pixels,
+) -> Option<Range<Pixels>> {
+    let raster_bounds = text_system
+        .raster_bounds(&RenderGlyphParams {
+            font_id,
+            glyph_id: glyph.id,
+            font_size,
+            subpixel_variant: Default::default(),
+            scale_factor,
+            is_emoji: false,
+        })
+        .ok()?;
+    if raster_bounds.is_zero() {
+        return None;
+    }
+
+    let bottom = baseline_origin.y
+        + px((raster_bounds.origin.y.0 + raster_bounds.size.height.0) as f32 / scale_factor);
+    if bottom <= underline_y {
+        return None;
     }
+    let left = baseline_origin.x + px(raster_bounds.origin.x.0 as f32 / scale_factor);
+    let right = left + px(raster_bounds.size.width.0 as f32 / scale_factor);
+    Some(left..right)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn paint_line(
     origin: Point<Pixels>,
     layout: &LineLayout,
     line_height: Pixels,
+    align: VerticalAlign,
     decoration_runs: &[DecorationRun],
+    glyph_colors: &[(usize, Hsla)],
+    inline_boxes: &[InlineBox],
+    trailing_background_x: Option<Pixels>,
     wrap_boundaries: &[WrapBoundary],
+    composition_underlines: &[CompositionUnderline],
+    background_fill: Option<&Fill>,
     cx: &mut ElementContext,
 ) -> Result<()> {
     let line_bounds = Bounds::new(origin, size(layout.width, line_height));
     cx.paint_layer(line_bounds, |cx| {
-        let padding_top = (line_height - layout.ascent - layout.descent) / 2.;
-        let baseline_offset = point(px(0.), padding_top + layout.ascent);
-        let mut decoration_runs = decoration_runs.iter();
-        let mut wraps = wrap_boundaries.iter().peekable();
-        let mut run_end = 0;
+        if let Some(background_fill) = background_fill {
+            for row_bounds in row_bounds(origin, layout, wrap_boundaries, line_height) {
+                cx.paint_fill(row_bounds, background_fill);
+            }
+        }
+        let baseline_offset = point(
+            px(0.),
+            align.baseline_offset(line_height, layout.ascent, layout.descent),
+        );
+        let trailing_background = decoration_runs
+            .last()
+            .and_then(|run| Some((run.faded(run.background_color?), run.background_radius)));
+        // Each glyph takes the decorations of the run its index falls in.
+        let decoration_ends = decoration_runs
+            .iter()
+            .scan(0, |end, run| {
+                *end += run.len as usize;
+                Some(*end)
+            })
+            .collect::<SmallVec<[usize; 32]>>();
+        let mut current_decoration_ix = None;
+        let placed_glyphs = layout
+            .runs
+            .iter()
+            .flat_map(|run| run.glyphs.iter().map(move |glyph| (run, glyph)))
+            .zip(glyph_placements(layout, wrap_boundaries, line_height));
+        // Decorations are extended from one glyph to the next, so the glyphs are painted from
+        // left to right. Right-to-left runs are only reordered on lines that aren't wrapped.
+        let placed_glyphs = if wrap_boundaries.is_empty() && layout.is_rtl {
+            let mut placed_glyphs = placed_glyphs.collect::<Vec<_>>();
+            placed_glyphs.sort_by_key(|(_, placement)| placement.origin.x);
+            Either::Left(placed_glyphs.into_iter())
+        } else {
+            Either::Right(placed_glyphs)
+        };
         let mut color = black();
+        let mut opacity = 1.;
+        let mut baseline_shift = px(0.);
         let mut current_underline: Option<(Point<Pixels>, UnderlineStyle)> = None;
         let mut current_strikethrough: Option<(Point<Pixels>, StrikethroughStyle)> = None;
-        let mut current_background: Option<(Point<Pixels>, Hsla)> = None;
+        let mut current_background: Option<RunBackground> = None;
         let text_system = cx.text_system().clone();
-        let mut glyph_origin = origin;
-        let mut prev_glyph_position = Point::default();
-        for (run_ix, run) in layout.runs.iter().enumerate() {
-            let max_glyph_size = text_system.bounding_box(run.font_id, layout.font_size).size;
-
-            for (glyph_ix, glyph) in run.glyphs.iter().enumerate() {
-                glyph_origin.x += glyph.position.x - prev_glyph_position.x;
-
-                if wraps.peek() == Some(&&WrapBoundary { run_ix, glyph_ix }) {
-                    wraps.next();
-                    if let Some((background_origin, background_color)) = current_background.as_mut()
-                    {
-                        cx.paint_quad(fill(
-                            Bounds {
-                                origin: *background_origin,
-                                size: size(glyph_origin.x - background_origin.x, line_height),
-                            },
-                            *background_color,
-                        ));
-                        background_origin.x = origin.x;
-                        background_origin.y += line_height;
-                    }
-                    if let Some((underline_origin, underline_style)) = current_underline.as_mut() {
-                        cx.paint_underline(
-                            *underline_origin,
-                            glyph_origin.x - underline_origin.x,
-                            underline_style,
-                        );
-                        underline_origin.x = origin.x;
-                        underline_origin.y += line_height;
-                    }
-                    if let Some((strikethrough_origin, strikethrough_style)) =
-                        current_strikethrough.as_mut()
-                    {
-                        cx.paint_strikethrough(
-                            *strikethrough_origin,
-                            glyph_origin.x - strikethrough_origin.x,
-                            strikethrough_style,
-                        );
-                        strikethrough_origin.x = origin.x;
-                        strikethrough_origin.y += line_height;
+        let scale_factor = cx.scale_factor();
+        let snap_to_pixel_grid = cx.snap_glyphs_to_pixel_grid() && scale_factor.fract() == 0.;
+        // Styled lines tend to alternate between a few fonts, so look each one up only once.
+        let mut font_bounding_boxes = SmallVec::<[(FontId, Pixels, Bounds<Pixels>); 4]>::new();
+        for ((run, glyph), placement) in placed_glyphs {
+            let font_bounding_box =
+                match font_bounding_boxes.iter().find(|(font_id, font_size, _)| {
+                    *font_id == run.font_id && *font_size == run.font_size
+                }) {
+                    Some((_, _, bounding_box)) => *bounding_box,
+                    None => {
+                        let bounding_box = text_system.bounding_box(run.font_id, run.font_size);
+                        font_bounding_boxes.push((run.font_id, run.font_size, bounding_box));
+                        bounding_box
                     }
+                };
+
+            let glyph_origin = origin + placement.origin;
 
-                    glyph_origin.x = origin.x;
-                    glyph_origin.y += line_height;
+            if let Some(row_end_x) = placement.wrapped_from_x {
+                let row_end_x = origin.x + row_end_x;
+                if let Some(background) = current_background.as_mut() {
+                    background.paint(row_end_x, line_height, false, cx);
+                    background.origin.x = origin.x;
+                    background.origin.y += line_height;
+                    background.rounded_start = false;
                 }
-                prev_glyph_position = glyph.position;
-
-                let mut finished_background: Option<(Point<Pixels>, Hsla)> = None;
-                let mut finished_underline: Option<(Point<Pixels>, UnderlineStyle)> = None;
-                let mut finished_strikethrough: Option<(Point<Pixels>, StrikethroughStyle)> = None;
-                if glyph.index >= run_end {
-                    if let Some(style_run) = decoration_runs.next() {
-                        if let Some((_, background_color)) = &mut current_background {
-                            if style_run.background_color.as_ref() != Some(background_color) {
-                                finished_background = current_background.take();
-                            }
-                        }
-                        if let Some(run_background) = style_run.background_color {
-                            current_background.get_or_insert((
-                                point(glyph_origin.x, glyph_origin.y),
-                                run_background,
-                            ));
-                        }
+                if let Some((underline_origin, underline_style)) = current_underline.as_mut() {
+                    cx.paint_underline(
+                        *underline_origin,
+                        (row_end_x - underline_origin.x).max(px(0.)),
+                        underline_style,
+                    );
+                    underline_origin.x = origin.x;
+                    underline_origin.y += line_height;
+                }
+                if let Some((strikethrough_origin, strikethrough_style)) =
+                    current_strikethrough.as_mut()
+                {
+                    cx.paint_strikethrough(
+                        *strikethrough_origin,
+                        row_end_x - strikethrough_origin.x,
+                        strikethrough_style,
+                    );
+                    strikethrough_origin.x = origin.x;
+                    strikethrough_origin.y += line_height;
+                }
+            }
 
-                        if let Some((_, underline_style)) = &mut current_underline {
-                            if style_run.underline.as_ref() != Some(underline_style) {
-                                finished_underline = current_underline.take();
-                            }
+            let mut finished_background: Option<RunBackground> = None;
+            let mut finished_underline: Option<(Point<Pixels>, UnderlineStyle)> = None;
+            let mut finished_strikethrough: Option<(Point<Pixels>, StrikethroughStyle)> = None;
+            let decoration_ix = decoration_ends.partition_point(|end| *end <= glyph.index);
+            if current_decoration_ix != Some(decoration_ix) {
+                current_decoration_ix = Some(decoration_ix);
+                if let Some(style_run) = decoration_runs.get(decoration_ix) {
+                    // Decorations follow the baseline of their run, so a shift in the
+                    // baseline ends them even if their style stays the same.
+                    let shift_changed = style_run.baseline_shift != baseline_shift;
+                    baseline_shift = style_run.baseline_shift;
+
+                    let run_background = style_run.background_color.map(|background_color| {
+                        (
+                            style_run.faded(background_color),
+                            style_run.background_radius,
+                        )
+                    });
+                    if let Some(background) = &current_background {
+                        if shift_changed
+                            || run_background != Some((background.color, background.radius))
+                        {
+                            finished_background = current_background.take();
                         }
-                        if let Some(run_underline) = style_run.underline.as_ref() {
-                            current_underline.get_or_insert((
-                                point(
-                                    glyph_origin.x,
-                                    glyph_origin.y + baseline_offset.y + (layout.descent * 0.618),
-                                ),
-                                UnderlineStyle {
-                                    color: Some(run_underline.color.unwrap_or(style_run.color)),
-                                    thickness: run_underline.thickness,
-                                    wavy: run_underline.wavy,
-                                },
-                            ));
+                    }
+                    if let Some((color, radius)) = run_background {
+                        current_background.get_or_insert(RunBackground {
+                            origin: point(glyph_origin.x, glyph_origin.y - baseline_shift),
+                            color,
+                            radius,
+                            rounded_start: true,
+                        });
+                    }
+
+                    // Compare against the resolved style, so that adjacent runs whose
+                    // underlines look the same are painted as a single underline.
+                    let run_underline = style_run.underline.map(|underline| UnderlineStyle {
+                        color: Some(style_run.faded(underline.color.unwrap_or(style_run.color))),
+                        thickness: underline
+                            .relative_thickness
+                            .map_or(underline.thickness, |relative| run.font_size * relative),
+                        relative_thickness: None,
+                        wavy_amplitude: Some(
+                            underline
+                                .wavy_amplitude
+                                .unwrap_or(run.font_size * WAVY_UNDERLINE_AMPLITUDE),
+                        ),
+                        wavy_wavelength: Some(
+                            underline
+                                .wavy_wavelength
+                                .unwrap_or(run.font_size * WAVY_UNDERLINE_WAVELENGTH),
+                        ),
+                        ..underline
+                    });
+                    if let Some((_, underline_style)) = &mut current_underline {
+                        if shift_changed || run_underline.as_ref() != Some(underline_style) {
+                            finished_underline = current_underline.take();
                         }
-                        if let Some((_, strikethrough_style)) = &mut current_strikethrough {
-                            if style_run.strikethrough.as_ref() != Some(strikethrough_style) {
-                                finished_strikethrough = current_strikethrough.take();
-                            }
+                    }
+                    if let Some(run_underline) = run_underline {
+                        current_underline.get_or_insert((
+                            point(
+                                glyph_origin.x,
+                                glyph_origin.y + baseline_offset.y + (layout.descent * 0.618)
+                                    - baseline_shift,
+                            ),
+                            run_underline,
+                        ));
+                    }
+                    if let Some((_, strikethrough_style)) = &mut current_strikethrough {
+                        if shift_changed
+                            || style_run.strikethrough.as_ref() != Some(strikethrough_style)
+                        {
+                            finished_strikethrough = current_strikethrough.take();
                         }
-                        if let Some(run_strikethrough) = style_run.strikethrough.as_ref() {
-                            current_strikethrough.get_or_insert((
-                                point(
-                                    glyph_origin.x,
-                                    glyph_origin.y
-                                        + (((layout.ascent * 0.5) + baseline_offset.y) * 0.5),
+                    }
+                    if let Some(run_strikethrough) = style_run.strikethrough.as_ref() {
+                        current_strikethrough.get_or_insert((
+                            point(
+                                glyph_origin.x,
+                                glyph_origin.y
+                                    + (((layout.ascent * 0.5) + baseline_offset.y) * 0.5)
+                                    - baseline_shift,
+                            ),
+                            StrikethroughStyle {
+                                color: Some(
+                                    style_run
+                                        .faded(run_strikethrough.color.unwrap_or(style_run.color)),
                                 ),
-                                StrikethroughStyle {
-                                    color: Some(run_strikethrough.color.unwrap_or(style_run.color)),
-                                    thickness: run_strikethrough.thickness,
-                                },
-                            ));
-                        }
-
-                        run_end += style_run.len as usize;
-                        color = style_run.color;
-                    } else {
-                        run_end = layout.len;
-                        finished_background = current_background.take();
-                        finished_underline = current_underline.take();
-                        finished_strikethrough = current_strikethrough.take();
+                                thickness: run_strikethrough.thickness,
+                            },
+                        ));
                     }
-                }
 
-                if let Some((background_origin, background_color)) = finished_background {
-                    cx.paint_quad(fill(
-                        Bounds {
-                            origin: background_origin,
-                            size: size(glyph_origin.x - background_origin.x, line_height),
-                        },
-                        background_color,
-                    ));
+                    color = style_run.faded(style_run.color);
+                    opacity = style_run.opacity.clamp(0., 1.);
+                } else {
+                    finished_background = current_background.take();
+                    finished_underline = current_underline.take();
+                    finished_strikethrough = current_strikethrough.take();
                 }
+            }
 
-                if let Some((underline_origin, underline_style)) = finished_underline {
-                    cx.paint_underline(
-                        underline_origin,
-                        glyph_origin.x - underline_origin.x,
-                        &underline_style,
-                    );
-                }
+            if let Some(background) = finished_background {
+                background.paint(glyph_origin.x, line_height, true, cx);
+            }
 
-                if let Some((strikethrough_origin, strikethrough_style)) = finished_strikethrough {
-                    cx.paint_strikethrough(
-                        strikethrough_origin,
-                        glyph_origin.x - strikethrough_origin.x,
-                        &strikethrough_style,
-                    );
-                }
+            if let Some((underline_origin, underline_style)) = finished_underline {
+                cx.paint_underline(
+                    underline_origin,
+                    (glyph_origin.x - underline_origin.x).max(px(0.)),
+                    &underline_style,
+                );
+            }
 
-                let max_glyph_bounds = Bounds {
-                    origin: glyph_origin,
-                    size: max_glyph_size,
-                };
+            if let Some((strikethrough_origin, strikethrough_style)) = finished_strikethrough {
+                cx.paint_strikethrough(
+                    strikethrough_origin,
+                    glyph_origin.x - strikethrough_origin.x,
+                    &strikethrough_style,
+                );
+            }
 
-                let content_mask = cx.content_mask();
-                if max_glyph_bounds.intersects(&content_mask.bounds) {
-                    if glyph.is_emoji {
-                        cx.paint_emoji(
-                            glyph_origin + baseline_offset,
-                            run.font_id,
-                            glyph.id,
-                            layout.font_size,
-                        )?;
-                    } else {
-                        cx.paint_glyph(
-                            glyph_origin + baseline_offset,
-                            run.font_id,
-                            glyph.id,
-                            layout.font_size,
-                            color,
-                        )?;
+            // Inline boxes are left blank for the caller to paint into.
+            let in_inline_box = inline_boxes
+                .iter()
+                .any(|inline_box| inline_box.range.contains(&glyph.index));
+            let mut paint_origin = glyph_origin + baseline_offset;
+            paint_origin.y -= baseline_shift;
+            if snap_to_pixel_grid {
+                paint_origin.x = (paint_origin.x * scale_factor).round() / scale_factor;
+            }
+
+            if let Some((underline_origin, underline_style)) = current_underline.as_mut() {
+                if underline_style.skip_descenders && !glyph.is_emoji && !in_inline_box {
+                    let descender = descender_extent(
+                        &text_system,
+                        paint_origin,
+                        run.font_id,
+                        glyph,
+                        run.font_size,
+                        scale_factor,
+                        underline_origin.y,
+                    );
+                    if let Some(descender) = descender {
+                        // Keep some clearance around the glyph, in proportion to the line.
+                        let clearance = underline_style.thickness * DESCENDER_CLEARANCE;
+                        let gap_start = descender.start - clearance;
+                        if gap_start > underline_origin.x {
+                            cx.paint_underline(
+                                *underline_origin,
+                                gap_start - underline_origin.x,
+                                underline_style,
+                            );
+                        }
+                        underline_origin.x = underline_origin.x.max(descender.end + clearance);
                     }
                 }
             }
+
+            // Glyphs are clipped to the content mask as they're drawn, so one that straddles
+            // its edge only needs to be kept here. Its ink can reach left of its origin, and
+            // antialiasing can bleed a pixel past its outline on either side.
+            let bleed = px(1. / scale_factor);
+            let max_glyph_bounds = Bounds {
+                origin: point(
+                    paint_origin.x + font_bounding_box.origin.x - bleed,
+                    glyph_origin.y - baseline_shift,
+                ),
+                size: size(
+                    font_bounding_box.size.width + bleed * 2.,
+                    font_bounding_box.size.height,
+                ),
+            };
+            let content_mask = cx.content_mask();
+            if !in_inline_box && max_glyph_bounds.intersects(&content_mask.bounds) {
+                if glyph.is_emoji {
+                    cx.paint_emoji(paint_origin, run.font_id, glyph.id, run.font_size)?;
+                } else {
+                    let color = glyph_colors
+                        .binary_search_by_key(&glyph.index, |(index, _)| *index)
+                        .map_or(color, |ix| {
+                            let color = glyph_colors[ix].1;
+                            Hsla {
+                                a: color.a * opacity,
+                                ..color
+                            }
+                        });
+                    cx.paint_glyph(paint_origin, run.font_id, glyph.id, run.font_size, color)?;
+                }
+            }
         }
 
         let mut last_line_end_x = origin.x + layout.width;
@@ -293,20 +1463,31 @@ fn paint_line(
             last_line_end_x -= glyph.position.x;
         }
 
-        if let Some((background_origin, background_color)) = current_background.take() {
-            cx.paint_quad(fill(
-                Bounds {
-                    origin: background_origin,
-                    size: size(last_line_end_x - background_origin.x, line_height),
-                },
-                background_color,
-            ));
+        let mut last_background_end_x = last_line_end_x;
+        if let Some(trailing_background_x) = trailing_background_x {
+            // Backgrounds only start at a glyph, so start the trailing one at the end of the text.
+            if current_background.is_none() {
+                if let Some((color, radius)) = trailing_background {
+                    let last_row_y = origin.y + line_height * wrap_boundaries.len() as f32;
+                    current_background = Some(RunBackground {
+                        origin: point(last_line_end_x, last_row_y),
+                        color,
+                        radius,
+                        rounded_start: true,
+                    });
+                }
+            }
+            last_background_end_x = last_background_end_x.max(origin.x + trailing_background_x);
+        }
+
+        if let Some(background) = current_background.take() {
+            background.paint(last_background_end_x, line_height, true, cx);
         }
 
         if let Some((underline_start, underline_style)) = current_underline.take() {
             cx.paint_underline(
                 underline_start,
-                last_line_end_x - underline_start.x,
+                (last_line_end_x - underline_start.x).max(px(0.)),
                 &underline_style,
             );
         }
@@ -319,6 +1500,691 @@ fn paint_line(
             );
         }
 
+        let underline_y = baseline_offset.y + layout.descent * 0.618;
+        for underline in composition_underlines {
+            paint_composition_underline(
+                origin,
+                layout,
+                line_height,
+                underline_y,
+                last_line_end_x,
+                decoration_color_at(decoration_runs, underline.range.start),
+                underline,
+                wrap_boundaries,
+                cx,
+            );
+        }
+
         Ok(())
     })
 }
+
+/// The background behind consecutive glyphs that share a background, within a single row.
+struct RunBackground {
+    origin: Point<Pixels>,
+    color: Hsla,
+    radius: Pixels,
+    /// False when the background continues from the previous row, so its start stays square.
+    rounded_start: bool,
+}
+
+impl RunBackground {
+    /// Paints the background up to `end_x`, rounding its end only when the background ends
+    /// there rather than wrapping onto the next row.
+    fn paint(
+        &self,
+        end_x: Pixels,
+        line_height: Pixels,
+        rounded_end: bool,
+        cx: &mut ElementContext,
+    ) {
+        let start_radius = if self.rounded_start {
+            self.radius
+        } else {
+            px(0.)
+        };
+        let end_radius = if rounded_end { self.radius } else { px(0.) };
+        cx.paint_quad(
+            fill(
+                Bounds {
+                    origin: self.origin,
+                    size: size(end_x - self.origin.x, line_height),
+                },
+                self.color,
+            )
+            .corner_radii(Corners {
+                top_left: start_radius,
+                top_right: end_radius,
+                bottom_right: end_radius,
+                bottom_left: start_radius,
+            }),
+        );
+    }
+}
+
+/// The color of the text at the given byte index, for decorations that follow it.
+fn decoration_color_at(decoration_runs: &[DecorationRun], index: usize) -> Hsla {
+    let mut run_end = 0;
+    for run in decoration_runs {
+        run_end += run.len as usize;
+        if index < run_end {
+            return run.faded(run.color);
+        }
+    }
+    decoration_runs
+        .last()
+        .map_or(black(), |run| run.faded(run.color))
+}
+
+/// Paints an underline of composed text, with a segment for each row of the line it spans.
+#[allow(clippy::too_many_arguments)]
+fn paint_composition_underline(
+    origin: Point<Pixels>,
+    layout: &LineLayout,
+    line_height: Pixels,
+    underline_y: Pixels,
+    last_line_end_x: Pixels,
+    text_color: Hsla,
+    underline: &CompositionUnderline,
+    wrap_boundaries: &[WrapBoundary],
+    cx: &mut ElementContext,
+) {
+    let style = UnderlineStyle {
+        color: Some(underline.style.color.unwrap_or(text_color)),
+        thickness: underline
+            .style
+            .relative_thickness
+            .map_or(underline.style.thickness, |relative| {
+                layout.font_size * relative
+            }),
+        relative_thickness: None,
+        skip_descenders: false,
+        ..underline.style
+    };
+    let glyphs = layout.runs.iter().flat_map(|run| run.glyphs.iter());
+    let mut segment_start: Option<Point<Pixels>> = None;
+    for (placement, glyph) in glyph_placements(layout, wrap_boundaries, line_height).zip(glyphs) {
+        let glyph_origin = origin + placement.origin;
+        if let Some(row_end_x) = placement.wrapped_from_x {
+            if let Some(start) = segment_start.as_mut() {
+                cx.paint_underline(*start, origin.x + row_end_x - start.x, &style);
+                *start = point(origin.x, start.y + line_height);
+            }
+        }
+        if glyph.index >= underline.range.end {
+            if let Some(start) = segment_start {
+                let width = glyph_origin.x - start.x - COMPOSITION_UNDERLINE_GAP;
+                if width > px(0.) {
+                    cx.paint_underline(start, width, &style);
+                }
+            }
+            return;
+        }
+        if glyph.index >= underline.range.start && segment_start.is_none() {
+            segment_start = Some(point(glyph_origin.x, glyph_origin.y + underline_y));
+        }
+    }
+    if let Some(start) = segment_start {
+        let width = last_line_end_x - start.x - COMPOSITION_UNDERLINE_GAP;
+        if width > px(0.) {
+            cx.paint_underline(start, width, &style);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{blue, font, red, white, GlyphId, TestAppContext, TextRun};
+
+    fn run(len: u32, color: Hsla) -> DecorationRun {
+        DecorationRun {
+            len,
+            color,
+            background_color: None,
+            background_radius: px(0.),
+            underline: None,
+            strikethrough: None,
+            baseline_shift: px(0.),
+            opacity: 1.,
+        }
+    }
+
+    #[test]
+    fn test_highlight_decoration_runs() {
+        let highlight = blue();
+        let runs = [run(4, red()), run(6, black())];
+
+        let highlighted = highlight_decoration_runs(&runs, &[1..2, 3..6, 9..12], highlight);
+        assert_eq!(
+            highlighted
+                .iter()
+                .map(|run| (run.len, run.color, run.background_color))
+                .collect::<Vec<_>>(),
+            &[
+                (1, red(), None),
+                (1, red(), Some(highlight)),
+                (1, red(), None),
+                (1, red(), Some(highlight)),
+                (2, black(), Some(highlight)),
+                (3, black(), None),
+                (1, black(), Some(highlight)),
+            ]
+        );
+
+        let highlighted = highlight_decoration_runs(&runs, &[], highlight);
+        assert_eq!(
+            highlighted.iter().map(|run| run.len).collect::<Vec<_>>(),
+            &[4, 6]
+        );
+    }
+
+    #[test]
+    fn test_glyph_placements_across_runs() {
+        // Two runs in different fonts and sizes, with positions measured from the start of the
+        // line, wrapped before the second glyph of the second run.
+        let run = |font_id, font_size, xs: &[f32]| ShapedRun {
+            font_id: FontId(font_id),
+            font_size: px(font_size),
+            is_rtl: false,
+            glyphs: xs
+                .iter()
+                .enumerate()
+                .map(|(ix, &x)| ShapedGlyph {
+                    id: GlyphId(0),
+                    position: point(px(x), px(0.)),
+                    index: ix,
+                    is_emoji: false,
+                })
+                .collect(),
+        };
+        let layout = LineLayout {
+            font_size: px(12.),
+            width: px(48.),
+            ascent: px(10.),
+            descent: px(4.),
+            runs: vec![run(0, 12., &[0., 7.]), run(1, 20., &[14., 26., 38.])],
+            len: 5,
+            ligature_carets: Vec::new(),
+            is_rtl: false,
+        };
+        let line_height = px(24.);
+
+        let placements = |wrap_boundaries: &[WrapBoundary]| {
+            glyph_placements(&layout, wrap_boundaries, line_height)
+                .map(|placement| (placement.origin, placement.wrapped_from_x))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            placements(&[]),
+            &[
+                (point(px(0.), px(0.)), None),
+                (point(px(7.), px(0.)), None),
+                (point(px(14.), px(0.)), None),
+                (point(px(26.), px(0.)), None),
+                (point(px(38.), px(0.)), None),
+            ]
+        );
+        assert_eq!(
+            placements(&[WrapBoundary {
+                run_ix: 1,
+                glyph_ix: 1,
+            }]),
+            &[
+                (point(px(0.), px(0.)), None),
+                (point(px(7.), px(0.)), None),
+                (point(px(14.), px(0.)), None),
+                (point(px(0.), px(24.)), Some(px(26.))),
+                (point(px(12.), px(24.)), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_shaped_line_equality_ignores_layout_allocation() {
+        use std::collections::hash_map::DefaultHasher;
+
+        let shaped_line = |color| {
+            let layout = LineLayout {
+                font_size: px(16.),
+                runs: vec![ShapedRun {
+                    font_id: FontId(0),
+                    font_size: px(16.),
+                    is_rtl: false,
+                    glyphs: (0..2)
+                        .map(|ix| ShapedGlyph {
+                            id: GlyphId(ix as u32),
+                            position: point(px(ix as f32 * 8.), px(0.)),
+                            index: ix,
+                            is_emoji: false,
+                        })
+                        .collect(),
+                }],
+                len: 2,
+                ..Default::default()
+            };
+            ShapedLine {
+                layout: Arc::new(layout),
+                text: "ab".into(),
+                decoration_runs: SmallVec::from_slice(&[run(2, color)]),
+                glyph_colors: Vec::new(),
+                inline_boxes: Vec::new(),
+                trailing_background_x: None,
+                composition_underlines: Vec::new(),
+            }
+        };
+        let hash = |line: &ShapedLine| {
+            let mut hasher = DefaultHasher::new();
+            line.hash(&mut hasher);
+            hasher.finish()
+        };
+
+        let (a, b) = (shaped_line(red()), shaped_line(red()));
+        assert!(!Arc::ptr_eq(&a.layout, &b.layout));
+        assert_eq!(a, b);
+        assert_eq!(hash(&a), hash(&b));
+
+        assert_ne!(a, shaped_line(blue()));
+
+        // Aligning a line to cells moves its glyphs and changes its width, so it no longer
+        // stands in for the line it was shaped as.
+        let mut aligned = shaped_line(red());
+        aligned.align_to_cells(px(10.));
+        assert_ne!(a.width, aligned.width);
+        assert_ne!(a, aligned);
+        assert_ne!(hash(&a), hash(&aligned));
+    }
+
+    #[test]
+    fn test_vertical_align_baseline_offset() {
+        let (line_height, ascent, descent) = (px(20.), px(10.), px(4.));
+        assert_eq!(
+            VerticalAlign::Top.baseline_offset(line_height, ascent, descent),
+            px(10.)
+        );
+        assert_eq!(
+            VerticalAlign::Center.baseline_offset(line_height, ascent, descent),
+            px(13.)
+        );
+        assert_eq!(
+            VerticalAlign::Bottom.baseline_offset(line_height, ascent, descent),
+            px(16.)
+        );
+    }
+
+    #[test]
+    fn test_split_shaped_line_at_cluster_boundary() {
+        // "office", where "ffi" was shaped into a single ligature glyph.
+        let glyphs = [(0, 0.), (1, 8.), (4, 20.), (5, 28.)]
+            .into_iter()
+            .map(|(index, x)| ShapedGlyph {
+                id: GlyphId(index as u32),
+                position: point(px(x), px(0.)),
+                index,
+                is_emoji: false,
+            })
+            .collect();
+        let line = ShapedLine {
+            layout: Arc::new(LineLayout {
+                font_size: px(16.),
+                width: px(36.),
+                runs: vec![ShapedRun {
+                    font_id: FontId(0),
+                    font_size: px(16.),
+                    is_rtl: false,
+                    glyphs,
+                }],
+                len: 6,
+                ..Default::default()
+            }),
+            text: "office".into(),
+            decoration_runs: SmallVec::from_slice(&[run(3, red()), run(3, black())]),
+            glyph_colors: vec![(0, blue()), (5, blue())],
+            inline_boxes: Vec::new(),
+            trailing_background_x: None,
+            composition_underlines: Vec::new(),
+        };
+        let glyphs = |line: &ShapedLine| {
+            line.runs
+                .iter()
+                .flat_map(|run| run.glyphs.iter())
+                .map(|glyph| (glyph.index, glyph.position.x))
+                .collect::<Vec<_>>()
+        };
+
+        // Splitting inside the ligature snaps to its nearest end.
+        let (left, right) = line.split_at(3);
+        assert_eq!((left.text.as_ref(), right.text.as_ref()), ("offi", "ce"));
+        assert_eq!((left.len(), left.width), (4, px(20.)));
+        assert_eq!((right.len(), right.width), (2, px(16.)));
+        assert_eq!(glyphs(&left), &[(0, px(0.)), (1, px(8.))]);
+        assert_eq!(glyphs(&right), &[(0, px(0.)), (1, px(8.))]);
+        assert_eq!(
+            left.decoration_runs
+                .iter()
+                .map(|run| (run.len, run.color))
+                .collect::<Vec<_>>(),
+            &[(3, red()), (1, black())]
+        );
+        assert_eq!(
+            right
+                .decoration_runs
+                .iter()
+                .map(|run| (run.len, run.color))
+                .collect::<Vec<_>>(),
+            &[(2, black())]
+        );
+        assert_eq!(left.glyph_colors, &[(0, blue())]);
+        assert_eq!(right.glyph_colors, &[(1, blue())]);
+
+        let (left, right) = line.split_at(6);
+        assert_eq!((left.len(), right.len()), (6, 0));
+        assert_eq!(right.width, px(0.));
+    }
+
+    #[test]
+    fn test_split_shaped_line_with_rtl_run() {
+        // "abאב", where the right-to-left run is laid out from right to left.
+        let shaped_run = |is_rtl: bool, glyphs: &[(usize, f32)]| ShapedRun {
+            font_id: FontId(0),
+            font_size: px(16.),
+            is_rtl,
+            glyphs: glyphs
+                .iter()
+                .map(|&(index, x)| ShapedGlyph {
+                    id: GlyphId(index as u32),
+                    position: point(px(x), px(0.)),
+                    index,
+                    is_emoji: false,
+                })
+                .collect(),
+        };
+        let line = ShapedLine {
+            layout: Arc::new(LineLayout {
+                font_size: px(16.),
+                width: px(40.),
+                runs: vec![
+                    shaped_run(false, &[(0, 0.), (1, 10.)]),
+                    shaped_run(true, &[(2, 30.), (4, 20.)]),
+                ],
+                len: 6,
+                is_rtl: true,
+                ..Default::default()
+            }),
+            text: "abאב".into(),
+            decoration_runs: SmallVec::from_slice(&[run(2, red()), run(4, blue())]),
+            glyph_colors: Vec::new(),
+            inline_boxes: Vec::new(),
+            trailing_background_x: None,
+            composition_underlines: Vec::new(),
+        };
+        let glyphs = |line: &ShapedLine| {
+            line.runs
+                .iter()
+                .flat_map(|run| run.glyphs.iter())
+                .map(|glyph| (glyph.index, glyph.position.x))
+                .collect::<Vec<_>>()
+        };
+
+        // Each half closes up the space the other half's glyphs took, wherever they were.
+        let (left, right) = line.split_at(4);
+        assert_eq!((left.text.as_ref(), right.text.as_ref()), ("abא", "ב"));
+        assert_eq!(left.width, px(30.));
+        assert_eq!(right.width, px(10.));
+        assert_eq!(glyphs(&left), &[(0, px(0.)), (1, px(10.)), (2, px(20.))]);
+        assert_eq!(glyphs(&right), &[(0, px(0.))]);
+
+        let (left, right) = line.split_at(2);
+        assert_eq!((left.width, right.width), (px(20.), px(20.)));
+        assert_eq!(glyphs(&left), &[(0, px(0.)), (1, px(10.))]);
+        assert_eq!(glyphs(&right), &[(0, px(10.)), (2, px(0.))]);
+        assert!(left.is_rtl() && right.is_rtl());
+        assert!(right.runs.iter().all(|run| run.is_rtl));
+    }
+
+    #[test]
+    fn test_reserve_inline_boxes() {
+        // "a\u{fffc}b", where the object replacement character takes 3 bytes and 8px.
+        let glyphs = [(0, 0.), (1, 8.), (4, 16.)]
+            .into_iter()
+            .map(|(index, x)| ShapedGlyph {
+                id: GlyphId(index as u32),
+                position: point(px(x), px(0.)),
+                index,
+                is_emoji: false,
+            })
+            .collect();
+        let mut line = ShapedLine {
+            layout: Arc::new(LineLayout {
+                font_size: px(16.),
+                width: px(24.),
+                ascent: px(12.),
+                descent: px(4.),
+                runs: vec![ShapedRun {
+                    font_id: FontId(0),
+                    font_size: px(16.),
+                    is_rtl: false,
+                    glyphs,
+                }],
+                len: 5,
+                ligature_carets: Vec::new(),
+                is_rtl: false,
+            }),
+            text: "a\u{fffc}b".into(),
+            decoration_runs: SmallVec::from_slice(&[run(5, black())]),
+            glyph_colors: Vec::new(),
+            inline_boxes: Vec::new(),
+            trailing_background_x: None,
+            composition_underlines: Vec::new(),
+        };
+
+        line.reserve_inline_boxes([(2, size(px(20.), px(10.)))]);
+        assert_eq!(line.width, px(36.));
+        assert_eq!(
+            line.runs[0]
+                .glyphs
+                .iter()
+                .map(|glyph| glyph.position.x)
+                .collect::<Vec<_>>(),
+            &[px(0.), px(8.), px(28.)]
+        );
+        assert_eq!(
+            line.inline_box_bounds(point(px(100.), px(0.)), px(20.), VerticalAlign::Top)
+                .collect::<Vec<_>>(),
+            &[(
+                1..4,
+                Bounds::new(point(px(108.), px(2.)), size(px(20.), px(10.)))
+            )]
+        );
+
+        // Reserving the same box again resizes it rather than adding another.
+        line.reserve_inline_boxes([(1, size(px(12.), px(10.)))]);
+        assert_eq!(line.width, px(28.));
+        assert_eq!(line.inline_boxes.len(), 1);
+        assert_eq!(line.x_for_index(4), px(20.));
+    }
+
+    #[test]
+    fn test_align_to_cells() {
+        // "a中b", shaped with kerning that pulls "b" towards the ideograph.
+        let glyphs = [(0, 0.), (1, 7.5), (4, 22.)]
+            .into_iter()
+            .map(|(index, x)| ShapedGlyph {
+                id: GlyphId(index as u32),
+                position: point(px(x), px(0.)),
+                index,
+                is_emoji: false,
+            })
+            .collect();
+        let mut line = ShapedLine {
+            layout: Arc::new(LineLayout {
+                font_size: px(16.),
+                width: px(29.),
+                runs: vec![ShapedRun {
+                    font_id: FontId(0),
+                    font_size: px(16.),
+                    is_rtl: false,
+                    glyphs,
+                }],
+                len: 5,
+                ..Default::default()
+            }),
+            text: "a中b".into(),
+            decoration_runs: SmallVec::from_slice(&[run(5, black())]),
+            glyph_colors: Vec::new(),
+            inline_boxes: Vec::new(),
+            trailing_background_x: None,
+            composition_underlines: Vec::new(),
+        };
+
+        line.align_to_cells(px(8.));
+        assert_eq!(line.width, px(32.));
+        assert_eq!(
+            line.runs[0]
+                .glyphs
+                .iter()
+                .map(|glyph| glyph.position.x)
+                .collect::<Vec<_>>(),
+            &[px(0.), px(8.), px(24.)]
+        );
+    }
+
+    #[test]
+    fn test_decoration_run_opacity() {
+        let ghost = DecorationRun {
+            background_color: Some(red()),
+            opacity: 0.5,
+            ..run(4, black())
+        };
+
+        // Fading keeps the hue and only scales the alpha.
+        let glyph_color = ghost.faded(ghost.color);
+        assert_eq!((glyph_color.h, glyph_color.s, glyph_color.l), (0., 0., 0.));
+        assert_eq!(glyph_color.a, 0.5);
+        assert_eq!(ghost.faded(red()).a, 0.5);
+
+        // Over a solid white background, the faded glyph lands halfway to black.
+        let painted = white().blend(glyph_color);
+        assert_eq!(painted.a, 1.);
+        assert!((painted.l - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_word_range_at() {
+        let line = ShapedLine {
+            text: "fn main(a, b) {}".into(),
+            ..Default::default()
+        };
+        assert_eq!(line.word_range_at(0), 0..2);
+        assert_eq!(line.word_range_at(2), 2..3);
+        assert_eq!(line.word_range_at(5), 3..7);
+        assert_eq!(line.word_range_at(7), 7..8);
+        assert_eq!(line.word_range_at(9), 9..10);
+        assert_eq!(line.word_range_at(12), 12..13);
+        assert_eq!(line.word_range_at(16), 15..16);
+
+        // Ideographs have no spaces between them and each form a word, while runs of katakana
+        // stay together.
+        let line = ShapedLine {
+            text: "日本語テキスト。".into(),
+            ..Default::default()
+        };
+        assert_eq!(line.word_range_at(0), 0..3);
+        assert_eq!(line.word_range_at(4), 3..6);
+        assert_eq!(line.word_range_at(9), 9..21);
+        assert_eq!(line.word_range_at(20), 9..21);
+        assert_eq!(line.word_range_at(21), 21..24);
+    }
+
+    #[test]
+    fn test_column_for_index() {
+        // A tab, two ASCII letters, two ideographs, an "e" with a combining acute accent, and
+        // a final letter.
+        let line = ShapedLine {
+            text: "\tab日本e\u{301}x".into(),
+            ..Default::default()
+        };
+        let columns = [0, 1, 3, 6, 9, 10, 12, 13]
+            .map(|index| line.column_for_index(index, 4))
+            .to_vec();
+        assert_eq!(columns, [0, 4, 6, 8, 10, 10, 11, 12]);
+
+        // Tabs stop at the next multiple of the tab size, not a fixed distance away.
+        let line = ShapedLine {
+            text: "ab\tc\t".into(),
+            ..Default::default()
+        };
+        assert_eq!(line.column_for_index(3, 4), 4);
+        assert_eq!(line.column_for_index(5, 4), 8);
+        assert_eq!(line.column_for_index(3, 2), 4);
+    }
+
+    #[test]
+    fn test_composition_underlines() {
+        let underline = |range: Range<usize>| CompositionUnderline {
+            range,
+            style: UnderlineStyle::default(),
+        };
+        // "e" with a combining acute accent, followed by two hiragana.
+        let mut line = ShapedLine {
+            text: "e\u{301}かな".into(),
+            ..Default::default()
+        };
+        line.set_composition_underlines([underline(4..9), underline(1..2), underline(3..3)]);
+        assert_eq!(
+            line.composition_underlines
+                .iter()
+                .map(|underline| underline.range.clone())
+                .collect::<Vec<_>>(),
+            [0..3, 3..9]
+        );
+    }
+
+    // For compatibility with the test macro
+    use crate as gpui;
+
+    #[crate::test]
+    fn test_adjacent_underlines_with_the_same_resolved_style(cx: &mut TestAppContext) {
+        let cx = cx.add_empty_window();
+        let underline = UnderlineStyle {
+            thickness: px(1.),
+            ..Default::default()
+        };
+        let run = |color: Hsla, underline_color: Option<Hsla>| TextRun {
+            len: 4,
+            font: font("Helvetica"),
+            color,
+            underline: Some(UnderlineStyle {
+                color: underline_color,
+                ..underline
+            }),
+            ..Default::default()
+        };
+        let mut count_underlines = |runs: &[TextRun]| {
+            cx.update(|cx| {
+                let line = cx
+                    .text_system()
+                    .shape_line("aaaabbbb".into(), px(16.), runs)
+                    .unwrap();
+                cx.with_element_context(|cx| {
+                    let underlines = cx.window.next_frame.scene.underlines.len();
+                    line.paint(Point::default(), px(20.), cx).unwrap();
+                    cx.window.next_frame.scene.underlines.len() - underlines
+                })
+            })
+        };
+
+        // An underline without a color takes on its run's color, so it looks the same as an
+        // explicitly colored underline next to it and both are painted as one.
+        assert_eq!(
+            count_underlines(&[run(red(), None), run(red(), Some(red()))]),
+            1
+        );
+        assert_eq!(count_underlines(&[run(red(), None), run(blue(), None)]), 2);
+        assert_eq!(
+            count_underlines(&[run(red(), None), run(red(), Some(blue()))]),
+            2
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use crate::{px, FontId, GlyphId, Pixels, PlatformTextSystem, Point, Size};
 use collections::FxHashMap;
+use itertools::Either;
 use parking_lot::{Mutex, RwLock, RwLockUpgradableReadGuard};
 use smallvec::SmallVec;
 use std::{
@@ -8,6 +9,7 @@ use std::{
     ops::Range,
     sync::Arc,
 };
+use unicode_segmentation::UnicodeSegmentation;
 
 /// A laid out and styled line of text
 #[derive(Default, Debug)]
@@ -20,10 +22,16 @@ pub struct LineLayout {
     pub ascent: Pixels,
     /// The descent of the line
     pub descent: Pixels,
-    /// The shaped runs that make up this line
+    /// The shaped runs that make up this line, in the order of the text rather than from left to
+    /// right, so the index of each glyph is at least that of the glyph before it
     pub runs: Vec<ShapedRun>,
     /// The length of the line in utf-8 bytes
     pub len: usize,
+    /// The grapheme boundaries inside ligatures, in utf-8 bytes, where a cursor can sit part of
+    /// the way across a single glyph.
+    pub ligature_carets: Vec<usize>,
+    /// Whether any of the runs read from right to left; see [`Self::is_rtl`].
+    pub(crate) is_rtl: bool,
 }
 
 /// A run of text that has been shaped .
@@ -31,6 +39,11 @@ pub struct LineLayout {
 pub struct ShapedRun {
     /// The font id for this run
     pub font_id: FontId,
+    /// The font size for this run
+    pub font_size: Pixels,
+    /// Whether this run reads from right to left, such as a run of Hebrew. Its glyphs are still
+    /// in the order of the text, so their positions move leftwards along the run.
+    pub is_rtl: bool,
     /// The glyphs that make up this run
     pub glyphs: SmallVec<[ShapedGlyph; 8]>,
 }
@@ -56,51 +69,400 @@ impl LineLayout {
     pub fn index_for_x(&self, x: Pixels) -> Option<usize> {
         if x >= self.width {
             None
+        } else if self.is_rtl {
+            let spans = self.bidi_caret_spans();
+            Some(
+                spans
+                    .iter()
+                    .find(|span| {
+                        span.start_x.min(span.end_x) <= x && x < span.start_x.max(span.end_x)
+                    })
+                    .map_or_else(|| self.closest_index_for_x(x), |span| span.index),
+            )
         } else {
-            for run in self.runs.iter().rev() {
-                for glyph in run.glyphs.iter().rev() {
-                    if glyph.position.x <= x {
-                        return Some(glyph.index);
-                    }
-                }
-            }
-            Some(0)
+            Some(
+                self.caret_positions()
+                    .rev()
+                    .find(|(_, caret_x)| *caret_x <= x)
+                    .map_or(0, |(index, _)| index),
+            )
         }
     }
 
     /// closest_index_for_x returns the character boundary closest to the given x coordinate
     /// (e.g. to handle aligning up/down arrow keys)
     pub fn closest_index_for_x(&self, x: Pixels) -> usize {
+        if self.is_rtl {
+            // Either edge of a character can be closest, and its far edge is where the next
+            // character in the text starts, even when that character is elsewhere on the line.
+            let spans = self.bidi_caret_spans();
+            let next_indices = spans
+                .iter()
+                .skip(1)
+                .map(|span| span.index)
+                .chain([self.len]);
+            return spans
+                .iter()
+                .zip(next_indices)
+                .flat_map(|(span, next_index)| {
+                    [(span.index, span.start_x), (next_index, span.end_x)]
+                })
+                .min_by_key(|(_, caret_x)| (*caret_x - x).abs())
+                .map_or(self.len, |(index, _)| index);
+        }
+
         let mut prev_index = 0;
         let mut prev_x = px(0.);
 
-        for run in self.runs.iter() {
-            for glyph in run.glyphs.iter() {
-                if glyph.position.x >= x {
-                    if glyph.position.x - x < x - prev_x {
-                        return glyph.index;
-                    } else {
-                        return prev_index;
-                    }
+        for (index, caret_x) in self.caret_positions() {
+            if caret_x >= x {
+                if caret_x - x < x - prev_x {
+                    return index;
+                } else {
+                    return prev_index;
                 }
-                prev_index = glyph.index;
-                prev_x = glyph.position.x;
             }
+            prev_index = index;
+            prev_x = caret_x;
         }
 
         self.len
     }
 
+    /// The byte range of the text covered by each cluster of glyphs, in order. A ligature's
+    /// range spans all of the characters it was shaped from, and the glyphs of a character
+    /// shaped as several glyphs share one range.
+    pub fn cluster_ranges(&self) -> Vec<Range<usize>> {
+        let mut starts = self
+            .runs
+            .iter()
+            .flat_map(|run| run.glyphs.iter().map(|glyph| glyph.index))
+            .peekable();
+        let mut ranges = Vec::new();
+        while let Some(start) = starts.next() {
+            while starts.next_if_eq(&start).is_some() {}
+            let end = starts.peek().copied().unwrap_or(self.len);
+            ranges.push(start..end);
+        }
+        ranges
+    }
+
     /// The x position of the character at the given index
     pub fn x_for_index(&self, index: usize) -> Pixels {
-        for run in &self.runs {
+        if self.is_rtl {
+            // The end of the text is at the far edge of its last character.
+            let spans = self.bidi_caret_spans();
+            return match spans.iter().find(|span| span.index >= index) {
+                Some(span) => span.start_x,
+                None => spans.last().map_or(self.width, |span| span.end_x),
+            };
+        }
+
+        self.caret_positions()
+            .find(|(caret_index, _)| *caret_index >= index)
+            .map_or(self.width, |(_, x)| x)
+    }
+
+    /// The stretches of the line taken up by the text before and after `index`, a cluster
+    /// boundary. Without right-to-left runs, these are the line on either side of
+    /// [`Self::x_for_index`], but otherwise the text on either side can be spread across the
+    /// line.
+    pub(crate) fn extents_around(&self, index: usize) -> (Vec<Range<Pixels>>, Vec<Range<Pixels>>) {
+        if !self.is_rtl {
+            let x = self.x_for_index(index);
+            return (vec![px(0.)..x], vec![x..self.width]);
+        }
+
+        let mut before = Vec::new();
+        let mut after = Vec::new();
+        for span in self.bidi_caret_spans() {
+            let extent = span.start_x.min(span.end_x)..span.start_x.max(span.end_x);
+            if span.index < index {
+                before.push(extent);
+            } else {
+                after.push(extent);
+            }
+        }
+        (before, after)
+    }
+
+    /// Every place the cursor can sit on a line with right-to-left runs, in the order of the
+    /// text, along with the stretch of the line up to the next place. Like
+    /// [`Self::caret_positions`], these are the start of each cluster of glyphs and the
+    /// [`Self::ligature_carets`], but a right-to-left cluster starts at its right edge and
+    /// ends at its left edge. Runs sit side by side, so each one ends where the next one to its
+    /// right starts.
+    fn bidi_caret_spans(&self) -> Vec<CaretSpan> {
+        let run_lefts = self
+            .runs
+            .iter()
+            .map(|run| {
+                run.glyphs
+                    .iter()
+                    .map(|glyph| glyph.position.x)
+                    .min()
+                    .unwrap_or(self.width)
+            })
+            .collect::<Vec<_>>();
+        let mut sorted_lefts = run_lefts.clone();
+        sorted_lefts.sort();
+
+        let mut clusters = Vec::<CaretSpan>::new();
+        for (run, left) in self.runs.iter().zip(&run_lefts) {
+            let right = sorted_lefts
+                .get(sorted_lefts.partition_point(|x| x <= left))
+                .copied()
+                .unwrap_or(self.width);
+            let run_start = clusters.len();
             for glyph in &run.glyphs {
-                if glyph.index >= index {
-                    return glyph.position.x;
+                let x = glyph.position.x;
+                let in_run = run_start < clusters.len();
+                match clusters.last_mut() {
+                    Some(cluster) if in_run && cluster.index == glyph.index => {
+                        cluster.end_x = cluster.end_x.min(x);
+                    }
+                    _ => clusters.push(CaretSpan {
+                        index: glyph.index,
+                        start_x: x,
+                        end_x: x,
+                    }),
+                }
+            }
+
+            let run_clusters = &mut clusters[run_start..];
+            if run.is_rtl {
+                // Each cluster starts at the left edge of the one before it.
+                let mut start_x = right;
+                for cluster in run_clusters {
+                    cluster.start_x = start_x;
+                    start_x = cluster.end_x;
+                }
+            } else {
+                let mut end_x = right;
+                for cluster in run_clusters.iter_mut().rev() {
+                    cluster.end_x = end_x;
+                    end_x = cluster.start_x;
                 }
             }
         }
-        self.width
+
+        let mut spans = Vec::with_capacity(clusters.len());
+        let mut ligature_carets = self.ligature_carets.iter().copied().peekable();
+        for (ix, cluster) in clusters.iter().enumerate() {
+            let end = clusters.get(ix + 1).map_or(self.len, |next| next.index);
+            let mut span = *cluster;
+            while let Some(caret) = ligature_carets.next_if(|caret| *caret < end) {
+                if caret > cluster.index {
+                    let progress = (caret - cluster.index) as f32 / (end - cluster.index) as f32;
+                    let caret_x = cluster.start_x + (cluster.end_x - cluster.start_x) * progress;
+                    span.end_x = caret_x;
+                    spans.push(span);
+                    span = CaretSpan {
+                        index: caret,
+                        start_x: caret_x,
+                        end_x: cluster.end_x,
+                    };
+                }
+            }
+            spans.push(span);
+        }
+        spans
+    }
+
+    /// The index and x position of every place the cursor can sit on a line without
+    /// right-to-left runs, in order: the start of each glyph, along with the
+    /// [`Self::ligature_carets`], which are spaced across their ligature's advance in
+    /// proportion to how many of its bytes precede them.
+    fn caret_positions(&self) -> impl DoubleEndedIterator<Item = (usize, Pixels)> + '_ {
+        let glyph_starts = self
+            .runs
+            .iter()
+            .flat_map(|run| run.glyphs.iter())
+            .map(|glyph| (glyph.index, glyph.position.x));
+        if self.ligature_carets.is_empty() {
+            return Either::Left(glyph_starts);
+        }
+
+        let mut positions = Vec::new();
+        let mut glyph_starts = glyph_starts.peekable();
+        let mut ligature_carets = self.ligature_carets.iter().copied().peekable();
+        let mut cluster = None;
+        while let Some((index, x)) = glyph_starts.next() {
+            positions.push((index, x));
+            let (start, start_x) = match cluster {
+                Some((start, start_x)) if start == index => (start, start_x),
+                _ => (index, x),
+            };
+            cluster = Some((start, start_x));
+
+            // Carets go after the last glyph of their cluster.
+            let (end, end_x) = match glyph_starts.peek() {
+                Some((next_index, _)) if *next_index == index => continue,
+                Some(next) => *next,
+                None => (self.len, self.width),
+            };
+            while let Some(caret) = ligature_carets.next_if(|caret| *caret < end) {
+                if caret > start {
+                    let progress = (caret - start) as f32 / (end - start) as f32;
+                    positions.push((caret, start_x + (end_x - start_x) * progress));
+                }
+            }
+        }
+        Either::Right(positions.into_iter())
+    }
+
+    /// Removes the glyphs that were produced for C0 control characters and zero-width joiners,
+    /// collapsing their advance so that they don't shift the glyphs that follow them.
+    ///
+    /// Shapers tend to render these characters as boxes or give them an arbitrary width. Once
+    /// their glyphs are removed, their bytes belong to the preceding glyph's cluster, which
+    /// keeps [`Self::index_for_x`] and [`Self::x_for_index`] from landing inside them.
+    pub(crate) fn collapse_invisible_glyphs(&mut self, text: &str) {
+        let is_invisible = |ix: usize| text[ix..].chars().next().map_or(false, is_invisible_char);
+        if !self
+            .runs
+            .iter()
+            .flat_map(|run| run.glyphs.iter())
+            .any(|glyph| is_invisible(glyph.index))
+        {
+            return;
+        }
+
+        if self.is_rtl {
+            // The glyphs aren't stored from left to right, so each invisible glyph is measured up
+            // to the glyph on its right, and collapsing it shifts every glyph to its right.
+            let mut lefts = self
+                .runs
+                .iter()
+                .flat_map(|run| run.glyphs.iter().map(|glyph| glyph.position.x))
+                .collect::<Vec<_>>();
+            lefts.sort();
+            let collapsed = self
+                .runs
+                .iter()
+                .flat_map(|run| run.glyphs.iter())
+                .filter(|glyph| is_invisible(glyph.index))
+                .map(|glyph| {
+                    let x = glyph.position.x;
+                    let next_x = lefts
+                        .get(lefts.partition_point(|left| *left <= x))
+                        .copied()
+                        .unwrap_or(self.width);
+                    (x, next_x - x)
+                })
+                .collect::<Vec<_>>();
+            for run in &mut self.runs {
+                run.glyphs.retain(|glyph| {
+                    if is_invisible(glyph.index) {
+                        return false;
+                    }
+                    let x = glyph.position.x;
+                    glyph.position.x -= collapsed
+                        .iter()
+                        .filter(|(collapsed_x, _)| *collapsed_x < x)
+                        .fold(px(0.), |sum, (_, advance)| sum + *advance);
+                    true
+                });
+            }
+            self.runs.retain(|run| !run.glyphs.is_empty());
+            self.width -= collapsed
+                .iter()
+                .fold(px(0.), |sum, (_, advance)| sum + *advance);
+            return;
+        }
+
+        let mut advances = Vec::new();
+        let mut next_x = self.width;
+        for run in self.runs.iter().rev() {
+            for glyph in run.glyphs.iter().rev() {
+                advances.push(next_x - glyph.position.x);
+                next_x = glyph.position.x;
+            }
+        }
+
+        let mut advances = advances.into_iter().rev();
+        let mut collapsed_width = px(0.);
+        for run in &mut self.runs {
+            run.glyphs.retain(|glyph| {
+                let advance = advances.next().unwrap_or_default();
+                if is_invisible(glyph.index) {
+                    collapsed_width += advance;
+                    false
+                } else {
+                    glyph.position.x -= collapsed_width;
+                    true
+                }
+            });
+        }
+        self.runs.retain(|run| !run.glyphs.is_empty());
+        self.width -= collapsed_width;
+    }
+
+    /// Moves the index of every glyph that starts inside a grapheme cluster, such as a combining
+    /// accent, the second half of a flag or a member of a ZWJ sequence, back to the start of
+    /// that cluster. This keeps [`Self::index_for_x`] and [`Self::x_for_index`] from placing
+    /// the cursor between the code points of a grapheme.
+    pub(crate) fn snap_glyph_indices_to_graphemes(&mut self, text: &str) {
+        if text.is_ascii() {
+            return;
+        }
+
+        let grapheme_starts = text
+            .grapheme_indices(true)
+            .map(|(ix, _)| ix)
+            .collect::<Vec<_>>();
+        for glyph in self.runs.iter_mut().flat_map(|run| run.glyphs.iter_mut()) {
+            let grapheme_ix = match grapheme_starts.binary_search(&glyph.index) {
+                Ok(ix) => ix,
+                Err(ix) => ix.saturating_sub(1),
+            };
+            glyph.index = grapheme_starts[grapheme_ix];
+        }
+    }
+
+    /// Whether any of the line's text reads from right to left, such as a Hebrew word, as the
+    /// platform's bidirectional analysis found when shaping it. Labels like this are usually
+    /// best aligned to the right, and the positions of their glyphs don't grow along with
+    /// their indices.
+    pub fn is_rtl(&self) -> bool {
+        self.is_rtl
+    }
+
+    /// Finds the [`Self::ligature_carets`]: the start of every grapheme but the first in a
+    /// cluster, such as the "i" of an "fi" ligature. Characters that were collapsed into the
+    /// cluster before them aren't given a caret.
+    pub(crate) fn find_ligature_carets(&mut self, text: &str) {
+        self.ligature_carets.clear();
+        for range in self.cluster_ranges() {
+            let Some(cluster) = text.get(range.clone()) else {
+                continue;
+            };
+            for (ix, grapheme) in cluster.grapheme_indices(true).skip(1) {
+                if !grapheme.starts_with(is_invisible_char) {
+                    self.ligature_carets.push(range.start + ix);
+                }
+            }
+        }
+    }
+
+    /// Marks glyphs as emoji or text according to the variation selector in their grapheme:
+    /// U+FE0F requests emoji presentation and U+FE0E requests text presentation, which decides
+    /// whether a character like "❤" is painted as a color emoji or as a monochrome glyph.
+    pub(crate) fn apply_presentation_selectors(&mut self, text: &str) {
+        if !text.contains(['\u{fe0e}', '\u{fe0f}']) {
+            return;
+        }
+
+        for glyph in self.runs.iter_mut().flat_map(|run| run.glyphs.iter_mut()) {
+            let Some(grapheme) = text[glyph.index..].graphemes(true).next() else {
+                continue;
+            };
+            if grapheme.contains('\u{fe0f}') {
+                glyph.is_emoji = true;
+            } else if grapheme.contains('\u{fe0e}') {
+                glyph.is_emoji = false;
+            }
+        }
     }
 
     fn compute_wrap_boundaries(
@@ -169,6 +531,21 @@ impl LineLayout {
     }
 }
 
+/// A place the cursor can sit on a line with right-to-left runs, and the stretch of the line up
+/// to the next one, which goes leftwards within a right-to-left run.
+#[derive(Clone, Copy, Debug)]
+struct CaretSpan {
+    index: usize,
+    start_x: Pixels,
+    end_x: Pixels,
+}
+
+/// Whether the given character should occupy no space in a laid out line.
+/// Tabs are excluded, as their width is meaningful to callers.
+fn is_invisible_char(ch: char) -> bool {
+    (ch.is_ascii_control() && ch != '\t') || ch == '\u{200d}'
+}
+
 /// A line of text that has been wrapped to fit a given width
 #[derive(Default, Debug)]
 pub struct WrappedLineLayout {
@@ -275,6 +652,35 @@ impl WrappedLineLayout {
                 .index_for_x(position_in_unwrapped_line.x)
         }
     }
+
+    /// The character boundary closest to a given position in this layout for the given line
+    /// height. Unlike `index_for_position`, positions outside of the text are clamped to the
+    /// nearest row and column, and positions below the last row resolve to the end of the line.
+    pub fn closest_index_for_position(
+        &self,
+        position: Point<Pixels>,
+        line_height: Pixels,
+    ) -> usize {
+        let wrapped_line_ix = (position.y / line_height).max(0.) as usize;
+        if wrapped_line_ix > self.wrap_boundaries.len() {
+            return self.len();
+        }
+
+        let boundary_x = |boundary: &WrapBoundary| {
+            let run = &self.unwrapped_layout.runs[boundary.run_ix];
+            run.glyphs[boundary.glyph_ix].position.x
+        };
+        let wrapped_line_start_x = wrapped_line_ix
+            .checked_sub(1)
+            .map_or(Pixels::ZERO, |ix| boundary_x(&self.wrap_boundaries[ix]));
+        let wrapped_line_end_x = self
+            .wrap_boundaries
+            .get(wrapped_line_ix)
+            .map_or(self.unwrapped_layout.width, boundary_x);
+
+        let x = (wrapped_line_start_x + position.x.max(Pixels::ZERO)).min(wrapped_line_end_x);
+        self.unwrapped_layout.closest_index_for_x(x)
+    }
 }
 
 pub(crate) struct LineLayoutCache {
@@ -422,7 +828,12 @@ impl LineLayoutCache {
             current_frame.used_lines.push(key);
             layout
         } else {
-            let layout = Arc::new(self.platform_text_system.layout_line(text, font_size, runs));
+            let mut layout = self.platform_text_system.layout_line(text, font_size, runs);
+            layout.collapse_invisible_glyphs(text);
+            layout.snap_glyph_indices_to_graphemes(text);
+            layout.find_ligature_carets(text);
+            layout.apply_presentation_selectors(text);
+            let layout = Arc::new(layout);
             let key = Arc::new(CacheKey {
                 text: text.into(),
                 font_size,
@@ -441,6 +852,8 @@ impl LineLayoutCache {
 pub struct FontRun {
     pub(crate) len: usize,
     pub(crate) font_id: FontId,
+    /// Overrides the font size of the line for this run.
+    pub(crate) font_size: Option<Pixels>,
 }
 
 trait AsCacheKeyRef {
@@ -511,3 +924,250 @@ impl<'a> AsCacheKeyRef for CacheKeyRef<'a> {
         *self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::point;
+
+    fn layout(text: &str, glyphs: &[(usize, f32)], width: f32) -> LineLayout {
+        let mut layout = LineLayout {
+            font_size: px(16.),
+            width: px(width),
+            ascent: px(12.),
+            descent: px(4.),
+            runs: vec![ShapedRun {
+                font_id: FontId(0),
+                font_size: px(16.),
+                is_rtl: false,
+                glyphs: glyphs
+                    .iter()
+                    .map(|&(index, x)| ShapedGlyph {
+                        id: GlyphId(0),
+                        position: point(px(x), px(0.)),
+                        index,
+                        is_emoji: false,
+                    })
+                    .collect(),
+            }],
+            len: text.len(),
+            ligature_carets: Vec::new(),
+            is_rtl: false,
+        };
+        layout.snap_glyph_indices_to_graphemes(text);
+        layout.find_ligature_carets(text);
+        layout
+    }
+
+    /// A layout with a run for each direction change, given as whether the run is right-to-left
+    /// and its glyphs in the order of the text.
+    fn bidi_layout(text: &str, runs: &[(bool, &[(usize, f32)])], width: f32) -> LineLayout {
+        let mut layout = layout(text, &[], width);
+        layout.runs = runs
+            .iter()
+            .map(|(is_rtl, glyphs)| ShapedRun {
+                font_id: FontId(0),
+                font_size: px(16.),
+                is_rtl: *is_rtl,
+                glyphs: glyphs
+                    .iter()
+                    .map(|&(index, x)| ShapedGlyph {
+                        id: GlyphId(0),
+                        position: point(px(x), px(0.)),
+                        index,
+                        is_emoji: false,
+                    })
+                    .collect(),
+            })
+            .collect();
+        layout.is_rtl = runs.iter().any(|(is_rtl, _)| *is_rtl);
+        layout.find_ligature_carets(text);
+        layout
+    }
+
+    #[test]
+    fn test_index_helpers_with_combining_accents() {
+        // "e" followed by a combining acute accent, drawn over the "e", then "x".
+        let layout = layout("e\u{301}x", &[(0, 0.), (1, 2.), (3, 10.)], 20.);
+        assert_eq!(layout.index_for_x(px(5.)), Some(0));
+        assert_eq!(layout.index_for_x(px(15.)), Some(3));
+        assert_eq!(layout.closest_index_for_x(px(4.)), 0);
+        assert_eq!(layout.x_for_index(0), px(0.));
+        assert_eq!(layout.x_for_index(1), px(10.));
+        assert_eq!(layout.x_for_index(3), px(10.));
+    }
+
+    #[test]
+    fn test_index_helpers_with_regional_indicator_flags() {
+        // A flag made of two regional indicators, shaped as two glyphs, then "a".
+        let layout = layout("\u{1f1fa}\u{1f1f8}a", &[(0, 0.), (4, 10.), (8, 20.)], 30.);
+        assert_eq!(layout.index_for_x(px(15.)), Some(0));
+        assert_eq!(layout.index_for_x(px(25.)), Some(8));
+        assert_eq!(layout.closest_index_for_x(px(12.)), 0);
+        assert_eq!(layout.x_for_index(4), px(20.));
+    }
+
+    #[test]
+    fn test_index_helpers_with_zwj_sequences() {
+        // A family emoji made of three people joined by zero-width joiners, then "a".
+        let text = "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}a";
+        let layout = layout(text, &[(0, 0.), (7, 10.), (14, 20.), (18, 30.)], 40.);
+        assert_eq!(layout.index_for_x(px(25.)), Some(0));
+        assert_eq!(layout.index_for_x(px(35.)), Some(18));
+        assert_eq!(layout.closest_index_for_x(px(22.)), 0);
+        assert_eq!(layout.x_for_index(7), px(30.));
+        assert_eq!(layout.x_for_index(18), px(30.));
+    }
+
+    #[test]
+    fn test_closest_index_for_position_in_wrapped_line() {
+        // "abcdef", wrapped before the "d".
+        let glyphs = [(0, 0.), (1, 10.), (2, 20.), (3, 30.), (4, 40.), (5, 50.)];
+        let layout = WrappedLineLayout {
+            unwrapped_layout: Arc::new(layout("abcdef", &glyphs, 60.)),
+            wrap_boundaries: smallvec::smallvec![WrapBoundary {
+                run_ix: 0,
+                glyph_ix: 3,
+            }],
+            wrap_width: Some(px(30.)),
+        };
+        let line_height = px(20.);
+        let index = |x, y| layout.closest_index_for_position(point(px(x), px(y)), line_height);
+
+        assert_eq!(index(-5., -5.), 0);
+        assert_eq!(index(14., 5.), 1);
+        assert_eq!(index(100., 5.), 3);
+        assert_eq!(index(12., 25.), 4);
+        assert_eq!(index(100., 25.), 6);
+        assert_eq!(index(5., 100.), 6);
+    }
+
+    #[test]
+    fn test_presentation_selectors() {
+        let presentations = |text: &str, glyphs: &[(usize, f32)], is_emoji: bool| {
+            let mut layout = layout(text, glyphs, 40.);
+            for glyph in layout.runs.iter_mut().flat_map(|run| run.glyphs.iter_mut()) {
+                glyph.is_emoji = is_emoji;
+            }
+            layout.apply_presentation_selectors(text);
+            layout.runs[0]
+                .glyphs
+                .iter()
+                .map(|glyph| glyph.is_emoji)
+                .collect::<Vec<_>>()
+        };
+
+        // A heart followed by the emoji or text selector, then "a".
+        assert_eq!(
+            presentations("\u{2764}\u{fe0f}a", &[(0, 0.), (6, 20.)], false),
+            &[true, false]
+        );
+        assert_eq!(
+            presentations("\u{2764}\u{fe0e}a", &[(0, 0.), (6, 10.)], true),
+            &[false, true]
+        );
+
+        // A keycap made of a digit, a selector and the combining enclosing keycap.
+        assert_eq!(
+            presentations("1\u{fe0f}\u{20e3}", &[(0, 0.), (4, 0.)], false),
+            &[true, true]
+        );
+        assert_eq!(
+            presentations("1\u{fe0e}\u{20e3}", &[(0, 0.), (4, 0.)], true),
+            &[false, false]
+        );
+
+        // Without a selector, the shaper's choice is kept.
+        assert_eq!(presentations("\u{2764}", &[(0, 0.)], true), &[true]);
+    }
+
+    #[test]
+    fn test_cluster_ranges() {
+        // "óffice", with a combining accent shaped as its own glyph and "ffi" as a ligature.
+        let text = "o\u{301}ffice";
+        let layout = layout(text, &[(0, 0.), (1, 0.), (3, 8.), (6, 20.), (7, 28.)], 36.);
+        assert_eq!(layout.cluster_ranges(), &[0..3, 3..6, 6..7, 7..8]);
+    }
+
+    #[test]
+    fn test_cursor_inside_ligature() {
+        // "fix", with "fi" shaped as a single ligature glyph.
+        let layout = layout("fix", &[(0, 0.), (2, 10.)], 16.);
+        assert_eq!(layout.ligature_carets, &[1]);
+        assert_eq!(layout.x_for_index(0), px(0.));
+        assert_eq!(layout.x_for_index(1), px(5.));
+        assert_eq!(layout.x_for_index(2), px(10.));
+        assert_eq!(layout.index_for_x(px(4.)), Some(0));
+        assert_eq!(layout.index_for_x(px(6.)), Some(1));
+        assert_eq!(layout.index_for_x(px(12.)), Some(2));
+        assert_eq!(layout.closest_index_for_x(px(3.)), 1);
+        assert_eq!(layout.closest_index_for_x(px(8.)), 2);
+
+        // An "ffi" ligature ending the line, spaced across its advance by byte.
+        let layout = layout("ffi", &[(0, 0.)], 12.);
+        assert_eq!(layout.ligature_carets, &[1, 2]);
+        assert_eq!(layout.x_for_index(1), px(4.));
+        assert_eq!(layout.x_for_index(2), px(8.));
+        assert_eq!(layout.index_for_x(px(9.)), Some(2));
+
+        // A combining accent is part of its grapheme, so it doesn't get a caret.
+        let layout = layout("e\u{301}x", &[(0, 0.), (3, 10.)], 20.);
+        assert!(layout.ligature_carets.is_empty());
+    }
+
+    #[test]
+    fn test_index_helpers_with_rtl_runs() {
+        // "ab" followed by the Hebrew "אב", which is laid out from right to left after it.
+        let text = "abאב";
+        assert!(!layout("ab", &[(0, 0.), (1, 10.)], 20.).is_rtl());
+        let layout = bidi_layout(
+            text,
+            &[(false, &[(0, 0.), (1, 10.)]), (true, &[(2, 30.), (4, 20.)])],
+            40.,
+        );
+        assert!(layout.is_rtl());
+        assert_eq!(layout.cluster_ranges(), &[0..1, 1..2, 2..4, 4..6]);
+
+        // Each Hebrew letter starts at its right edge, and the text ends at the left edge of
+        // the last one.
+        assert_eq!(layout.x_for_index(1), px(10.));
+        assert_eq!(layout.x_for_index(2), px(40.));
+        assert_eq!(layout.x_for_index(4), px(30.));
+        assert_eq!(layout.x_for_index(6), px(20.));
+
+        assert_eq!(layout.index_for_x(px(15.)), Some(1));
+        assert_eq!(layout.index_for_x(px(25.)), Some(4));
+        assert_eq!(layout.index_for_x(px(35.)), Some(2));
+        assert_eq!(layout.closest_index_for_x(px(9.)), 1);
+        assert_eq!(layout.closest_index_for_x(px(29.)), 4);
+        assert_eq!(layout.closest_index_for_x(px(38.)), 2);
+    }
+
+    #[test]
+    fn test_cursor_inside_rtl_ligature() {
+        // The Arabic "لا", with the lam and alef shaped as a single ligature glyph.
+        let layout = bidi_layout("لا", &[(true, &[(0, 0.)])], 10.);
+        assert_eq!(layout.ligature_carets, &[2]);
+        assert_eq!(layout.x_for_index(0), px(10.));
+        assert_eq!(layout.x_for_index(2), px(5.));
+        assert_eq!(layout.x_for_index(4), px(0.));
+        assert_eq!(layout.index_for_x(px(7.)), Some(0));
+        assert_eq!(layout.index_for_x(px(3.)), Some(2));
+    }
+
+    #[test]
+    fn test_collapse_invisible_glyphs_in_rtl_run() {
+        // "א", a zero-width joiner and "ב", laid out from right to left.
+        let text = "א\u{200d}ב";
+        let mut layout = bidi_layout(text, &[(true, &[(0, 15.), (2, 10.), (5, 0.)])], 25.);
+        layout.collapse_invisible_glyphs(text);
+
+        let glyphs = layout.runs[0]
+            .glyphs
+            .iter()
+            .map(|glyph| (glyph.index, glyph.position.x))
+            .collect::<Vec<_>>();
+        assert_eq!(glyphs, &[(0, px(10.)), (5, px(0.))]);
+        assert_eq!(layout.width, px(20.));
+    }
+}
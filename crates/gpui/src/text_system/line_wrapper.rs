@@ -119,6 +119,7 @@ impl LineWrapper {
                 &[FontRun {
                     len: buffer.len(),
                     font_id: self.font_id,
+                    font_size: None,
                 }],
             )
             .width
@@ -223,18 +224,12 @@ mod tests {
             let normal = TextRun {
                 len: 0,
                 font: font("Helvetica"),
-                color: Default::default(),
-                underline: Default::default(),
-                strikethrough: None,
-                background_color: None,
+                ..Default::default()
             };
             let bold = TextRun {
                 len: 0,
                 font: font("Helvetica").bold(),
-                color: Default::default(),
-                underline: Default::default(),
-                strikethrough: None,
-                background_color: None,
+                ..Default::default()
             };
 
             impl TextRun {
@@ -0,0 +1,200 @@
+use crate::{FontRun, LineLayout, ShapedRun};
+use itertools::Itertools;
+use smallvec::SmallVec;
+
+/// How the letters of a run of text are cased when it's laid out, without changing the text
+/// itself, like CSS's `text-transform`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum TextTransform {
+    /// Lay out the text as it is.
+    #[default]
+    None,
+    /// Lay out every letter in upper case.
+    Uppercase,
+    /// Lay out every letter in lower case.
+    Lowercase,
+    /// Lay out the first letter of every word in upper case.
+    Capitalize,
+}
+
+/// A line of text with the transforms of its runs applied, to be laid out in place of the
+/// original. Changing case can change the length of a character, as with "ß" becoming "SS", so
+/// this also keeps track of where each character came from.
+pub(crate) struct TransformedText {
+    pub(crate) text: String,
+    /// The start of every original character in the transformed text and in the original
+    /// text, in order.
+    char_starts: Vec<(usize, usize)>,
+    original_len: usize,
+}
+
+impl TransformedText {
+    /// Apply the transform of each run, given with the run's length in bytes. Returns `None`
+    /// when none of the runs transform their text.
+    pub(crate) fn new(
+        text: &str,
+        runs: impl IntoIterator<Item = (usize, TextTransform)>,
+    ) -> Option<Self> {
+        let runs = runs.into_iter().collect::<SmallVec<[_; 8]>>();
+        if runs
+            .iter()
+            .all(|(_, transform)| *transform == TextTransform::None)
+        {
+            return None;
+        }
+
+        let mut transformed = String::with_capacity(text.len());
+        let mut char_starts = Vec::with_capacity(text.len());
+        let mut previous_char = None;
+        let mut run_start = 0;
+        // Text past the end of the runs is left as it is.
+        let trailing_run = (
+            text.len()
+                .saturating_sub(runs.iter().map(|(len, _)| *len).sum::<usize>()),
+            TextTransform::None,
+        );
+        for (len, transform) in runs.into_iter().chain([trailing_run]) {
+            let run_end = (run_start + len).min(text.len());
+            for (ix, ch) in text[run_start..run_end].char_indices() {
+                char_starts.push((transformed.len(), run_start + ix));
+                let starts_word = previous_char.map_or(true, |ch: char| !ch.is_alphanumeric());
+                match transform {
+                    TextTransform::Uppercase => transformed.extend(ch.to_uppercase()),
+                    TextTransform::Lowercase => transformed.extend(ch.to_lowercase()),
+                    TextTransform::Capitalize if starts_word => {
+                        transformed.extend(ch.to_uppercase())
+                    }
+                    TextTransform::Capitalize | TextTransform::None => transformed.push(ch),
+                }
+                previous_char = Some(ch);
+            }
+            run_start = run_end;
+        }
+
+        Some(Self {
+            text: transformed,
+            char_starts,
+            original_len: text.len(),
+        })
+    }
+
+    /// Maps a byte index in the original text to the transformed text.
+    fn transformed_ix(&self, original_ix: usize) -> usize {
+        let ix = self
+            .char_starts
+            .partition_point(|(_, original_start)| *original_start < original_ix);
+        self.char_starts
+            .get(ix)
+            .map_or(self.text.len(), |(transformed_start, _)| *transformed_start)
+    }
+
+    /// Maps a byte index in the transformed text to the start of the original character it
+    /// came from.
+    fn original_ix(&self, transformed_ix: usize) -> usize {
+        let ix = self
+            .char_starts
+            .partition_point(|(transformed_start, _)| *transformed_start <= transformed_ix);
+        ix.checked_sub(1).map_or(0, |ix| self.char_starts[ix].1)
+    }
+
+    /// Resize font runs, given over the original text, to cover the same characters of the
+    /// transformed text.
+    pub(crate) fn transform_font_runs(&self, font_runs: &mut [FontRun]) {
+        let mut original_end = 0;
+        let mut transformed_start = 0;
+        for run in font_runs {
+            original_end += run.len;
+            let transformed_end = self.transformed_ix(original_end);
+            run.len = transformed_end - transformed_start;
+            transformed_start = transformed_end;
+        }
+    }
+
+    /// Copy a layout of the transformed text, with the indices of its glyphs pointing back
+    /// into the original text.
+    pub(crate) fn restore_indices(&self, layout: &LineLayout) -> LineLayout {
+        LineLayout {
+            font_size: layout.font_size,
+            width: layout.width,
+            ascent: layout.ascent,
+            descent: layout.descent,
+            runs: layout
+                .runs
+                .iter()
+                .map(|run| ShapedRun {
+                    font_id: run.font_id,
+                    font_size: run.font_size,
+                    is_rtl: run.is_rtl,
+                    glyphs: run
+                        .glyphs
+                        .iter()
+                        .cloned()
+                        .map(|mut glyph| {
+                            glyph.index = self.original_ix(glyph.index);
+                            glyph
+                        })
+                        .collect(),
+                })
+                .collect(),
+            len: self.original_len,
+            ligature_carets: layout
+                .ligature_carets
+                .iter()
+                .map(|caret| self.original_ix(*caret))
+                .dedup()
+                .collect(),
+            is_rtl: layout.is_rtl,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transformed_text() {
+        assert!(TransformedText::new("hello", [(5, TextTransform::None)]).is_none());
+
+        let text = "straße café";
+        let transformed = TransformedText::new(
+            text,
+            [(7, TextTransform::Uppercase), (6, TextTransform::None)],
+        )
+        .unwrap();
+        assert_eq!(transformed.text, "STRASSE café");
+        // Both "S"es of "ß" map back to it.
+        assert_eq!(transformed.original_ix(4), 4);
+        assert_eq!(transformed.original_ix(5), 4);
+        assert_eq!(transformed.original_ix(6), 6);
+        assert_eq!(transformed.transformed_ix(6), 6);
+        assert_eq!(
+            transformed.transformed_ix(text.len()),
+            transformed.text.len()
+        );
+
+        let mut font_runs = [
+            FontRun {
+                len: 7,
+                font_id: crate::FontId(0),
+                font_size: None,
+            },
+            FontRun {
+                len: 6,
+                font_id: crate::FontId(1),
+                font_size: None,
+            },
+        ];
+        transformed.transform_font_runs(&mut font_runs);
+        assert_eq!(font_runs[0].len, "STRASSE".len());
+        assert_eq!(font_runs[1].len, " café".len());
+
+        let transformed =
+            TransformedText::new("the QUICK-brown fox", [(19, TextTransform::Capitalize)]).unwrap();
+        assert_eq!(transformed.text, "The QUICK-Brown Fox");
+
+        let transformed =
+            TransformedText::new("Mixed Case", [(5, TextTransform::Lowercase)]).unwrap();
+        assert_eq!(transformed.text, "mixed Case");
+    }
+}
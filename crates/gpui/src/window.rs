@@ -6,9 +6,9 @@ use crate::{
     KeyDownEvent, KeyMatch, KeymatchResult, Keystroke, KeystrokeEvent, Model, ModelContext,
     Modifiers, ModifiersChangedEvent, MouseButton, MouseMoveEvent, MouseUpEvent, Pixels,
     PlatformAtlas, PlatformDisplay, PlatformInput, PlatformWindow, Point, PromptLevel, Render,
-    ScaledPixels, SharedString, Size, SubscriberSet, Subscription, TaffyLayoutEngine, Task,
-    TextStyle, TextStyleRefinement, View, VisualContext, WeakView, WindowAppearance, WindowOptions,
-    WindowParams, WindowTextSystem,
+    RenderGlyphParams, ScaledPixels, SharedString, Size, SubscriberSet, Subscription,
+    TaffyLayoutEngine, Task, TextStyle, TextStyleRefinement, View, VisualContext, WeakView,
+    WindowAppearance, WindowOptions, WindowParams, WindowTextSystem, DEFAULT_REFRESH_RATE,
 };
 use anyhow::{anyhow, Context as _, Result};
 use collections::FxHashSet;
@@ -20,7 +20,7 @@ use slotmap::SlotMap;
 use smallvec::SmallVec;
 use std::{
     any::{Any, TypeId},
-    borrow::{Borrow, BorrowMut},
+    borrow::{Borrow, BorrowMut, Cow},
     cell::{Cell, RefCell},
     fmt::{Debug, Display},
     future::Future,
@@ -297,6 +297,7 @@ pub struct Window {
     mouse_hit_test: HitTest,
     modifiers: Modifiers,
     scale_factor: f32,
+    snap_glyphs_to_pixel_grid: bool,
     bounds_observers: SubscriberSet<(), AnyObserver>,
     appearance: WindowAppearance,
     appearance_observers: SubscriberSet<(), AnyObserver>,
@@ -552,6 +553,7 @@ impl Window {
             mouse_hit_test: HitTest::default(),
             modifiers,
             scale_factor,
+            snap_glyphs_to_pixel_grid: false,
             bounds_observers: SubscriberSet::new(),
             appearance,
             appearance_observers: SubscriberSet::new(),
@@ -607,6 +609,57 @@ impl ContentMask<Pixels> {
     }
 }
 
+/// A region with rounded corners that quads, underlines and strikethroughs are clipped to, so
+/// that text backgrounds and decorations don't overflow the corners of a rounded container.
+/// Glyphs are only clipped to the rectangular [`ContentMask`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RoundedContentMask {
+    /// The bounds of the region.
+    pub bounds: Bounds<Pixels>,
+    /// The radii of the region's corners.
+    pub corner_radii: Corners<Pixels>,
+}
+
+impl RoundedContentMask {
+    /// The corner radii that keep the given rectangle, which lies within the mask's bounds, from
+    /// overflowing the mask's rounded corners.
+    pub fn corner_radii_for(&self, rect: &Bounds<Pixels>) -> Corners<Pixels> {
+        let left = rect.left() - self.bounds.left();
+        let top = rect.top() - self.bounds.top();
+        let right = self.bounds.right() - rect.right();
+        let bottom = self.bounds.bottom() - rect.bottom();
+        let radius = |radius: Pixels, distance: Pixels| (radius - distance.max(px(0.))).max(px(0.));
+        Corners {
+            top_left: radius(self.corner_radii.top_left, left.max(top)),
+            top_right: radius(self.corner_radii.top_right, right.max(top)),
+            bottom_right: radius(self.corner_radii.bottom_right, right.max(bottom)),
+            bottom_left: radius(self.corner_radii.bottom_left, left.max(bottom)),
+        }
+    }
+
+    /// How far a horizontal line at `y` has to be inset from the left and right edges of the mask
+    /// to stay inside its rounded corners.
+    pub fn horizontal_insets(&self, y: Pixels) -> (Pixels, Pixels) {
+        let inset = |radius: Pixels, distance: Pixels| {
+            let distance = distance.max(px(0.));
+            if distance >= radius {
+                px(0.)
+            } else {
+                let offset = radius.0 - distance.0;
+                px(radius.0 - (radius.0 * radius.0 - offset * offset).sqrt())
+            }
+        };
+        let top = y - self.bounds.top();
+        let bottom = self.bounds.bottom() - y;
+        (
+            inset(self.corner_radii.top_left, top)
+                .max(inset(self.corner_radii.bottom_left, bottom)),
+            inset(self.corner_radii.top_right, top)
+                .max(inset(self.corner_radii.bottom_right, bottom)),
+        )
+    }
+}
+
 /// Provides access to application state in the context of a single window. Derefs
 /// to an [`AppContext`], so you can also pass a [`WindowContext`] to any method that takes
 /// an [`AppContext`] and call any [`AppContext`] methods.
@@ -924,6 +977,13 @@ impl<'a> WindowContext<'a> {
             .find(|display| display.id() == self.window.display_id)
     }
 
+    /// Get the refresh rate, in hertz, of the display on which the window is visible.
+    /// Falls back to 60 if the display is unknown or doesn't report one.
+    pub fn display_refresh_rate(&self) -> f32 {
+        self.display()
+            .map_or(DEFAULT_REFRESH_RATE, |display| display.refresh_rate())
+    }
+
     /// Show the platform character palette.
     pub fn show_character_palette(&self) {
         self.window.platform_window.show_character_palette();
@@ -936,6 +996,33 @@ impl<'a> WindowContext<'a> {
         self.window.scale_factor
     }
 
+    /// Whether glyph origins are snapped to the device pixel grid when painting text.
+    pub fn snap_glyphs_to_pixel_grid(&self) -> bool {
+        self.window.snap_glyphs_to_pixel_grid
+    }
+
+    /// Rasterizes a glyph into the window's atlas without painting it, unless it's already there.
+    pub(crate) fn rasterize_glyph(&mut self, params: &RenderGlyphParams) -> Result<()> {
+        let raster_bounds = self.text_system().raster_bounds(params)?;
+        if !raster_bounds.is_zero() {
+            self.window
+                .sprite_atlas
+                .get_or_insert_with(&params.clone().into(), &mut || {
+                    let (size, bytes) = self.text_system().rasterize_glyph(params)?;
+                    Ok((size, Cow::Owned(bytes)))
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Sets whether glyph origins are snapped to the device pixel grid when painting text.
+    /// Snapping only applies at integral scale factors, where sub-pixel glyph positions make
+    /// text look blurry. Fractional and high density scale factors keep sub-pixel positioning,
+    /// so kerning is preserved where it's visible.
+    pub fn set_snap_glyphs_to_pixel_grid(&mut self, snap: bool) {
+        self.window.snap_glyphs_to_pixel_grid = snap;
+    }
+
     /// The size of an em for the base font of the application. Adjusting this value allows the
     /// UI to scale, just like zooming a web page.
     pub fn rem_size(&self) -> Pixels {
@@ -1085,11 +1172,12 @@ impl<'a> WindowContext<'a> {
     }
 
     #[profiling::function]
-    fn present(&self) {
+    pub(crate) fn present(&self) {
         self.window
             .platform_window
             .draw(&self.window.rendered_frame.scene);
         self.window.needs_present.set(false);
+        self.app.foreground_executor().frame_presented();
         profiling::finish_frame!();
     }
 
@@ -29,17 +29,21 @@ use media::core_video::CVImageBuffer;
 use smallvec::SmallVec;
 
 use crate::{
-    prelude::*, size, AnyElement, AnyTooltip, AppContext, AvailableSpace, Bounds, BoxShadow,
-    ContentMask, Corners, CursorStyle, DevicePixels, DispatchNodeId, DispatchPhase, DispatchTree,
-    DrawPhase, ElementId, ElementStateBox, EntityId, FocusHandle, FocusId, FontId, GlobalElementId,
-    GlyphId, Hsla, ImageData, InputHandler, IsZero, KeyContext, KeyEvent, LayoutId,
-    LineLayoutIndex, ModifiersChangedEvent, MonochromeSprite, MouseEvent, PaintQuad, Path, Pixels,
-    PlatformInputHandler, Point, PolychromeSprite, Quad, RenderGlyphParams, RenderImageParams,
-    RenderSvgParams, Scene, Shadow, SharedString, Size, StrikethroughStyle, Style,
-    TextStyleRefinement, TransformationMatrix, Underline, UnderlineStyle, Window, WindowContext,
-    SUBPIXEL_VARIANTS,
+    point, prelude::*, px, size, Along, AnyElement, AnyTooltip, AppContext, AvailableSpace, Axis,
+    Bounds, BoxShadow, ContentMask, Corners, CursorStyle, DevicePixels, DispatchNodeId,
+    DispatchPhase, DispatchTree, DrawPhase, ElementId, ElementStateBox, EntityId, Fill,
+    FocusHandle, FocusId, FontId, GlobalElementId, GlyphId, Hsla, ImageData, InputHandler, IsZero,
+    KeyContext, KeyEvent, LayoutId, LineLayoutIndex, ModifiersChangedEvent, MonochromeSprite,
+    MouseEvent, PaintQuad, Path, Pixels, PlatformInputHandler, Point, PolychromeSprite, Quad,
+    RenderGlyphParams, RenderImageParams, RenderSvgParams, RoundedContentMask, ScaledPixels, Scene,
+    Shadow, SharedString, Size, StrikethroughStyle, Style, TextStyleRefinement,
+    TransformationMatrix, Underline, UnderlineStyle, Window, WindowContext, SUBPIXEL_VARIANTS,
 };
 
+/// The most bands of solid color a gradient is painted with. Gradients between 8-bit colors
+/// have at most this many distinct steps anyway.
+const MAX_GRADIENT_BANDS: usize = 256;
+
 pub(crate) type AnyMouseListener =
     Box<dyn FnMut(&dyn Any, DispatchPhase, &mut ElementContext) + 'static>;
 
@@ -105,6 +109,7 @@ pub(crate) struct Frame {
     pub(crate) hitboxes: Vec<Hitbox>,
     pub(crate) deferred_draws: Vec<DeferredDraw>,
     pub(crate) content_mask_stack: Vec<ContentMask<Pixels>>,
+    pub(crate) rounded_content_mask_stack: Vec<RoundedContentMask>,
     pub(crate) element_offset_stack: Vec<Point<Pixels>>,
     pub(crate) input_handlers: Vec<Option<PlatformInputHandler>>,
     pub(crate) tooltip_requests: Vec<Option<AnyTooltip>>,
@@ -146,6 +151,7 @@ impl Frame {
             hitboxes: Vec::new(),
             deferred_draws: Vec::new(),
             content_mask_stack: Vec::new(),
+            rounded_content_mask_stack: Vec::new(),
             element_offset_stack: Vec::new(),
             input_handlers: Vec::new(),
             tooltip_requests: Vec::new(),
@@ -634,6 +640,29 @@ impl<'a> ElementContext<'a> {
         }
     }
 
+    /// Invoke the given function with the content mask intersected with the given bounds, and with
+    /// quads, underlines and strikethroughs clipped to the bounds' rounded corners.
+    pub fn with_rounded_content_mask<R>(
+        &mut self,
+        bounds: Bounds<Pixels>,
+        corner_radii: Corners<Pixels>,
+        f: impl FnOnce(&mut Self) -> R,
+    ) -> R {
+        self.window_mut()
+            .next_frame
+            .rounded_content_mask_stack
+            .push(RoundedContentMask {
+                bounds,
+                corner_radii,
+            });
+        let result = self.with_content_mask(Some(ContentMask { bounds }), f);
+        self.window_mut()
+            .next_frame
+            .rounded_content_mask_stack
+            .pop();
+        result
+    }
+
     /// Updates the global element offset relative to the current offset. This is used to implement
     /// scrolling.
     pub fn with_element_offset<R>(
@@ -690,6 +719,23 @@ impl<'a> ElementContext<'a> {
             })
     }
 
+    /// Obtain the innermost rounded content mask, if any.
+    pub fn rounded_content_mask(&self) -> Option<&RoundedContentMask> {
+        self.window().next_frame.rounded_content_mask_stack.last()
+    }
+
+    /// Shrink a horizontal line's bounds to keep it inside the innermost rounded content mask.
+    fn inset_to_rounded_content_mask(&self, mut bounds: Bounds<Pixels>) -> Bounds<Pixels> {
+        if let Some(mask) = self.rounded_content_mask() {
+            let (left, right) = mask.horizontal_insets(bounds.center().y);
+            let min_x = bounds.left().max(mask.bounds.left() + left);
+            let max_x = bounds.right().min(mask.bounds.right() - right);
+            bounds.origin.x = min_x;
+            bounds.size.width = (max_x - min_x).max(px(0.));
+        }
+        bounds
+    }
+
     /// The size of an em for the base font of the application. Adjusting this value allows the
     /// UI to scale, just like zooming a web page.
     pub fn rem_size(&self) -> Pixels {
@@ -872,9 +918,17 @@ impl<'a> ElementContext<'a> {
     /// Paint one or more quads into the scene for the next frame at the current stacking context.
     /// Quads are colored rectangular regions with an optional background, border, and corner radius.
     /// see [`fill`](crate::fill), [`outline`](crate::outline), and [`quad`](crate::quad) to construct this type.
-    pub fn paint_quad(&mut self, quad: PaintQuad) {
+    pub fn paint_quad(&mut self, mut quad: PaintQuad) {
         let scale_factor = self.scale_factor();
         let content_mask = self.content_mask();
+        if let Some(mask) = self.rounded_content_mask() {
+            let clipped = mask.corner_radii_for(&quad.bounds);
+            let radii = &mut quad.corner_radii;
+            radii.top_left = radii.top_left.max(clipped.top_left);
+            radii.top_right = radii.top_right.max(clipped.top_right);
+            radii.bottom_right = radii.bottom_right.max(clipped.bottom_right);
+            radii.bottom_left = radii.bottom_left.max(clipped.bottom_left);
+        }
         self.window.next_frame.scene.insert_primitive(Quad {
             order: 0,
             pad: 0,
@@ -887,6 +941,37 @@ impl<'a> ElementContext<'a> {
         });
     }
 
+    /// Paint `fill` across `bounds`. Gradients are painted as bands of solid color, one per device
+    /// pixel along the gradient's axis, up to 256 bands.
+    pub fn paint_fill(&mut self, bounds: Bounds<Pixels>, fill: &Fill) {
+        let axis = match fill {
+            Fill::Color(color) => {
+                self.paint_quad(crate::fill(bounds, *color));
+                return;
+            }
+            Fill::LinearGradient { axis, .. } => *axis,
+        };
+        let length = bounds.size.along(axis);
+        let band_count =
+            ((length.0 * self.scale_factor()).ceil() as usize).clamp(1, MAX_GRADIENT_BANDS);
+        for band_ix in 0..band_count {
+            let start = length * (band_ix as f32 / band_count as f32);
+            let end = length * ((band_ix + 1) as f32 / band_count as f32);
+            let band_bounds = match axis {
+                Axis::Horizontal => Bounds::new(
+                    point(bounds.origin.x + start, bounds.origin.y),
+                    size(end - start, bounds.size.height),
+                ),
+                Axis::Vertical => Bounds::new(
+                    point(bounds.origin.x, bounds.origin.y + start),
+                    size(bounds.size.width, end - start),
+                ),
+            };
+            let color = fill.color_at((band_ix as f32 + 0.5) / band_count as f32);
+            self.paint_quad(crate::fill(band_bounds, color));
+        }
+    }
+
     /// Paint the given `Path` into the scene for the next frame at the current z-index.
     pub fn paint_path(&mut self, mut path: Path<Pixels>, color: impl Into<Hsla>) {
         let scale_factor = self.scale_factor();
@@ -900,6 +985,7 @@ impl<'a> ElementContext<'a> {
     }
 
     /// Paint an underline into the scene for the next frame at the current z-index.
+    /// Underlines are never painted thinner than one device pixel, so they stay visible.
     pub fn paint_underline(
         &mut self,
         origin: Point<Pixels>,
@@ -907,15 +993,19 @@ impl<'a> ElementContext<'a> {
         style: &UnderlineStyle,
     ) {
         let scale_factor = self.scale_factor();
+        let thickness = style.painted_thickness(scale_factor);
         let height = if style.wavy {
-            style.thickness * 3.
+            let wave_height = style
+                .wavy_amplitude
+                .map_or(px(0.), |amplitude| amplitude * 2. + thickness);
+            (thickness * 3.).max(wave_height)
         } else {
-            style.thickness
+            thickness
         };
-        let bounds = Bounds {
+        let bounds = self.inset_to_rounded_content_mask(Bounds {
             origin,
             size: size(width, height),
-        };
+        });
         let content_mask = self.content_mask();
 
         self.window.next_frame.scene.insert_primitive(Underline {
@@ -924,7 +1014,16 @@ impl<'a> ElementContext<'a> {
             bounds: bounds.scale(scale_factor),
             content_mask: content_mask.scale(scale_factor),
             color: style.color.unwrap_or_default(),
-            thickness: style.thickness.scale(scale_factor),
+            thickness: thickness.scale(scale_factor),
+            // Without an explicit shape, wavy underlines keep a fixed shape in device pixels.
+            wavy_amplitude: style
+                .wavy_amplitude
+                .map_or(ScaledPixels(1.5), |amplitude| amplitude.scale(scale_factor)),
+            wavy_wavelength: style
+                .wavy_wavelength
+                .map_or(ScaledPixels(16.), |wavelength| {
+                    wavelength.scale(scale_factor)
+                }),
             wavy: style.wavy,
         });
     }
@@ -938,10 +1037,10 @@ impl<'a> ElementContext<'a> {
     ) {
         let scale_factor = self.scale_factor();
         let height = style.thickness;
-        let bounds = Bounds {
+        let bounds = self.inset_to_rounded_content_mask(Bounds {
             origin,
             size: size(width, height),
-        };
+        });
         let content_mask = self.content_mask();
 
         self.window.next_frame.scene.insert_primitive(Underline {
@@ -951,6 +1050,8 @@ impl<'a> ElementContext<'a> {
             content_mask: content_mask.scale(scale_factor),
             thickness: style.thickness.scale(scale_factor),
             color: style.color.unwrap_or_default(),
+            wavy_amplitude: ScaledPixels(0.),
+            wavy_wavelength: ScaledPixels(0.),
             wavy: false,
         });
     }
@@ -19,6 +19,8 @@ actions!(
         SelectNext,
         SelectFirst,
         SelectLast,
+        SelectPageUp,
+        SelectPageDown,
         UseSelectedQuery,
     ]
 );
@@ -1,2 +1,2 @@
 use gpui::actions;
-actions!(storybook, [Quit]);
+actions!(storybook, [Quit, ToggleFullscreen]);
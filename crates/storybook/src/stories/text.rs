@@ -1,6 +1,6 @@
 use gpui::{
-    div, green, red, HighlightStyle, InteractiveText, IntoElement, ParentElement, Render, Styled,
-    StyledText, View, VisualContext, WindowContext,
+    blue, canvas, div, green, px, red, Axis, Fill, HighlightStyle, InteractiveText, IntoElement,
+    ParentElement, Render, SharedString, Styled, StyledText, View, VisualContext, WindowContext,
 };
 use indoc::indoc;
 use story::*;
@@ -106,6 +106,56 @@ impl Render for TextStory {
                         "##
                     })
                 )
+                .child(
+                    StoryItem::new("Wavy Underlines",
+                        div().flex().flex_col().gap_2().children([8., 14., 24.].map(|size| {
+                            div()
+                                .text_size(px(size))
+                                .text_decoration_1()
+                                .text_decoration_wavy()
+                                .text_decoration_color(red())
+                                .child(format!("Misspeled text at {size}px"))
+                        }))
+                    )
+                    .description("The amplitude and wavelength of wavy underlines scale with the font size.")
+                    .usage(indoc! {r##"
+                        div()
+                            .text_size(px(24.))
+                            .text_decoration_1()
+                            .text_decoration_wavy()
+                            .child("Misspeled text")
+                        "##
+                    })
+                )
+                .child(
+                    StoryItem::new("Gradient Background",
+                        div().w_96().h(px(32.)).child(
+                            canvas(|_, _| {}, |bounds, _, cx| {
+                                let text = SharedString::from("A header over a gradient");
+                                let run = cx.text_style().to_run(text.len());
+                                let line = cx.text_system().shape_line(text, px(24.), &[run]).unwrap();
+                                line.paint_with_background_fill(
+                                    bounds.origin,
+                                    bounds.size.height,
+                                    Some(Fill::linear_gradient(Axis::Horizontal, blue(), red())),
+                                    cx,
+                                )
+                                .unwrap();
+                            })
+                            .size_full()
+                        )
+                    )
+                    .description("The fill covers the line's bounds, behind the text and the backgrounds of its runs.")
+                    .usage(indoc! {r##"
+                        line.paint_with_background_fill(
+                            origin,
+                            line_height,
+                            Some(Fill::linear_gradient(Axis::Horizontal, blue(), red())),
+                            cx,
+                        )
+                        "##
+                    })
+                )
         ]
             ).into_element()
     }
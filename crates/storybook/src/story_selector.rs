@@ -6,6 +6,8 @@ use anyhow::anyhow;
 use clap::builder::PossibleValue;
 use clap::ValueEnum;
 use gpui::{AnyView, VisualContext};
+#[cfg(any(test, feature = "test-support"))]
+use gpui::{AvailableSpace, Point, VisualTestContext};
 use strum::{EnumIter, EnumString, IntoEnumIterator};
 use ui::prelude::*;
 
@@ -71,11 +73,45 @@ impl ComponentStory {
             Self::Picker => PickerStory::new(cx).into(),
         }
     }
+
+    /// Draws every story to the test window, laid out at [`crate::STORY_WINDOW_SIZE`], then
+    /// calls `capture` with it so that the frame can be snapshotted.
+    #[cfg(any(test, feature = "test-support"))]
+    pub fn draw_all(
+        cx: &mut VisualTestContext,
+        mut capture: impl FnMut(ComponentStory, &mut VisualTestContext),
+    ) {
+        use settings::Settings;
+        use theme::ThemeSettings;
+
+        cx.simulate_resize(crate::STORY_WINDOW_SIZE);
+        cx.update(|cx| {
+            let ui_font_size = ThemeSettings::get_global(cx).ui_font_size;
+            cx.set_rem_size(ui_font_size);
+        });
+
+        for story in Self::iter() {
+            let selector = StorySelector::Component(story);
+            cx.draw(
+                Point::default(),
+                crate::STORY_WINDOW_SIZE.map(AvailableSpace::Definite),
+                |cx| {
+                    let story = selector.story(cx);
+                    cx.new_view(|_| crate::StoryWrapper::new(story, selector.is_fullscreen()))
+                        .into_any_element()
+                },
+            );
+            cx.run_until_parked();
+            capture(story, cx);
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum StorySelector {
     Component(ComponentStory),
+    /// A single component story, centered in the window with nothing around it.
+    Fullscreen(ComponentStory),
     KitchenSink,
 }
 
@@ -91,6 +127,13 @@ impl FromStr for StorySelector {
             return Ok(Self::KitchenSink);
         }
 
+        if let Some((_, story)) = story.split_once("fullscreen/") {
+            let component_story = ComponentStory::from_str(story)
+                .with_context(|| format!("story not found for component '{story}'"))?;
+
+            return Ok(Self::Fullscreen(component_story));
+        }
+
         if let Some((_, story)) = story.split_once("components/") {
             let component_story = ComponentStory::from_str(story)
                 .with_context(|| format!("story not found for component '{story}'"))?;
@@ -105,10 +148,16 @@ impl FromStr for StorySelector {
 impl StorySelector {
     pub fn story(&self, cx: &mut WindowContext) -> AnyView {
         match self {
-            Self::Component(component_story) => component_story.story(cx),
+            Self::Component(component_story) | Self::Fullscreen(component_story) => {
+                component_story.story(cx)
+            }
             Self::KitchenSink => KitchenSinkStory::view(cx).into(),
         }
     }
+
+    pub fn is_fullscreen(&self) -> bool {
+        matches!(self, Self::Fullscreen(_))
+    }
 }
 
 /// The list of all stories available in the storybook.
@@ -118,8 +167,10 @@ impl ValueEnum for StorySelector {
     fn value_variants<'a>() -> &'a [Self] {
         let stories = ALL_STORY_SELECTORS.get_or_init(|| {
             let component_stories = ComponentStory::iter().map(StorySelector::Component);
+            let fullscreen_stories = ComponentStory::iter().map(StorySelector::Fullscreen);
 
             component_stories
+                .chain(fullscreen_stories)
                 .chain(std::iter::once(StorySelector::KitchenSink))
                 .collect::<Vec<_>>()
         });
@@ -130,6 +181,7 @@ impl ValueEnum for StorySelector {
     fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
         let value = match self {
             Self::Component(story) => format!("components/{story}"),
+            Self::Fullscreen(story) => format!("fullscreen/{story}"),
             Self::KitchenSink => "kitchen_sink".to_string(),
         };
 
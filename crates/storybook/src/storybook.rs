@@ -7,7 +7,8 @@ mod story_selector;
 use clap::Parser;
 use dialoguer::FuzzySelect;
 use gpui::{
-    div, px, size, AnyView, AppContext, Bounds, Render, ViewContext, VisualContext, WindowOptions,
+    div, px, size, AnyView, AppContext, AssetSource, Bounds, Pixels, Render, Size, ViewContext,
+    VisualContext, WindowOptions,
 };
 use log::LevelFilter;
 use settings::{default_settings, KeymapFile, Settings, SettingsStore};
@@ -19,9 +20,13 @@ use ui::prelude::*;
 use crate::app_menus::app_menus;
 use crate::assets::Assets;
 use crate::story_selector::{ComponentStory, StorySelector};
-use actions::Quit;
+use actions::{Quit, ToggleFullscreen};
 pub use indoc::indoc;
 
+/// The size of the storybook window, which stories are also laid out at when they're drawn
+/// without one.
+pub const STORY_WINDOW_SIZE: Size<Pixels> = size(px(1500.), px(780.));
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
@@ -61,31 +66,14 @@ fn main() {
     let theme_name = args.theme.unwrap_or("One Dark".to_string());
 
     gpui::App::new().with_assets(Assets).run(move |cx| {
-        load_embedded_fonts(cx).unwrap();
-
-        let mut store = SettingsStore::default();
-        store
-            .set_default_settings(default_settings().as_ref(), cx)
-            .unwrap();
-        cx.set_global(store);
-
-        theme::init(theme::LoadThemes::All(Box::new(Assets)), cx);
+        init_stories(&theme_name, cx);
 
         let selector = story_selector;
 
-        let theme_registry = ThemeRegistry::global(cx);
-        let mut theme_settings = ThemeSettings::get_global(cx).clone();
-        theme_settings.active_theme = theme_registry.get(&theme_name).unwrap();
-        ThemeSettings::override_global(theme_settings, cx);
-
-        language::init(cx);
-        editor::init(cx);
-        init(cx);
         load_storybook_keymap(cx);
         cx.set_menus(app_menus());
 
-        let size = size(px(1500.), px(780.));
-        let bounds = Bounds::centered(None, size, cx);
+        let bounds = Bounds::centered(None, STORY_WINDOW_SIZE, cx);
         let _window = cx.open_window(
             WindowOptions {
                 bounds: Some(bounds),
@@ -95,7 +83,7 @@ fn main() {
                 let ui_font_size = ThemeSettings::get_global(cx).ui_font_size;
                 cx.set_rem_size(ui_font_size);
 
-                cx.new_view(|cx| StoryWrapper::new(selector.story(cx)))
+                cx.new_view(|cx| StoryWrapper::new(selector.story(cx), selector.is_fullscreen()))
             },
         );
 
@@ -106,31 +94,62 @@ fn main() {
 #[derive(Clone)]
 pub struct StoryWrapper {
     story: AnyView,
+    /// Whether the story is centered in the window, with nothing around it.
+    fullscreen: bool,
 }
 
 impl StoryWrapper {
-    pub(crate) fn new(story: AnyView) -> Self {
-        Self { story }
+    pub(crate) fn new(story: AnyView, fullscreen: bool) -> Self {
+        Self { story, fullscreen }
+    }
+
+    fn toggle_fullscreen(&mut self, _: &ToggleFullscreen, cx: &mut ViewContext<Self>) {
+        self.fullscreen = !self.fullscreen;
+        cx.notify();
     }
 }
 
 impl Render for StoryWrapper {
-    fn render(&mut self, _cx: &mut ViewContext<Self>) -> impl IntoElement {
+    fn render(&mut self, cx: &mut ViewContext<Self>) -> impl IntoElement {
         div()
             .flex()
             .flex_col()
             .size_full()
             .font("Zed Mono")
+            .on_action(cx.listener(Self::toggle_fullscreen))
+            .when(self.fullscreen, |this| this.items_center().justify_center())
             .child(self.story.clone())
     }
 }
 
+/// Sets up the fonts, settings, and theme that the stories rely on, with the given theme active.
+fn init_stories(theme_name: &str, cx: &mut AppContext) {
+    load_embedded_fonts(cx).unwrap();
+
+    let mut store = SettingsStore::default();
+    store
+        .set_default_settings(default_settings().as_ref(), cx)
+        .unwrap();
+    cx.set_global(store);
+
+    theme::init(theme::LoadThemes::All(Box::new(Assets)), cx);
+
+    let theme_registry = ThemeRegistry::global(cx);
+    let mut theme_settings = ThemeSettings::get_global(cx).clone();
+    theme_settings.active_theme = theme_registry.get(theme_name).unwrap();
+    ThemeSettings::override_global(theme_settings, cx);
+
+    language::init(cx);
+    editor::init(cx);
+    init(cx);
+}
+
 fn load_embedded_fonts(cx: &AppContext) -> gpui::Result<()> {
-    let font_paths = cx.asset_source().list("fonts")?;
+    let font_paths = Assets.list("fonts")?;
     let mut embedded_fonts = Vec::new();
     for font_path in font_paths {
         if font_path.ends_with(".ttf") {
-            let font_bytes = cx.asset_source().load(&font_path)?;
+            let font_bytes = Assets.load(&font_path)?;
             embedded_fonts.push(font_bytes);
         }
     }
@@ -153,3 +172,19 @@ fn quit(_: &Quit, cx: &mut AppContext) {
     })
     .detach_and_log_err(cx);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gpui::TestAppContext;
+
+    #[gpui::test]
+    fn test_draw_all_stories(cx: &mut TestAppContext) {
+        cx.update(|cx| init_stories("One Dark", cx));
+        let cx = cx.add_empty_window();
+
+        let mut drawn_stories = Vec::new();
+        ComponentStory::draw_all(cx, |story, _| drawn_stories.push(story));
+        assert_eq!(drawn_stories, ComponentStory::iter().collect::<Vec<_>>());
+    }
+}
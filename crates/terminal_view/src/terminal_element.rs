@@ -347,7 +347,11 @@ impl TerminalElement {
         .then(|| UnderlineStyle {
             color: Some(fg),
             thickness: Pixels::from(1.0),
+            relative_thickness: None,
             wavy: flags.contains(Flags::UNDERCURL),
+            wavy_amplitude: None,
+            wavy_wavelength: None,
+            skip_descenders: false,
         });
 
         let strikethrough = flags
@@ -372,7 +376,6 @@ impl TerminalElement {
         let mut result = TextRun {
             len: indexed.c.len_utf8(),
             color: fg,
-            background_color: None,
             font: Font {
                 weight,
                 style,
@@ -380,6 +383,7 @@ impl TerminalElement {
             },
             underline,
             strikethrough,
+            ..Default::default()
         };
 
         if let Some((style, range)) = hyperlink {
@@ -595,8 +599,12 @@ impl Element for TerminalElement {
                     background_color: None,
                     underline: Some(UnderlineStyle {
                         thickness: px(1.0),
+                        relative_thickness: None,
                         color: Some(theme.colors().link_text_hover),
                         wavy: false,
+                        wavy_amplitude: None,
+                        wavy_wavelength: None,
+                        skip_descenders: false,
                     }),
                     strikethrough: None,
                     fade_out: None,
@@ -722,9 +730,7 @@ impl Element for TerminalElement {
                                     len,
                                     font: text_style.font(),
                                     color: theme.colors().terminal_background,
-                                    background_color: None,
-                                    underline: Default::default(),
-                                    strikethrough: None,
+                                    ..Default::default()
                                 }],
                             )
                             .unwrap()
@@ -1,26 +1,138 @@
 use crate::{
-    h_flex, prelude::*, v_flex, Icon, IconName, KeyBinding, Label, List, ListItem, ListSeparator,
-    ListSubHeader,
+    h_flex, prelude::*, v_flex, Checkbox, Icon, IconName, KeyBinding, Label, LabelLike, List,
+    ListItem, ListSeparator, ListSubHeader, Selection, Tooltip,
 };
 use gpui::{
-    px, Action, AnyElement, AppContext, DismissEvent, EventEmitter, FocusHandle, FocusableView,
-    IntoElement, Render, Subscription, View, VisualContext,
+    percentage, px, transparent_black, Action, Animation, AnimationExt, AnyElement, AppContext,
+    DismissEvent, Empty, EntityId, EventEmitter, FocusHandle, FocusableView, HighlightStyle,
+    IntoElement, KeyDownEvent, KeyUpEvent, Keystroke, MouseButton, MouseDownEvent, MouseUpEvent,
+    NoAction, Render, ScrollHandle, StyledText, Subscription, Task, Transformation, UnderlineStyle,
+    View, VisualContext,
 };
-use menu::{SelectFirst, SelectLast, SelectNext, SelectPrev};
-use std::{rc::Rc, time::Duration};
+use menu::{SelectFirst, SelectLast, SelectNext, SelectPageDown, SelectPageUp, SelectPrev};
+use std::{cmp::Reverse, mem, ops::Range, rc::Rc, time::Duration};
+
+/// How often a menu with timestamped entries re-renders them while it is open.
+const TIMESTAMP_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A button at the end of a header, such as "Clear" on a group of recent items.
+struct HeaderAction {
+    label: SharedString,
+    icon: Option<IconName>,
+    handler: Rc<dyn Fn(&mut WindowContext)>,
+    dismiss: bool,
+}
+
+/// A group of entries added with [`ContextMenu::pinnable_entries`]. Its pinned entries come
+/// first, followed by a [`ContextMenuItem::PinnedSeparator`] and the unpinned entries.
+struct PinnedSection {
+    on_pin_change: Rc<dyn Fn(&SharedString, bool, &mut WindowContext)>,
+}
+
+/// Identifies an entry added with [`ContextMenu::pinnable_entries`] by its section and its
+/// position among the entries it was added with, neither of which change as it moves around the
+/// menu.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct PinnedEntryId {
+    section: usize,
+    index: usize,
+}
+
+/// The states of a group of toggles added with [`ContextMenu::toggle_group`].
+struct ToggleGroup {
+    states: Vec<bool>,
+    on_change: Rc<dyn Fn(usize, bool, &mut WindowContext)>,
+}
+
+impl ToggleGroup {
+    /// The state of the group's header checkbox: checked when every toggle is on, and
+    /// indeterminate when only some are.
+    fn selection(&self) -> Selection {
+        let any_on = self.states.iter().any(|on| *on);
+        let all_on = self.states.iter().all(|on| *on);
+        match (any_on, all_on) {
+            (true, true) => Selection::Selected,
+            (true, false) => Selection::Indeterminate,
+            (false, _) => Selection::Unselected,
+        }
+    }
+}
+
+/// A row pinned below the entries, such as "Manage Accounts…".
+struct Footer {
+    label: SharedString,
+    handler: Rc<dyn Fn(&mut WindowContext)>,
+}
 
 enum ContextMenuItem {
     Separator,
-    Header(SharedString),
+    /// The separator between the pinned and unpinned entries of a pinned section.
+    PinnedSeparator {
+        section: usize,
+    },
+    Header {
+        title: SharedString,
+        action: Option<HeaderAction>,
+    },
+    /// A placeholder row shown while the menu's entries are still being fetched.
+    Loading,
+    /// The header of a toggle group, with a checkbox that turns all of its toggles on or off.
+    ToggleGroupHeader {
+        title: SharedString,
+        group: usize,
+    },
+    /// A checkbox entry in a toggle group, which keeps the menu open when clicked.
+    Toggle {
+        label: SharedString,
+        group: usize,
+        index: usize,
+    },
     Entry {
         label: SharedString,
         icon: Option<IconName>,
+        /// The color of `icon`, or `None` to tint it like the label.
+        icon_color: Option<Color>,
         handler: Rc<dyn Fn(&mut WindowContext)>,
         action: Option<Box<dyn Action>>,
+        /// A trailing icon button, which keeps the menu open when clicked.
+        action_button: Option<(
+            IconName,
+            Rc<dyn Fn(&mut ContextMenu, &mut ViewContext<ContextMenu>)>,
+        )>,
+        /// Byte index into `label` of the access key, which is underlined and activates the
+        /// entry when typed.
+        mnemonic: Option<usize>,
+        /// A keystroke that runs the entry while the menu is focused, shown in place of the
+        /// action's key binding.
+        shortcut: Option<gpui::KeyBinding>,
+        /// Decides whether the entry can be used, each time the menu is opened or rendered.
+        enabled_if: Option<Rc<dyn Fn(&AppContext) -> bool>>,
+        /// Whether `enabled_if` last returned false. Disabled entries are greyed out and are
+        /// skipped when navigating the menu with the keyboard.
+        disabled: bool,
+        /// Why the entry is disabled, shown in a tooltip while a disabled entry is hovered.
+        disabled_reason: Option<SharedString>,
+        /// Formats a relative time, like "2 min ago", shown at the end of the entry. It's called
+        /// on every render, and the menu re-renders periodically while it has such entries.
+        timestamp: Option<Rc<dyn Fn() -> SharedString>>,
+        /// Emits a [`ValueSelected`] event from the menu when the entry is run.
+        value: Option<Rc<dyn Fn(&mut ViewContext<ContextMenu>)>>,
+        /// How many levels the entry is indented by, for showing a tree such as a document
+        /// outline as a flat list.
+        indent: usize,
+        /// How long the entry has to be held down before it starts running repeatedly, and how
+        /// often it runs after that. Such entries run when pressed rather than clicked, and keep
+        /// the menu open.
+        repeat_on_hold: Option<(Duration, Duration)>,
+        /// Set for the entries of a pinned section, which are pinned while they're above the
+        /// section's separator.
+        pin_id: Option<PinnedEntryId>,
     },
     CustomEntry {
         entry_render: Box<dyn Fn(&mut WindowContext) -> AnyElement>,
-        handler: Rc<dyn Fn(&mut WindowContext)>,
+        /// Custom entries without a handler are display-only, and are skipped when navigating
+        /// the menu with the keyboard.
+        handler: Option<Rc<dyn Fn(&mut WindowContext)>>,
     },
 }
 
@@ -31,7 +143,35 @@ pub struct ContextMenu {
     selected_index: Option<usize>,
     delayed: bool,
     clicked: bool,
+    /// Whether the menu was dismissed after running an entry, so that losing focus as it closes
+    /// doesn't report the selection as reverted.
+    dismissed: bool,
+    on_entry_used: Option<Rc<dyn Fn(&SharedString, &mut WindowContext)>>,
+    on_selection_change: Option<Rc<dyn Fn(Option<usize>, &mut WindowContext)>>,
+    on_reorder: Option<Rc<dyn Fn(usize, usize, &mut WindowContext)>>,
+    show_focus_ring: bool,
+    footer: Option<Footer>,
+    pinned_sections: Vec<PinnedSection>,
+    toggle_groups: Vec<ToggleGroup>,
+    max_visible_entries: Option<usize>,
+    /// Whether a frame was requested to measure the rows for [`Self::max_visible_entries`]. It's
+    /// only requested once until the rows are measured, so rows that can't be measured don't
+    /// keep re-rendering the menu.
+    rows_measurement_requested: bool,
+    /// The entry that [`Self::scroll_to_entry`] scrolls to once the rows are measured.
+    pending_scroll_to_entry: Option<usize>,
+    /// How many entries are laid out side by side, in a grid, rather than one per row.
+    columns: usize,
+    scroll_handle: ScrollHandle,
+    /// The text typed into the search field, when the menu has one.
+    search_query: Option<String>,
+    /// Which items are hidden because they don't match `search_query`.
+    filtered_out: Vec<bool>,
+    /// Runs the entry that's being held down again on a timer, until it's released.
+    hold_repeat: Option<Task<()>>,
+    _timestamp_refresh: Option<Task<()>>,
     _on_blur_subscription: Subscription,
+    _on_window_deactivation_subscription: Subscription,
 }
 
 impl FocusableView for ContextMenu {
@@ -42,6 +182,13 @@ impl FocusableView for ContextMenu {
 
 impl EventEmitter<DismissEvent> for ContextMenu {}
 
+/// Emitted by a [`ContextMenu`] when an entry added with [`ContextMenu::value_entry`] is run,
+/// carrying the entry's value. It's emitted before the menu is dismissed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValueSelected<T>(pub T);
+
+impl<T: 'static> EventEmitter<ValueSelected<T>> for ContextMenu {}
+
 impl FluentBuilder for ContextMenu {}
 
 impl ContextMenu {
@@ -54,8 +201,16 @@ impl ContextMenu {
             let _on_blur_subscription = cx.on_blur(&focus_handle, |this: &mut ContextMenu, cx| {
                 this.cancel(&menu::Cancel, cx)
             });
+            // Close the menu when another app is activated, so it doesn't linger over it or
+            // show up stale when the window is activated again.
+            let _on_window_deactivation_subscription =
+                cx.observe_window_activation(|this: &mut ContextMenu, cx| {
+                    if !cx.is_window_active() {
+                        this.cancel(&menu::Cancel, cx)
+                    }
+                });
             cx.refresh();
-            f(
+            let mut menu = f(
                 Self {
                     items: Default::default(),
                     focus_handle,
@@ -63,10 +218,70 @@ impl ContextMenu {
                     selected_index: None,
                     delayed: false,
                     clicked: false,
+                    dismissed: false,
+                    on_entry_used: None,
+                    on_selection_change: None,
+                    on_reorder: None,
+                    show_focus_ring: false,
+                    footer: None,
+                    pinned_sections: Vec::new(),
+                    toggle_groups: Vec::new(),
+                    max_visible_entries: None,
+                    rows_measurement_requested: false,
+                    pending_scroll_to_entry: None,
+                    columns: 1,
+                    scroll_handle: ScrollHandle::new(),
+                    search_query: None,
+                    filtered_out: Vec::new(),
+                    hold_repeat: None,
+                    _timestamp_refresh: None,
                     _on_blur_subscription,
+                    _on_window_deactivation_subscription,
                 },
                 cx,
-            )
+            );
+            menu.update_enabled_entries(cx);
+            if menu
+                .selected_index
+                .and_then(|ix| menu.items.get(ix))
+                .is_some_and(|item| !item.is_selectable())
+            {
+                menu.selected_index = None;
+            }
+            if menu.items.iter().any(|item| {
+                matches!(
+                    item,
+                    ContextMenuItem::Entry {
+                        timestamp: Some(_),
+                        ..
+                    }
+                )
+            }) {
+                // A single timer refreshes every timestamp, and it stops once the menu is dropped.
+                menu._timestamp_refresh = Some(cx.spawn(|this, mut cx| async move {
+                    loop {
+                        cx.background_executor()
+                            .timer(TIMESTAMP_REFRESH_INTERVAL)
+                            .await;
+                        if this.update(&mut cx, |_, cx| cx.notify()).is_err() {
+                            break;
+                        }
+                    }
+                }));
+            }
+            menu
+        })
+    }
+
+    /// Builds a menu with an entry for each named action, showing the action's key binding.
+    pub fn from_actions(
+        cx: &mut WindowContext,
+        actions: Vec<(SharedString, Box<dyn Action>)>,
+    ) -> View<Self> {
+        Self::build(cx, |menu, _| {
+            actions
+                .into_iter()
+                .fold(menu, |menu, (label, action)| menu.action(label, action))
         })
     }
 
@@ -76,7 +291,32 @@ impl ContextMenu {
     }
 
     pub fn header(mut self, title: impl Into<SharedString>) -> Self {
-        self.items.push(ContextMenuItem::Header(title.into()));
+        self.items.push(ContextMenuItem::Header {
+            title: title.into(),
+            action: None,
+        });
+        self
+    }
+
+    /// Appends a header with a button at its end. Clicking the button runs `handler`, and then
+    /// dismisses the menu if `dismiss` is true.
+    pub fn header_with_action(
+        mut self,
+        title: impl Into<SharedString>,
+        label: impl Into<SharedString>,
+        icon: Option<IconName>,
+        dismiss: bool,
+        handler: impl Fn(&mut WindowContext) + 'static,
+    ) -> Self {
+        self.items.push(ContextMenuItem::Header {
+            title: title.into(),
+            action: Some(HeaderAction {
+                label: label.into(),
+                icon,
+                handler: Rc::new(handler),
+                dismiss,
+            }),
+        });
         self
     }
 
@@ -85,274 +325,2430 @@ impl ContextMenu {
         self
     }
 
+    /// Appends a row with a spinner, for menus whose entries are still loading.
+    /// The row can't be selected or confirmed.
+    pub fn loading(mut self) -> Self {
+        self.items.push(ContextMenuItem::Loading);
+        self
+    }
+
+    /// Appends an entry. A `&` in the label marks the following character as the entry's access
+    /// key, as in `"&Save"`; use `&&` for a literal ampersand.
     pub fn entry(
         mut self,
         label: impl Into<SharedString>,
         action: Option<Box<dyn Action>>,
         handler: impl Fn(&mut WindowContext) + 'static,
     ) -> Self {
-        self.items.push(ContextMenuItem::Entry {
-            label: label.into(),
-            handler: Rc::new(handler),
-            icon: None,
-            action,
-        });
+        self.items
+            .push(ContextMenuItem::entry(label, Rc::new(handler)));
+        if let Some(ContextMenuItem::Entry {
+            action: entry_action,
+            ..
+        }) = self.items.last_mut()
+        {
+            *entry_action = action;
+        }
         self
     }
 
-    pub fn custom_entry(
+    /// Appends an entry indented by `indent` levels, so that consecutive entries can show a tree,
+    /// like the symbols of a document outline. Keyboard navigation still moves through the entries
+    /// in order, regardless of their depth.
+    pub fn entry_indented(
         mut self,
-        entry_render: impl Fn(&mut WindowContext) -> AnyElement + 'static,
+        label: impl Into<SharedString>,
+        indent: usize,
         handler: impl Fn(&mut WindowContext) + 'static,
     ) -> Self {
-        self.items.push(ContextMenuItem::CustomEntry {
-            entry_render: Box::new(entry_render),
-            handler: Rc::new(handler),
-        });
+        self.items
+            .push(ContextMenuItem::entry(label, Rc::new(handler)));
+        if let Some(ContextMenuItem::Entry {
+            indent: entry_indent,
+            ..
+        }) = self.items.last_mut()
+        {
+            *entry_indent = indent;
+        }
         self
     }
 
-    pub fn action(mut self, label: impl Into<SharedString>, action: Box<dyn Action>) -> Self {
-        self.items.push(ContextMenuItem::Entry {
-            label: label.into(),
-            action: Some(action.boxed_clone()),
-            handler: Rc::new(move |cx| cx.dispatch_action(action.boxed_clone())),
-            icon: None,
-        });
+    /// Appends an entry with an icon after its label. When `icon_color` is `None` the icon is
+    /// tinted like the label, so that it follows the entry's state; otherwise it keeps the
+    /// given color, as file type icons and logos should.
+    pub fn entry_with_icon(
+        mut self,
+        label: impl Into<SharedString>,
+        icon: IconName,
+        icon_color: Option<Color>,
+        handler: impl Fn(&mut WindowContext) + 'static,
+    ) -> Self {
+        self.items
+            .push(ContextMenuItem::entry(label, Rc::new(handler)));
+        if let Some(ContextMenuItem::Entry {
+            icon: entry_icon,
+            icon_color: entry_icon_color,
+            ..
+        }) = self.items.last_mut()
+        {
+            *entry_icon = Some(icon);
+            *entry_icon_color = icon_color;
+        }
         self
     }
 
-    pub fn link(mut self, label: impl Into<SharedString>, action: Box<dyn Action>) -> Self {
-        self.items.push(ContextMenuItem::Entry {
-            label: label.into(),
-            action: Some(action.boxed_clone()),
-            handler: Rc::new(move |cx| cx.dispatch_action(action.boxed_clone())),
-            icon: Some(IconName::Link),
+    /// Appends an entry that also runs when `keystroke`, such as `"cmd-backspace"`, is pressed
+    /// while the menu is focused. The keystroke is shown at the end of the entry. Keystrokes
+    /// that are bound in the keymap run their bound action instead.
+    pub fn entry_with_shortcut(
+        mut self,
+        label: impl Into<SharedString>,
+        keystroke: &str,
+        handler: impl Fn(&mut WindowContext) + 'static,
+    ) -> Self {
+        self.items
+            .push(ContextMenuItem::entry(label, Rc::new(handler)));
+        if let Some(ContextMenuItem::Entry { shortcut, .. }) = self.items.last_mut() {
+            *shortcut = Some(gpui::KeyBinding::new(keystroke, NoAction, None));
+        }
+        self
+    }
+
+    /// Appends an entry that shows the time returned by `format_timestamp`, such as "2 min ago",
+    /// after its label. The time is formatted again every little while as long as the menu
+    /// stays open, so that it doesn't go stale.
+    pub fn entry_with_timestamp(
+        mut self,
+        label: impl Into<SharedString>,
+        format_timestamp: impl Fn() -> SharedString + 'static,
+        handler: impl Fn(&mut WindowContext) + 'static,
+    ) -> Self {
+        self.items
+            .push(ContextMenuItem::entry(label, Rc::new(handler)));
+        if let Some(ContextMenuItem::Entry { timestamp, .. }) = self.items.last_mut() {
+            *timestamp = Some(Rc::new(format_timestamp));
+        }
+        self
+    }
+
+    /// Appends an entry for picking `value`, for menus that choose between values. Running the
+    /// entry emits a [`ValueSelected`] event with the value, so the parent can subscribe to the
+    /// menu instead of giving each entry its own handler.
+    pub fn value_entry<T: Clone + 'static>(
+        mut self,
+        label: impl Into<SharedString>,
+        value: T,
+    ) -> Self {
+        self.items
+            .push(ContextMenuItem::entry(label, Rc::new(|_| {})));
+        if let Some(ContextMenuItem::Entry {
+            value: entry_value, ..
+        }) = self.items.last_mut()
+        {
+            *entry_value = Some(Rc::new(move |cx| cx.emit(ValueSelected(value.clone()))));
+        }
+        self
+    }
+
+    /// Appends an entry that can only be used while `predicate` holds for the given view. The
+    /// predicate is checked whenever the menu is opened or rendered, so the entry follows the
+    /// view's state without rebuilding the menu.
+    pub fn entry_enabled_if<V: 'static>(
+        mut self,
+        label: impl Into<SharedString>,
+        view: &View<V>,
+        predicate: impl Fn(&V) -> bool + 'static,
+        on_click: impl Fn(&mut WindowContext) + 'static,
+    ) -> Self {
+        let view = view.downgrade();
+        self.items
+            .push(ContextMenuItem::entry(label, Rc::new(on_click)));
+        if let Some(ContextMenuItem::Entry { enabled_if, .. }) = self.items.last_mut() {
+            *enabled_if = Some(Rc::new(move |cx: &AppContext| {
+                view.upgrade()
+                    .map_or(false, |view| predicate(view.read(cx)))
+            }));
+        }
+        self
+    }
+
+    /// Like [`Self::entry_enabled_if`], but while the entry is disabled, hovering it shows
+    /// `reason` in a tooltip, such as "Save the file first".
+    pub fn entry_enabled_if_with_reason<V: 'static>(
+        mut self,
+        label: impl Into<SharedString>,
+        view: &View<V>,
+        predicate: impl Fn(&V) -> bool + 'static,
+        reason: impl Into<SharedString>,
+        on_click: impl Fn(&mut WindowContext) + 'static,
+    ) -> Self {
+        self = self.entry_enabled_if(label, view, predicate, on_click);
+        if let Some(ContextMenuItem::Entry {
+            disabled_reason, ..
+        }) = self.items.last_mut()
+        {
+            *disabled_reason = Some(reason.into());
+        }
+        self
+    }
+
+    /// Makes the entry added last run again and again while Enter or the mouse button is held
+    /// down on it: once when it's pressed, again after `initial_delay`, and then every `interval`
+    /// until it's released. The menu stays open, so that entries like "Increase Font Size" can
+    /// be used several times in a row.
+    pub fn repeat_on_hold(mut self, initial_delay: Duration, interval: Duration) -> Self {
+        if let Some(ContextMenuItem::Entry { repeat_on_hold, .. }) = self.items.last_mut() {
+            *repeat_on_hold = Some((initial_delay, interval));
+        }
+        self
+    }
+
+    /// Appends an entry that can't be used, greyed out, with `reason` shown in a tooltip when
+    /// it's hovered.
+    pub fn disabled_entry(
+        mut self,
+        label: impl Into<SharedString>,
+        reason: impl Into<SharedString>,
+    ) -> Self {
+        self.items
+            .push(ContextMenuItem::entry(label, Rc::new(|_| {})));
+        if let Some(ContextMenuItem::Entry {
+            disabled,
+            disabled_reason,
+            ..
+        }) = self.items.last_mut()
+        {
+            *disabled = true;
+            *disabled_reason = Some(reason.into());
+        }
+        self
+    }
+
+    /// Appends an entry with a trailing icon button. Clicking the row runs `on_click` and
+    /// dismisses the menu, while clicking the button runs `on_secondary` and keeps it open.
+    pub fn entry_with_action_button(
+        mut self,
+        label: impl Into<SharedString>,
+        on_click: impl Fn(&mut WindowContext) + 'static,
+        icon: IconName,
+        on_secondary: impl Fn(&mut WindowContext) + 'static,
+    ) -> Self {
+        self.items
+            .push(ContextMenuItem::entry(label, Rc::new(on_click)));
+        if let Some(ContextMenuItem::Entry { action_button, .. }) = self.items.last_mut() {
+            *action_button = Some((icon, Rc::new(move |_, cx| on_secondary(cx))));
+        }
+        self
+    }
+
+    /// Appends an entry for each label and handler pair, preserving their order.
+    pub fn entries<L: Into<SharedString>>(
+        mut self,
+        entries: impl IntoIterator<Item = (L, Rc<dyn Fn(&mut WindowContext)>)>,
+    ) -> Self {
+        for (label, handler) in entries {
+            self.items.push(ContextMenuItem::entry(label, handler));
+        }
+        self
+    }
+
+    /// Appends a group of entries split into a pinned section, listed first, and the rest, with
+    /// a separator between them. Each entry gets a trailing pin button that moves it to the other
+    /// section without closing the menu, and calls `on_pin_change` with the entry's label and
+    /// whether it's now pinned, so that the caller can persist it.
+    pub fn pinnable_entries<L: Into<SharedString>>(
+        mut self,
+        entries: impl IntoIterator<Item = (L, bool, Rc<dyn Fn(&mut WindowContext)>)>,
+        on_pin_change: impl Fn(&SharedString, bool, &mut WindowContext) + 'static,
+    ) -> Self {
+        let section = self.pinned_sections.len();
+        let pinnable_entry = |(index, (label, pinned, handler)): (usize, (L, bool, _))| {
+            let id = PinnedEntryId { section, index };
+            let mut entry = ContextMenuItem::entry(label, handler);
+            if let ContextMenuItem::Entry {
+                action_button,
+                pin_id,
+                ..
+            } = &mut entry
+            {
+                *action_button = Some((
+                    pin_button_icon(pinned),
+                    Rc::new(
+                        move |menu: &mut ContextMenu, cx: &mut ViewContext<ContextMenu>| {
+                            menu.toggle_pinned(id, cx)
+                        },
+                    ),
+                ));
+                *pin_id = Some(id);
+            }
+            (pinned, entry)
+        };
+        let (pinned, unpinned): (Vec<_>, Vec<_>) = entries
+            .into_iter()
+            .enumerate()
+            .map(pinnable_entry)
+            .partition(|(pinned, _)| *pinned);
+        self.items
+            .extend(pinned.into_iter().map(|(_, entry)| entry));
+        self.items
+            .push(ContextMenuItem::PinnedSeparator { section });
+        self.items
+            .extend(unpinned.into_iter().map(|(_, entry)| entry));
+        self.pinned_sections.push(PinnedSection {
+            on_pin_change: Rc::new(on_pin_change),
         });
         self
     }
 
-    pub fn confirm(&mut self, _: &menu::Confirm, cx: &mut ViewContext<Self>) {
-        match self.selected_index.and_then(|ix| self.items.get(ix)) {
-            Some(
-                ContextMenuItem::Entry { handler, .. }
-                | ContextMenuItem::CustomEntry { handler, .. },
-            ) => (handler)(cx),
-            _ => {}
+    /// Moves the entry with the given id to the other side of its pinned section's separator,
+    /// keeping the selection on the same item. The entry is found by its id rather than by where
+    /// it was added, since sorting or reordering the menu can move it.
+    fn toggle_pinned(&mut self, id: PinnedEntryId, cx: &mut ViewContext<Self>) {
+        let ix = self.items.iter().position(|item| {
+            matches!(item, ContextMenuItem::Entry { pin_id: Some(pin_id), .. } if *pin_id == id)
+        });
+        let separator_ix = self.items.iter().position(|item| {
+            matches!(item, ContextMenuItem::PinnedSeparator { section } if *section == id.section)
+        });
+        let (Some(ix), Some(separator_ix)) = (ix, separator_ix) else {
+            return;
+        };
+
+        let pinned = ix > separator_ix;
+        let mut item = self.items.remove(ix);
+        let ContextMenuItem::Entry {
+            label,
+            action_button,
+            ..
+        } = &mut item
+        else {
+            return;
+        };
+        if let Some((icon, _)) = action_button {
+            *icon = pin_button_icon(pinned);
         }
+        let label = label.clone();
+        // Pinned entries go to the end of the pinned ones, and unpinned entries to the top of the
+        // rest, so that they stay next to the separator. Either way the entry takes the
+        // separator's index: a pinned entry pushes the separator down, and removing an unpinned
+        // entry from above it moved the separator up.
+        let new_ix = separator_ix;
+        self.items.insert(new_ix, item);
 
-        cx.emit(DismissEvent);
+        self.selected_index = self
+            .selected_index
+            .map(|selected| index_after_move(selected, ix, new_ix));
+        if self.search_query.is_some() {
+            self.filter_entries();
+        }
+        let on_pin_change = self.pinned_sections[id.section].on_pin_change.clone();
+        on_pin_change(&label, pinned, cx);
+        cx.notify();
     }
 
-    pub fn cancel(&mut self, _: &menu::Cancel, cx: &mut ViewContext<Self>) {
-        cx.emit(DismissEvent);
-        cx.emit(DismissEvent);
+    /// Appends a header with a checkbox, followed by a checkbox entry for each label, starting in
+    /// the given states. Clicking an entry flips it, while clicking the header turns every entry
+    /// on, or off when they're all on already. The menu stays open either way, and `on_change`
+    /// is called with the index and new state of each entry that changed.
+    pub fn toggle_group<L: Into<SharedString>>(
+        mut self,
+        title: impl Into<SharedString>,
+        toggles: impl IntoIterator<Item = (L, bool)>,
+        on_change: impl Fn(usize, bool, &mut WindowContext) + 'static,
+    ) -> Self {
+        let group = self.toggle_groups.len();
+        self.items.push(ContextMenuItem::ToggleGroupHeader {
+            title: title.into(),
+            group,
+        });
+        let mut states = Vec::new();
+        for (index, (label, on)) in toggles.into_iter().enumerate() {
+            self.items.push(ContextMenuItem::Toggle {
+                label: label.into(),
+                group,
+                index,
+            });
+            states.push(on);
+        }
+        self.toggle_groups.push(ToggleGroup {
+            states,
+            on_change: Rc::new(on_change),
+        });
+        self
     }
 
-    fn select_first(&mut self, _: &SelectFirst, cx: &mut ViewContext<Self>) {
-        self.selected_index = self.items.iter().position(|item| item.is_selectable());
+    /// Flips the toggle at `ix`, or every toggle in the group if it's a group's header. Returns
+    /// false if the item isn't part of a toggle group.
+    fn activate_toggle(&mut self, ix: usize, cx: &mut ViewContext<Self>) -> bool {
+        let (group_ix, toggle_ix) = match self.items.get(ix) {
+            Some(ContextMenuItem::Toggle { group, index, .. }) => (*group, Some(*index)),
+            Some(ContextMenuItem::ToggleGroupHeader { group, .. }) => (*group, None),
+            _ => return false,
+        };
+        let group = &mut self.toggle_groups[group_ix];
+        let changes = match toggle_ix {
+            Some(toggle_ix) => vec![(toggle_ix, !group.states[toggle_ix])],
+            None => {
+                let on = group.selection() != Selection::Selected;
+                (0..group.states.len())
+                    .filter(|toggle_ix| group.states[*toggle_ix] != on)
+                    .map(|toggle_ix| (toggle_ix, on))
+                    .collect()
+            }
+        };
+        for (toggle_ix, on) in &changes {
+            group.states[*toggle_ix] = *on;
+        }
+        let on_change = group.on_change.clone();
+        for (toggle_ix, on) in changes {
+            on_change(toggle_ix, on, cx);
+        }
         cx.notify();
+        true
     }
 
-    pub fn select_last(&mut self) -> Option<usize> {
-        for (ix, item) in self.items.iter().enumerate().rev() {
-            if item.is_selectable() {
-                self.selected_index = Some(ix);
-                return Some(ix);
+    /// Appends a group of entries where at most one is selected, marked with a check.
+    /// Clicking the selected entry clears the selection, calling `on_select` with `None`.
+    pub fn radio_group_optional<L: Into<SharedString>, T: PartialEq + Clone + 'static>(
+        mut self,
+        options: impl IntoIterator<Item = (L, T)>,
+        selected: Option<T>,
+        on_select: impl Fn(Option<T>, &mut WindowContext) + 'static,
+    ) -> Self {
+        let on_select = Rc::new(on_select);
+        for (label, value) in options {
+            let is_selected = selected.as_ref() == Some(&value);
+            let on_select = on_select.clone();
+            self.items.push(ContextMenuItem::entry(
+                label,
+                Rc::new(move |cx| {
+                    on_select((!is_selected).then(|| value.clone()), cx);
+                }),
+            ));
+            if let Some(ContextMenuItem::Entry { icon, .. }) = self.items.last_mut() {
+                *icon = is_selected.then_some(IconName::Check);
             }
         }
-        None
+        self
     }
 
-    fn handle_select_last(&mut self, _: &SelectLast, cx: &mut ViewContext<Self>) {
-        if self.select_last().is_some() {
-            cx.notify();
+    /// Appends the items added by `f`, with their entries ordered by `score`, highest first. The
+    /// score usually comes from a usage map kept by the caller, counting how recently or often
+    /// each entry was picked; see [`Self::on_entry_used`]. Items other than entries, like headers,
+    /// keep their positions, and each run of entries between them is sorted on its own, so that
+    /// entries stay in their groups.
+    pub fn sorted_by_usage(
+        self,
+        score: impl Fn(&SharedString) -> usize,
+        f: impl FnOnce(Self) -> Self,
+    ) -> Self {
+        let start = self.items.len();
+        let mut menu = f(self);
+        let is_entry = |item: &ContextMenuItem| matches!(item, ContextMenuItem::Entry { .. });
+        let mut run_start = start;
+        while run_start < menu.items.len() {
+            let run_end = (run_start..menu.items.len())
+                .find(|ix| !is_entry(&menu.items[*ix]))
+                .unwrap_or(menu.items.len());
+            menu.items[run_start..run_end].sort_by_cached_key(|entry| match entry {
+                ContextMenuItem::Entry { label, .. } => Reverse(score(label)),
+                _ => Reverse(0),
+            });
+            run_start = run_end + 1;
         }
+        menu
     }
 
-    fn select_next(&mut self, _: &SelectNext, cx: &mut ViewContext<Self>) {
-        if let Some(ix) = self.selected_index {
-            for (ix, item) in self.items.iter().enumerate().skip(ix + 1) {
-                if item.is_selectable() {
-                    self.selected_index = Some(ix);
-                    cx.notify();
-                    break;
-                }
+    /// Calls `callback` with the label of every entry that gets run, so that the caller can
+    /// record its usage for [`Self::sorted_by_usage`].
+    pub fn on_entry_used(
+        mut self,
+        callback: impl Fn(&SharedString, &mut WindowContext) + 'static,
+    ) -> Self {
+        self.on_entry_used = Some(Rc::new(callback));
+        self
+    }
+
+    /// Calls `callback` with the index of the selected item whenever the selection moves, with
+    /// the keyboard or by hovering an entry, before any entry is run. It's called with `None`
+    /// when the menu is dismissed without running an entry, so that a preview of the selected
+    /// entry, such as of a theme, can be reverted.
+    pub fn on_selection_change(
+        mut self,
+        callback: impl Fn(Option<usize>, &mut WindowContext) + 'static,
+    ) -> Self {
+        self.on_selection_change = Some(Rc::new(callback));
+        self
+    }
+
+    /// Lets entries be dragged to new places among the entries around them, up to the nearest
+    /// header or separator, with a line showing where the dragged entry would land. The menu
+    /// stays open while dragging, and `on_reorder` is called with the indices of the item the
+    /// entry moved from and to when it's dropped.
+    pub fn reorderable(
+        mut self,
+        on_reorder: impl Fn(usize, usize, &mut WindowContext) + 'static,
+    ) -> Self {
+        self.on_reorder = Some(Rc::new(on_reorder));
+        self
+    }
+
+    /// Moves the entry at `from` to `to` after it's dropped there, keeping the selection on the
+    /// same item.
+    fn move_entry(&mut self, from: usize, to: usize, cx: &mut ViewContext<Self>) {
+        if from == to {
+            return;
+        }
+
+        let item = self.items.remove(from);
+        self.items.insert(to, item);
+        self.selected_index = self
+            .selected_index
+            .map(|selected| index_after_move(selected, from, to));
+        if self.search_query.is_some() {
+            self.filter_entries();
+        }
+        if let Some(on_reorder) = self.on_reorder.clone() {
+            on_reorder(from, to, cx);
+        }
+        cx.notify();
+    }
+
+    /// Reports the selection to [`Self::on_selection_change`] if it moved away from `previous`.
+    fn selection_changed(&self, previous: Option<usize>, cx: &mut WindowContext) {
+        if let Some(callback) = self.on_selection_change.clone() {
+            if self.selected_index != previous {
+                callback(self.selected_index, cx);
             }
-        } else {
-            self.select_first(&Default::default(), cx);
         }
     }
 
-    pub fn select_prev(&mut self, _: &SelectPrev, cx: &mut ViewContext<Self>) {
-        if let Some(ix) = self.selected_index {
-            for (ix, item) in self.items.iter().enumerate().take(ix).rev() {
-                if item.is_selectable() {
-                    self.selected_index = Some(ix);
-                    cx.notify();
+    /// Selects the entry at `ix` when it's hovered, as if it was moved to with the keyboard.
+    fn select_hovered(&mut self, ix: usize, cx: &mut ViewContext<Self>) {
+        let previous = self.selected_index;
+        self.selected_index = Some(ix);
+        self.selection_changed(previous, cx);
+        cx.notify();
+    }
+
+    /// Runs the entry at `ix` and starts running it on a timer, if it repeats while held down.
+    /// Returns false for entries that don't.
+    fn start_hold_repeat(&mut self, ix: usize, cx: &mut ViewContext<Self>) -> bool {
+        let Some(ContextMenuItem::Entry {
+            handler,
+            repeat_on_hold: Some((initial_delay, interval)),
+            disabled: false,
+            ..
+        }) = self.items.get(ix)
+        else {
+            return false;
+        };
+        // The key repeat of a held Enter confirms again, but the timer is already running.
+        if self.hold_repeat.is_some() {
+            return true;
+        }
+        let (handler, initial_delay, interval) = (handler.clone(), *initial_delay, *interval);
+        handler(cx);
+        self.entry_used(ix, cx);
+        self.hold_repeat = Some(cx.spawn(|this, mut cx| async move {
+            let mut delay = initial_delay;
+            loop {
+                cx.background_executor().timer(delay).await;
+                delay = interval;
+                if this.update(&mut cx, |_, cx| handler(cx)).is_err() {
                     break;
                 }
             }
-        } else {
-            self.handle_select_last(&Default::default(), cx);
-        }
+        }));
+        true
     }
 
-    pub fn on_action_dispatch(&mut self, dispatched: &Box<dyn Action>, cx: &mut ViewContext<Self>) {
-        if self.clicked {
-            cx.propagate();
-            return;
-        }
+    fn stop_hold_repeat(&mut self) {
+        self.hold_repeat.take();
+    }
 
-        if let Some(ix) = self.items.iter().position(|item| {
+    fn update_enabled_entries(&mut self, cx: &AppContext) {
+        for item in &mut self.items {
             if let ContextMenuItem::Entry {
-                action: Some(action),
+                enabled_if: Some(enabled_if),
+                disabled,
                 ..
             } = item
             {
-                action.partial_eq(&**dispatched)
-            } else {
-                false
+                *disabled = !enabled_if(cx);
             }
-        }) {
-            self.selected_index = Some(ix);
-            self.delayed = true;
-            cx.notify();
-            let action = dispatched.boxed_clone();
-            cx.spawn(|this, mut cx| async move {
-                cx.background_executor()
-                    .timer(Duration::from_millis(50))
-                    .await;
-                this.update(&mut cx, |this, cx| {
-                    this.cancel(&menu::Cancel, cx);
-                    cx.dispatch_action(action);
-                })
-            })
-            .detach_and_log_err(cx);
-        } else {
-            cx.propagate()
         }
     }
-}
 
-impl ContextMenuItem {
-    fn is_selectable(&self) -> bool {
-        matches!(self, Self::Entry { .. } | Self::CustomEntry { .. })
+    fn entry_used(&self, ix: usize, cx: &mut ViewContext<Self>) {
+        let Some(ContextMenuItem::Entry { label, value, .. }) = self.items.get(ix) else {
+            return;
+        };
+        if let Some(value) = value {
+            value(cx);
+        }
+        if let Some(callback) = &self.on_entry_used {
+            callback(label, cx);
+        }
     }
-}
 
-impl Render for ContextMenu {
+    pub fn custom_entry(
+        self,
+        entry_render: impl Fn(&mut WindowContext) -> AnyElement + 'static,
+        handler: impl Fn(&mut WindowContext) + 'static,
+    ) -> Self {
+        self.custom(entry_render, Some(Rc::new(handler)))
+    }
+
+    /// Appends an arbitrary element, such as a grid of color swatches. When `on_click` is given
+    /// the element behaves like an entry, otherwise it is only displayed.
+    pub fn custom(
+        mut self,
+        entry_render: impl Fn(&mut WindowContext) -> AnyElement + 'static,
+        on_click: Option<Rc<dyn Fn(&mut WindowContext)>>,
+    ) -> Self {
+        self.items.push(ContextMenuItem::CustomEntry {
+            entry_render: Box::new(entry_render),
+            handler: on_click,
+        });
+        self
+    }
+
+    pub fn action(mut self, label: impl Into<SharedString>, action: Box<dyn Action>) -> Self {
+        let entry_action = action.boxed_clone();
+        self.items.push(ContextMenuItem::entry(
+            label,
+            Rc::new(move |cx| cx.dispatch_action(action.boxed_clone())),
+        ));
+        if let Some(ContextMenuItem::Entry { action, .. }) = self.items.last_mut() {
+            *action = Some(entry_action);
+        }
+        self
+    }
+
+    pub fn link(mut self, label: impl Into<SharedString>, action: Box<dyn Action>) -> Self {
+        self = self.action(label, action);
+        if let Some(ContextMenuItem::Entry { icon, .. }) = self.items.last_mut() {
+            *icon = Some(IconName::Link);
+        }
+        self
+    }
+
+    /// Selects the item at `ix` when the menu opens, such as the entry for the current value,
+    /// and scrolls it into view. Indices count every item added so far, and are ignored unless
+    /// they refer to an item that can be selected.
+    pub fn default_selection(mut self, ix: usize) -> Self {
+        if self.items.get(ix).is_some_and(|item| item.is_selectable()) {
+            self.selected_index = Some(ix);
+            self.pending_scroll_to_entry = Some(ix);
+        }
+        self
+    }
+
+    /// Pins a row below the entries, separated from them, that runs `on_click` and dismisses
+    /// the menu when clicked. The footer isn't part of the keyboard navigation order.
+    pub fn footer(
+        mut self,
+        label: impl Into<SharedString>,
+        on_click: impl Fn(&mut WindowContext) + 'static,
+    ) -> Self {
+        self.footer = Some(Footer {
+            label: label.into(),
+            handler: Rc::new(on_click),
+        });
+        self
+    }
+
+    /// Limits the menu's height to fit `max_entries` entries, scrolling through the rest. Headers
+    /// and separators between those entries are shown too, without counting towards the limit.
+    /// Page up and page down move the selection by `max_entries` entries.
+    /// The height is measured from the rendered rows, so it adapts to their content.
+    pub fn max_visible_entries(mut self, max_entries: usize) -> Self {
+        self.max_visible_entries = Some(max_entries.max(1));
+        self
+    }
+
+    /// Lays out each run of consecutive entries as a grid `columns` entries wide, for pickers
+    /// like a grid of emoji. Headers and separators still take up a whole row, starting a new
+    /// grid after them. The arrow keys move the selection to the neighboring entry in the grid.
+    pub fn columns(mut self, columns: usize) -> Self {
+        self.columns = columns.max(1);
+        self
+    }
+
+    /// Pins a search field above the entries. Typing while the menu is open filters the entries
+    /// to those whose labels contain the typed characters in order, hiding the headers of groups
+    /// left without entries, and the down arrow moves from the field into the filtered entries.
+    /// Access keys are disabled, since the keys they'd use go to the field.
+    pub fn searchable(mut self) -> Self {
+        self.search_query = Some(String::new());
+        self
+    }
+
+    /// Outlines the selected entry with the theme's focus color, in addition to its
+    /// background, so that keyboard selection stands out more.
+    pub fn focus_ring(mut self, show_focus_ring: bool) -> Self {
+        self.show_focus_ring = show_focus_ring;
+        self
+    }
+
+    /// Selects the first entry whose label matches `predicate` when the menu opens, skipping
+    /// disabled entries, and scrolls it into view.
+    pub fn select_entry_matching(mut self, predicate: impl Fn(&str) -> bool) -> Self {
+        if let Some(ix) = self.items.iter().position(|item| match item {
+            ContextMenuItem::Entry { label, .. } => item.is_selectable() && predicate(label),
+            _ => false,
+        }) {
+            self.selected_index = Some(ix);
+            self.pending_scroll_to_entry = Some(ix);
+        }
+        self
+    }
+
+    /// Runs the selected entry and dismisses the menu. Holding alt keeps the menu open, so that
+    /// several entries can be run in a row.
+    pub fn confirm(&mut self, _: &menu::Confirm, cx: &mut ViewContext<Self>) {
+        if let Some(ix) = self.selected_index {
+            if self.activate_toggle(ix, cx) {
+                return;
+            }
+        }
+        if self
+            .selected_index
+            .and_then(|ix| self.items.get(ix))
+            .is_some_and(|item| item.is_disabled())
+        {
+            return;
+        }
+        if let Some(ix) = self.selected_index {
+            if self.start_hold_repeat(ix, cx) {
+                return;
+            }
+        }
+        match self.selected_index.and_then(|ix| self.items.get(ix)) {
+            Some(
+                ContextMenuItem::Entry { handler, .. }
+                | ContextMenuItem::CustomEntry {
+                    handler: Some(handler),
+                    ..
+                },
+            ) => (handler)(cx),
+            _ => {}
+        }
+        if let Some(ix) = self.selected_index {
+            self.entry_used(ix, cx);
+        }
+
+        if cx.modifiers().alt {
+            cx.notify();
+        } else {
+            self.dismiss(cx);
+        }
+    }
+
+    pub fn cancel(&mut self, _: &menu::Cancel, cx: &mut ViewContext<Self>) {
+        self._timestamp_refresh.take();
+        self.stop_hold_repeat();
+        if !self.dismissed {
+            if let (Some(callback), Some(_)) =
+                (self.on_selection_change.clone(), self.selected_index)
+            {
+                callback(None, cx);
+            }
+        }
+        cx.emit(DismissEvent);
+        cx.emit(DismissEvent);
+    }
+
+    /// Dismisses the menu after an entry was run. Unlike [`Self::cancel`], this keeps what the
+    /// entry chose instead of calling [`Self::on_selection_change`] with `None` to revert it.
+    fn dismiss(&mut self, cx: &mut ViewContext<Self>) {
+        self._timestamp_refresh.take();
+        self.stop_hold_repeat();
+        self.dismissed = true;
+        cx.emit(DismissEvent);
+    }
+
+    /// Runs the entry whose shortcut matches the pressed keystroke.
+    fn handle_shortcut(&mut self, event: &KeyDownEvent, cx: &mut ViewContext<Self>) {
+        let keystroke = Keystroke {
+            ime_key: None,
+            ..event.keystroke.clone()
+        };
+        let entry = self
+            .items
+            .iter()
+            .enumerate()
+            .find_map(|(ix, item)| match item {
+                ContextMenuItem::Entry {
+                    handler,
+                    shortcut: Some(shortcut),
+                    disabled: false,
+                    ..
+                } if shortcut.keystrokes() == [keystroke.clone()] => Some((ix, handler.clone())),
+                _ => None,
+            });
+        if let Some((ix, handler)) = entry {
+            cx.stop_propagation();
+            handler(cx);
+            self.entry_used(ix, cx);
+            self.dismiss(cx);
+        }
+    }
+
+    /// Edits the search query with the typed character or backspace.
+    fn handle_search_input(&mut self, event: &KeyDownEvent, cx: &mut ViewContext<Self>) {
+        let Some(query) = self.search_query.as_mut() else {
+            return;
+        };
+        let keystroke = &event.keystroke;
+        let modifiers = keystroke.modifiers;
+        if modifiers.control || modifiers.command || modifiers.function {
+            return;
+        }
+        if keystroke.key == "backspace" {
+            if query.pop().is_none() {
+                return;
+            }
+        } else {
+            let text: &str = match keystroke.ime_key.as_deref() {
+                Some(text) => text,
+                None if keystroke.key.chars().count() == 1 => &keystroke.key,
+                None => return,
+            };
+            if text.chars().any(char::is_control) {
+                return;
+            }
+            query.push_str(text);
+        }
+
+        cx.stop_propagation();
+        self.filter_entries();
+        let previous = self.selected_index;
+        self.selected_index = (0..self.items.len()).find(|ix| self.can_select(*ix));
+        if let Some(ix) = self.selected_index {
+            self.scroll_handle.scroll_to_item(ix);
+        }
+        self.selection_changed(previous, cx);
+        cx.notify();
+    }
+
+    /// Hides the entries that don't match the search query, along with the headers of groups
+    /// that no longer have any entries.
+    fn filter_entries(&mut self) {
+        let query = self.search_query.as_deref().unwrap_or_default();
+        self.filtered_out = self
+            .items
+            .iter()
+            .map(|item| match item {
+                ContextMenuItem::Entry { label, .. } => !matches_search_query(label, query),
+                ContextMenuItem::Toggle { label, .. } => !matches_search_query(label, query),
+                ContextMenuItem::CustomEntry { .. } => !query.is_empty(),
+                _ => false,
+            })
+            .collect();
+        if query.is_empty() {
+            return;
+        }
+
+        let mut group_has_entries = false;
+        for (ix, item) in self.items.iter().enumerate().rev() {
+            match item {
+                ContextMenuItem::Header { .. } | ContextMenuItem::ToggleGroupHeader { .. } => {
+                    self.filtered_out[ix] = !group_has_entries;
+                    group_has_entries = false;
+                }
+                ContextMenuItem::Entry { .. }
+                | ContextMenuItem::CustomEntry { .. }
+                | ContextMenuItem::Toggle { .. } => {
+                    group_has_entries |= !self.filtered_out[ix];
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn is_filtered_out(&self, ix: usize) -> bool {
+        self.filtered_out.get(ix).copied().unwrap_or(false)
+    }
+
+    fn can_select(&self, ix: usize) -> bool {
+        !self.is_filtered_out(ix) && self.items.get(ix).is_some_and(|item| item.is_selectable())
+    }
+
+    /// Runs the first entry whose access key matches the typed character.
+    fn handle_mnemonic(&mut self, event: &KeyDownEvent, cx: &mut ViewContext<Self>) {
+        if self.search_query.is_some() {
+            return;
+        }
+        let modifiers = event.keystroke.modifiers;
+        if modifiers.control || modifiers.alt || modifiers.command || modifiers.function {
+            return;
+        }
+        let mut key = event.keystroke.key.chars();
+        let (Some(key), None) = (key.next(), key.next()) else {
+            return;
+        };
+
+        let entry = self
+            .items
+            .iter()
+            .enumerate()
+            .find_map(|(ix, item)| match item {
+                ContextMenuItem::Entry {
+                    label,
+                    handler,
+                    mnemonic: Some(mnemonic),
+                    disabled: false,
+                    ..
+                } => label[*mnemonic..]
+                    .chars()
+                    .next()
+                    .filter(|ch| ch.to_lowercase().eq(key.to_lowercase()))
+                    .map(|_| (ix, handler.clone())),
+                _ => None,
+            });
+        if let Some((ix, handler)) = entry {
+            cx.stop_propagation();
+            handler(cx);
+            self.entry_used(ix, cx);
+            self.dismiss(cx);
+        }
+    }
+
+    fn select_first(&mut self, _: &SelectFirst, cx: &mut ViewContext<Self>) {
+        let previous = self.selected_index;
+        self.selected_index = (0..self.items.len()).find(|ix| self.can_select(*ix));
+        if let Some(ix) = self.selected_index {
+            self.scroll_handle.scroll_to_item(ix);
+        }
+        self.selection_changed(previous, cx);
+        cx.notify();
+    }
+
+    pub fn select_last(&mut self) -> Option<usize> {
+        for ix in (0..self.items.len()).rev() {
+            if self.can_select(ix) {
+                self.selected_index = Some(ix);
+                self.scroll_handle.scroll_to_item(ix);
+                return Some(ix);
+            }
+        }
+        None
+    }
+
+    fn handle_select_last(&mut self, _: &SelectLast, cx: &mut ViewContext<Self>) {
+        let previous = self.selected_index;
+        if self.select_last().is_some() {
+            self.selection_changed(previous, cx);
+            cx.notify();
+        }
+    }
+
+    fn select_next(&mut self, _: &SelectNext, cx: &mut ViewContext<Self>) {
+        if let Some(mut ix) = self.selected_index {
+            if let Some((cells, pos)) = self.grid_cell(ix) {
+                // Move to the entry below, or to the last one when the row below is shorter.
+                let row = pos / self.columns;
+                let below = (1..)
+                    .map(|rows| pos + rows * self.columns)
+                    .take_while(|pos| *pos < cells.len())
+                    .chain([cells.len() - 1])
+                    .find(|pos| *pos / self.columns > row && self.can_select(cells[*pos]));
+                if let Some(below) = below {
+                    self.select_grid_cell(cells[below], cx);
+                    return;
+                }
+                ix = cells[cells.len() - 1];
+            }
+            for ix in ix + 1..self.items.len() {
+                if self.can_select(ix) {
+                    let previous = self.selected_index;
+                    self.selected_index = Some(ix);
+                    self.scroll_handle.scroll_to_item(ix);
+                    self.selection_changed(previous, cx);
+                    cx.notify();
+                    break;
+                }
+            }
+        } else {
+            self.select_first(&Default::default(), cx);
+        }
+    }
+
+    pub fn select_prev(&mut self, _: &SelectPrev, cx: &mut ViewContext<Self>) {
+        if let Some(mut ix) = self.selected_index {
+            if let Some((cells, pos)) = self.grid_cell(ix) {
+                let above = (1..=pos / self.columns)
+                    .map(|rows| pos - rows * self.columns)
+                    .find(|pos| self.can_select(cells[*pos]));
+                if let Some(above) = above {
+                    self.select_grid_cell(cells[above], cx);
+                    return;
+                }
+                ix = cells[0];
+            }
+            for ix in (0..ix).rev() {
+                if self.can_select(ix) {
+                    let previous = self.selected_index;
+                    self.selected_index = Some(ix);
+                    self.scroll_handle.scroll_to_item(ix);
+                    self.selection_changed(previous, cx);
+                    cx.notify();
+                    break;
+                }
+            }
+        } else {
+            self.handle_select_last(&Default::default(), cx);
+        }
+    }
+
+    /// Moves the selection to the previous or next entry in its grid with the left and right
+    /// arrow keys, continuing onto the row above or below at the ends of a row.
+    fn handle_grid_navigation(&mut self, event: &KeyDownEvent, cx: &mut ViewContext<Self>) {
+        if event.keystroke.modifiers.modified() {
+            return;
+        }
+        let forward = match event.keystroke.key.as_str() {
+            "left" => false,
+            "right" => true,
+            _ => return,
+        };
+        let Some((cells, pos)) = self.selected_index.and_then(|ix| self.grid_cell(ix)) else {
+            return;
+        };
+        cx.stop_propagation();
+        let target = if forward {
+            cells[pos + 1..]
+                .iter()
+                .find(|ix| self.can_select(**ix))
+                .copied()
+        } else {
+            cells[..pos]
+                .iter()
+                .rev()
+                .find(|ix| self.can_select(**ix))
+                .copied()
+        };
+        if let Some(target) = target {
+            self.select_grid_cell(target, cx);
+        }
+    }
+
+    /// The visible entries of the grid that the item at `ix` is laid out in, and the position of
+    /// the item among them, or `None` if the menu has a single column.
+    fn grid_cell(&self, ix: usize) -> Option<(Vec<usize>, usize)> {
+        if self.columns < 2 {
+            return None;
+        }
+        let section = grid_sections(&self.items)
+            .into_iter()
+            .find(|section| section.contains(&ix))?;
+        let cells = section
+            .filter(|ix| !self.is_filtered_out(*ix))
+            .collect::<Vec<_>>();
+        let pos = cells.iter().position(|cell| *cell == ix)?;
+        Some((cells, pos))
+    }
+
+    fn select_grid_cell(&mut self, ix: usize, cx: &mut ViewContext<Self>) {
+        let previous = self.selected_index;
+        self.selected_index = Some(ix);
+        self.scroll_handle.scroll_to_item(ix);
+        self.selection_changed(previous, cx);
+        cx.notify();
+    }
+
+    fn select_page_down(&mut self, _: &SelectPageDown, cx: &mut ViewContext<Self>) {
+        let Some(page_len) = self.max_visible_entries else {
+            // The menu doesn't scroll, so a page is the whole menu.
+            self.handle_select_last(&Default::default(), cx);
+            return;
+        };
+        let Some(start) = self.selected_index else {
+            self.select_first(&Default::default(), cx);
+            return;
+        };
+        let target = (start + 1..self.items.len())
+            .filter(|ix| self.can_select(*ix))
+            .take(page_len)
+            .last();
+        self.select_page_target(target, cx);
+    }
+
+    fn select_page_up(&mut self, _: &SelectPageUp, cx: &mut ViewContext<Self>) {
+        let Some(page_len) = self.max_visible_entries else {
+            self.select_first(&Default::default(), cx);
+            return;
+        };
+        let Some(start) = self.selected_index else {
+            self.handle_select_last(&Default::default(), cx);
+            return;
+        };
+        let target = (0..start)
+            .rev()
+            .filter(|ix| self.can_select(*ix))
+            .take(page_len)
+            .last();
+        self.select_page_target(target, cx);
+    }
+
+    /// Selects the entry a page away, scrolling it into view. `target` is `None` when the
+    /// selection is already at the end it moved towards.
+    fn select_page_target(&mut self, target: Option<usize>, cx: &mut ViewContext<Self>) {
+        if let Some(ix) = target {
+            let previous = self.selected_index;
+            self.selected_index = Some(ix);
+            self.scroll_handle.scroll_to_item(ix);
+            self.selection_changed(previous, cx);
+            cx.notify();
+        }
+    }
+
+    /// Scrolls the item at `ix` into view, centered in the menu when there are enough items on
+    /// either side of it, without changing the selection. Indices past the end scroll to the
+    /// last item. Does nothing unless [`Self::max_visible_entries`] makes the menu scroll.
+    pub fn scroll_to_entry(&mut self, ix: usize, cx: &mut ViewContext<Self>) {
+        let Some(last_ix) = self.items.len().checked_sub(1) else {
+            return;
+        };
+        let ix = ix.min(last_ix);
+        if self.max_visible_entries.is_none() {
+            return;
+        }
+
+        match self.max_visible_entries_height(cx) {
+            Some(height) => self.center_entry(ix, height),
+            // Either every entry fits, or the rows haven't been measured yet, in which case the
+            // entry is scrolled to once they have been.
+            None => self.pending_scroll_to_entry = Some(ix),
+        }
+        cx.notify();
+    }
+
+    /// Scrolls the item at `ix` towards the middle of a menu that's `height` tall, stopping
+    /// where the first or last item reaches the edge of the menu.
+    fn center_entry(&mut self, ix: usize, height: Pixels) {
+        let bounds = (
+            self.scroll_handle.bounds_for_item(0),
+            self.scroll_handle.bounds_for_item(ix),
+            self.scroll_handle
+                .bounds_for_item(self.items.len().saturating_sub(1)),
+        );
+        if let (Some(first), Some(item), Some(last)) = bounds {
+            let max_scroll = (last.bottom() - first.top() - height).max(px(0.));
+            let margin = ((height - item.size.height) / 2.).max(px(0.));
+            let scroll = (item.top() - first.top() - margin)
+                .min(max_scroll)
+                .max(px(0.));
+            self.scroll_handle
+                .set_logical_scroll_top(ix, item.top() - first.top() - scroll);
+        }
+    }
+
+    /// Handles a menu action dispatched outside the menu, such as by a text field that keeps
+    /// focus while the menu is open, so that the field can forward its keyboard navigation.
+    /// Returns false when the action isn't one the menu handles.
+    pub fn navigate(&mut self, action: &dyn Action, cx: &mut ViewContext<Self>) -> bool {
+        let action = action.as_any();
+        if let Some(action) = action.downcast_ref::<SelectNext>() {
+            self.select_next(action, cx);
+        } else if let Some(action) = action.downcast_ref::<SelectPrev>() {
+            self.select_prev(action, cx);
+        } else if let Some(action) = action.downcast_ref::<SelectFirst>() {
+            self.select_first(action, cx);
+        } else if let Some(action) = action.downcast_ref::<SelectLast>() {
+            self.handle_select_last(action, cx);
+        } else if let Some(action) = action.downcast_ref::<SelectPageDown>() {
+            self.select_page_down(action, cx);
+        } else if let Some(action) = action.downcast_ref::<SelectPageUp>() {
+            self.select_page_up(action, cx);
+        } else if let Some(action) = action.downcast_ref::<menu::Confirm>() {
+            self.confirm(action, cx);
+        } else if let Some(action) = action.downcast_ref::<menu::Cancel>() {
+            self.cancel(action, cx);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    pub fn on_action_dispatch(&mut self, dispatched: &Box<dyn Action>, cx: &mut ViewContext<Self>) {
+        if self.clicked {
+            cx.propagate();
+            return;
+        }
+
+        if let Some(ix) = self.items.iter().position(|item| {
+            if let ContextMenuItem::Entry {
+                action: Some(action),
+                ..
+            } = item
+            {
+                action.partial_eq(&**dispatched)
+            } else {
+                false
+            }
+        }) {
+            let previous = self.selected_index;
+            self.selected_index = Some(ix);
+            self.selection_changed(previous, cx);
+            self.delayed = true;
+            cx.notify();
+            let action = dispatched.boxed_clone();
+            cx.spawn(|this, mut cx| async move {
+                cx.background_executor()
+                    .timer(Duration::from_millis(50))
+                    .await;
+                this.update(&mut cx, |this, cx| {
+                    this.dismiss(cx);
+                    cx.dispatch_action(action);
+                    this.entry_used(ix, cx);
+                })
+            })
+            .detach_and_log_err(cx);
+        } else {
+            cx.propagate()
+        }
+    }
+}
+
+/// Strips the access key marker from `label`, returning the label to display and the byte index of
+/// the marked character within it. Only the first marker counts, and `&&` becomes a literal `&`.
+fn parse_mnemonic(label: SharedString) -> (SharedString, Option<usize>) {
+    if !label.contains('&') {
+        return (label, None);
+    }
+
+    let mut stripped = String::with_capacity(label.len());
+    let mut mnemonic = None;
+    let mut chars = label.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch == '&' {
+            match chars.peek() {
+                Some('&') => {
+                    chars.next();
+                }
+                Some(next) if mnemonic.is_none() && next.is_alphanumeric() => {
+                    mnemonic = Some(stripped.len());
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        stripped.push(ch);
+    }
+    (stripped.into(), mnemonic)
+}
+
+fn mnemonic_label(
+    label: SharedString,
+    mnemonic: usize,
+    color: Color,
+    cx: &WindowContext,
+) -> AnyElement {
+    let len = label[mnemonic..].chars().next().map_or(0, char::len_utf8);
+    let mut text_style = cx.text_style();
+    text_style.color = color.color(cx);
+    let underline = HighlightStyle {
+        underline: Some(UnderlineStyle {
+            thickness: px(1.),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    LabelLike::new()
+        .child(
+            StyledText::new(label)
+                .with_highlights(&text_style, [(mnemonic..mnemonic + len, underline)]),
+        )
+        .into_any_element()
+}
+
+/// Finds the separators that would only add an empty gap to the menu: those at its start or end,
+/// and those right after another separator or a header. Items that are filtered out don't count.
+fn redundant_separators(items: &[ContextMenuItem], filtered_out: &[bool]) -> Vec<bool> {
+    let is_filtered_out = |ix: usize| filtered_out.get(ix).copied().unwrap_or(false);
+    let mut redundant = vec![false; items.len()];
+    let mut follows_boundary = true;
+    for (ix, item) in items.iter().enumerate() {
+        if is_filtered_out(ix) {
+            continue;
+        }
+        match item {
+            ContextMenuItem::Separator | ContextMenuItem::PinnedSeparator { .. } => {
+                redundant[ix] = follows_boundary;
+                follows_boundary = true;
+            }
+            ContextMenuItem::Header { .. } | ContextMenuItem::ToggleGroupHeader { .. } => {
+                follows_boundary = true
+            }
+            _ => follows_boundary = false,
+        }
+    }
+    for (ix, item) in items.iter().enumerate().rev() {
+        if is_filtered_out(ix) {
+            continue;
+        }
+        if !matches!(
+            item,
+            ContextMenuItem::Separator | ContextMenuItem::PinnedSeparator { .. }
+        ) {
+            break;
+        }
+        redundant[ix] = true;
+    }
+    redundant
+}
+
+/// Where the item at `ix` ends up after the item at `from` is removed and inserted again at `to`.
+fn index_after_move(ix: usize, from: usize, to: usize) -> usize {
+    match ix {
+        ix if ix == from => to,
+        ix if from < ix && ix <= to => ix - 1,
+        ix if to <= ix && ix < from => ix + 1,
+        ix => ix,
+    }
+}
+
+/// The icon of the button that pins an entry, or that unpins it when it's `pinned`.
+fn pin_button_icon(pinned: bool) -> IconName {
+    if pinned {
+        IconName::Close
+    } else {
+        IconName::Pin
+    }
+}
+
+/// Renders a row of a toggle group, which flips its toggles when clicked instead of closing the
+/// menu.
+fn render_toggle(
+    ix: usize,
+    label: Label,
+    selection: Selection,
+    selected: bool,
+    show_focus_ring: bool,
+    cx: &mut ViewContext<ContextMenu>,
+) -> AnyElement {
+    let menu = cx.view().downgrade();
+    let hovered_menu = menu.clone();
+    ListItem::new(ix)
+        .inset(true)
+        .selected(selected)
+        .focused(show_focus_ring && selected)
+        .on_hover(move |hovered, cx| {
+            if *hovered {
+                hovered_menu
+                    .update(cx, |menu, cx| menu.select_hovered(ix, cx))
+                    .ok();
+            }
+        })
+        .on_click(move |_, cx| {
+            menu.update(cx, |menu, cx| menu.activate_toggle(ix, cx))
+                .ok();
+        })
+        .child(
+            h_flex()
+                .gap_2()
+                .child(Checkbox::new(("context-menu-toggle", ix), selection))
+                .child(label),
+        )
+        .into_any_element()
+}
+
+/// An entry of a [`ContextMenu::reorderable`] menu that's being dragged.
+#[derive(Clone)]
+struct DraggedMenuEntry {
+    menu_id: EntityId,
+    ix: usize,
+    /// The items the entry can be dropped on, which are the entries around it.
+    section: Range<usize>,
+    label: SharedString,
+}
+
+impl Render for DraggedMenuEntry {
     fn render(&mut self, cx: &mut ViewContext<Self>) -> impl IntoElement {
-        div().occlude().elevation_2(cx).flex().flex_row().child(
-            v_flex()
-                .min_w(px(200.))
-                .track_focus(&self.focus_handle)
-                .on_mouse_down_out(cx.listener(|this, _, cx| this.cancel(&menu::Cancel, cx)))
-                .key_context("menu")
-                .on_action(cx.listener(ContextMenu::select_first))
-                .on_action(cx.listener(ContextMenu::handle_select_last))
-                .on_action(cx.listener(ContextMenu::select_next))
-                .on_action(cx.listener(ContextMenu::select_prev))
-                .on_action(cx.listener(ContextMenu::confirm))
-                .on_action(cx.listener(ContextMenu::cancel))
-                .when(!self.delayed, |mut el| {
-                    for item in self.items.iter() {
-                        if let ContextMenuItem::Entry {
-                            action: Some(action),
-                            ..
-                        } = item
-                        {
-                            el = el.on_boxed_action(
-                                &**action,
-                                cx.listener(ContextMenu::on_action_dispatch),
-                            );
+        h_flex()
+            .elevation_2(cx)
+            .px_2()
+            .py_1()
+            .child(Label::new(self.label.clone()))
+    }
+}
+
+/// For each item, the range of consecutive entries it belongs to, which a
+/// [`ContextMenu::reorderable`] entry can be moved within.
+fn entry_sections(items: &[ContextMenuItem]) -> Vec<Range<usize>> {
+    let mut sections = vec![0..0; items.len()];
+    let mut start = 0;
+    for ix in 0..=items.len() {
+        if !matches!(items.get(ix), Some(ContextMenuItem::Entry { .. })) {
+            for section in &mut sections[start..ix] {
+                *section = start..ix;
+            }
+            start = ix + 1;
+        }
+    }
+    sections
+}
+
+/// The runs of consecutive entries, which a menu with [`ContextMenu::columns`] lays out as grids.
+fn grid_sections(items: &[ContextMenuItem]) -> Vec<Range<usize>> {
+    let mut sections = Vec::new();
+    let mut start = 0;
+    for ix in 0..=items.len() {
+        if !matches!(
+            items.get(ix),
+            Some(ContextMenuItem::Entry { .. } | ContextMenuItem::CustomEntry { .. })
+        ) {
+            if start < ix {
+                sections.push(start..ix);
+            }
+            start = ix + 1;
+        }
+    }
+    sections
+}
+
+/// Wraps the row of an entry in a [`ContextMenu::reorderable`] menu so that it can be dragged
+/// within its section, drawing a line on the side of the row that a dragged entry would land on.
+fn render_reorderable_entry(
+    ix: usize,
+    section: Range<usize>,
+    label: SharedString,
+    row: ListItem,
+    cx: &mut ViewContext<ContextMenu>,
+) -> AnyElement {
+    let menu_id = cx.view().entity_id();
+    let drop_section = section.clone();
+    let indicator_color = cx.theme().colors().border_focused;
+    div()
+        .id(("context-menu-reorderable-entry", ix))
+        // Every row keeps room for the line, so that showing it doesn't move the rows around.
+        .border_y_1()
+        .border_color(transparent_black())
+        .on_drag(
+            DraggedMenuEntry {
+                menu_id,
+                ix,
+                section,
+                label,
+            },
+            |entry, cx| cx.new_view(|_| entry.clone()),
+        )
+        .drag_over::<DraggedMenuEntry>(move |style, dragged, _| {
+            if dragged.menu_id != menu_id || dragged.section != drop_section || dragged.ix == ix {
+                return style;
+            }
+            // An entry dragged down lands below the row it's dropped on, and one dragged up
+            // lands above it.
+            let style = if dragged.ix < ix {
+                style.border_t_0().border_b_2()
+            } else {
+                style.border_t_2().border_b_0()
+            };
+            style.border_color(indicator_color)
+        })
+        .can_drop(move |dragged, _| {
+            dragged
+                .downcast_ref::<DraggedMenuEntry>()
+                .is_some_and(|dragged| dragged.menu_id == menu_id && dragged.section.contains(&ix))
+        })
+        .on_drop(cx.listener(move |menu, dragged: &DraggedMenuEntry, cx| {
+            menu.move_entry(dragged.ix, ix, cx)
+        }))
+        .child(row)
+        .into_any_element()
+}
+
+/// Runs the entry while the mouse button is held down on it, instead of when it's clicked.
+fn render_hold_repeat_entry(
+    row: AnyElement,
+    ix: usize,
+    cx: &mut ViewContext<ContextMenu>,
+) -> AnyElement {
+    div()
+        .on_mouse_down(
+            MouseButton::Left,
+            cx.listener(move |menu, _: &MouseDownEvent, cx| {
+                menu.clicked = true;
+                menu.start_hold_repeat(ix, cx);
+            }),
+        )
+        .on_mouse_up(
+            MouseButton::Left,
+            cx.listener(|menu, _: &MouseUpEvent, _| menu.stop_hold_repeat()),
+        )
+        .on_mouse_up_out(
+            MouseButton::Left,
+            cx.listener(|menu, _: &MouseUpEvent, _| menu.stop_hold_repeat()),
+        )
+        .child(row)
+        .into_any_element()
+}
+
+/// Whether `label` contains the characters of `query` in order, ignoring case.
+fn matches_search_query(label: &str, query: &str) -> bool {
+    let mut label = label.chars().flat_map(char::to_lowercase);
+    query
+        .chars()
+        .flat_map(char::to_lowercase)
+        .all(|query_char| label.any(|label_char| label_char == query_char))
+}
+
+impl ContextMenuItem {
+    /// An entry that runs `handler`, with its access key parsed out of `label` as described in
+    /// [`ContextMenu::entry`], and the defaults that the entry builders override.
+    fn entry(label: impl Into<SharedString>, handler: Rc<dyn Fn(&mut WindowContext)>) -> Self {
+        let (label, mnemonic) = parse_mnemonic(label.into());
+        Self::Entry {
+            label,
+            icon: None,
+            icon_color: None,
+            handler,
+            action: None,
+            action_button: None,
+            mnemonic,
+            shortcut: None,
+            enabled_if: None,
+            disabled: false,
+            disabled_reason: None,
+            timestamp: None,
+            value: None,
+            indent: 0,
+            repeat_on_hold: None,
+            pin_id: None,
+        }
+    }
+
+    fn is_selectable(&self) -> bool {
+        matches!(
+            self,
+            Self::Entry {
+                disabled: false,
+                ..
+            } | Self::CustomEntry {
+                handler: Some(_),
+                ..
+            } | Self::ToggleGroupHeader { .. }
+                | Self::Toggle { .. }
+        )
+    }
+
+    fn is_disabled(&self) -> bool {
+        matches!(self, Self::Entry { disabled: true, .. })
+    }
+
+    fn is_entry(&self) -> bool {
+        matches!(
+            self,
+            Self::Entry { .. } | Self::CustomEntry { .. } | Self::Toggle { .. }
+        )
+    }
+}
+
+impl ContextMenu {
+    /// The height of the rows up to and including the last entry that
+    /// [`Self::max_visible_entries`] lets through, or `None` if every entry fits. Only entries
+    /// that aren't filtered out count. The rows are measured on the frame after they're first
+    /// rendered, so the menu is capped from then on.
+    fn max_visible_entries_height(&mut self, cx: &mut ViewContext<Self>) -> Option<Pixels> {
+        let max_entries = self.max_visible_entries?;
+        let first_visible_ix = (0..self.items.len()).find(|ix| !self.is_filtered_out(*ix))?;
+        let mut visible_entries = (first_visible_ix..self.items.len())
+            .filter(|ix| self.items[*ix].is_entry() && !self.is_filtered_out(*ix));
+        let last_visible_ix = visible_entries.nth(max_entries - 1)?;
+        visible_entries.next()?;
+
+        let bounds = self
+            .scroll_handle
+            .bounds_for_item(first_visible_ix)
+            .zip(self.scroll_handle.bounds_for_item(last_visible_ix));
+        match bounds {
+            Some((first, last)) => {
+                self.rows_measurement_requested = false;
+                Some(last.bottom() - first.top())
+            }
+            None => {
+                if !mem::replace(&mut self.rows_measurement_requested, true) {
+                    cx.on_next_frame(|_, cx| cx.notify());
+                }
+                None
+            }
+        }
+    }
+}
+
+impl Render for ContextMenu {
+    fn render(&mut self, cx: &mut ViewContext<Self>) -> impl IntoElement {
+        self.update_enabled_entries(cx);
+        let redundant_separators = redundant_separators(&self.items, &self.filtered_out);
+        let filtered_out = self.filtered_out.clone();
+        let max_height = self.max_visible_entries_height(cx);
+        if let Some(height) = max_height {
+            if let Some(ix) = self.pending_scroll_to_entry.take() {
+                self.center_entry(ix, height);
+            }
+        }
+        let reorder_sections = self
+            .on_reorder
+            .is_some()
+            .then(|| entry_sections(&self.items));
+        div()
+            .occlude()
+            .elevation_2(cx)
+            // A stronger outline than the variant that other elevated surfaces use, so the menu
+            // stands out against content of a similar color behind it.
+            .border_color(cx.theme().colors().border)
+            .flex()
+            .flex_row()
+            .child(
+                v_flex()
+                    .min_w(px(200.))
+                    .track_focus(&self.focus_handle)
+                    .on_mouse_down_out(cx.listener(|this, _, cx| this.cancel(&menu::Cancel, cx)))
+                    .key_context("menu")
+                    .on_action(cx.listener(ContextMenu::select_first))
+                    .on_action(cx.listener(ContextMenu::handle_select_last))
+                    .on_action(cx.listener(ContextMenu::select_next))
+                    .on_action(cx.listener(ContextMenu::select_prev))
+                    .on_action(cx.listener(ContextMenu::select_page_down))
+                    .on_action(cx.listener(ContextMenu::select_page_up))
+                    .on_action(cx.listener(ContextMenu::confirm))
+                    .on_action(cx.listener(ContextMenu::cancel))
+                    .on_key_down(cx.listener(ContextMenu::handle_shortcut))
+                    .on_key_down(cx.listener(ContextMenu::handle_search_input))
+                    .on_key_down(cx.listener(ContextMenu::handle_mnemonic))
+                    .on_key_down(cx.listener(ContextMenu::handle_grid_navigation))
+                    .on_key_up(cx.listener(|this, event: &KeyUpEvent, _| {
+                        if event.keystroke.key == "enter" {
+                            this.stop_hold_repeat();
                         }
-                    }
-                    el
-                })
-                .flex_none()
-                .child(List::new().children(self.items.iter_mut().enumerate().map(
-                    |(ix, item)| {
-                        match item {
-                            ContextMenuItem::Separator => ListSeparator.into_any_element(),
-                            ContextMenuItem::Header(header) => ListSubHeader::new(header.clone())
-                                .inset(true)
-                                .into_any_element(),
-                            ContextMenuItem::Entry {
-                                label,
-                                handler,
-                                icon,
-                                action,
-                            } => {
-                                let handler = handler.clone();
-                                let menu = cx.view().downgrade();
-
-                                let label_element = if let Some(icon) = icon {
-                                    h_flex()
-                                        .gap_1()
-                                        .child(Label::new(label.clone()))
-                                        .child(Icon::new(*icon))
-                                        .into_any_element()
+                    }))
+                    .when(!self.delayed, |mut el| {
+                        for item in self.items.iter() {
+                            if let ContextMenuItem::Entry {
+                                action: Some(action),
+                                ..
+                            } = item
+                            {
+                                el = el.on_boxed_action(
+                                    &**action,
+                                    cx.listener(ContextMenu::on_action_dispatch),
+                                );
+                            }
+                        }
+                        el
+                    })
+                    // Fill the width the menu is given, such as by `RightClickMenu::match_trigger_width`.
+                    .flex_grow()
+                    .flex_shrink_0()
+                    .when_some(self.search_query.as_ref(), |el, query| {
+                        el.child(
+                            h_flex()
+                                .gap_2()
+                                .px_2()
+                                .py_1()
+                                .border_b_1()
+                                .border_color(cx.theme().colors().border_variant)
+                                .child(
+                                    Icon::new(IconName::MagnifyingGlass)
+                                        .size(IconSize::Small)
+                                        .color(Color::Muted),
+                                )
+                                .child(if query.is_empty() {
+                                    Label::new("Search…").color(Color::Placeholder)
                                 } else {
-                                    Label::new(label.clone()).into_any_element()
-                                };
-
-                                ListItem::new(ix)
-                                    .inset(true)
-                                    .selected(Some(ix) == self.selected_index)
-                                    .on_click(move |_, cx| {
-                                        handler(cx);
-                                        menu.update(cx, |menu, cx| {
-                                            menu.clicked = true;
-                                            cx.emit(DismissEvent);
-                                        })
-                                        .ok();
-                                    })
+                                    Label::new(query.clone())
+                                }),
+                        )
+                    })
+                    .child(List::new().map(|list| {
+                        let items = self
+                            .items
+                            .iter_mut()
+                            .enumerate()
+                            .map(|(ix, item)| match item {
+                                _ if filtered_out.get(ix).copied().unwrap_or(false) => {
+                                    Empty.into_any_element()
+                                }
+                                ContextMenuItem::Separator
+                                | ContextMenuItem::PinnedSeparator { .. }
+                                    if redundant_separators[ix] =>
+                                {
+                                    Empty.into_any_element()
+                                }
+                                ContextMenuItem::Separator
+                                | ContextMenuItem::PinnedSeparator { .. } => {
+                                    ListSeparator.into_any_element()
+                                }
+                                ContextMenuItem::Header { title, action } => {
+                                    ListSubHeader::new(title.clone())
+                                        .inset(true)
+                                        .end_slot(action.as_ref().map(|action| {
+                                            let handler = action.handler.clone();
+                                            let dismiss = action.dismiss;
+                                            let menu = cx.view().downgrade();
+                                            Button::new(
+                                                ("context-menu-header-action", ix),
+                                                action.label.clone(),
+                                            )
+                                            .icon(action.icon)
+                                            .icon_size(IconSize::Small)
+                                            .label_size(LabelSize::Small)
+                                            .on_click(
+                                                move |_, cx| {
+                                                    handler(cx);
+                                                    if dismiss {
+                                                        menu.update(cx, |menu, cx| {
+                                                            menu.dismiss(cx)
+                                                        })
+                                                        .ok();
+                                                    }
+                                                },
+                                            )
+                                        }))
+                                        .into_any_element()
+                                }
+                                ContextMenuItem::ToggleGroupHeader { title, group } => {
+                                    render_toggle(
+                                        ix,
+                                        Label::new(title.clone()).color(Color::Muted),
+                                        self.toggle_groups[*group].selection(),
+                                        Some(ix) == self.selected_index,
+                                        self.show_focus_ring,
+                                        cx,
+                                    )
+                                }
+                                ContextMenuItem::Toggle {
+                                    label,
+                                    group,
+                                    index,
+                                } => render_toggle(
+                                    ix,
+                                    Label::new(label.clone()),
+                                    if self.toggle_groups[*group].states[*index] {
+                                        Selection::Selected
+                                    } else {
+                                        Selection::Unselected
+                                    },
+                                    Some(ix) == self.selected_index,
+                                    self.show_focus_ring,
+                                    cx,
+                                ),
+                                ContextMenuItem::Loading => h_flex()
+                                    .w_full()
+                                    .py_1()
+                                    .justify_center()
                                     .child(
+                                        Icon::new(IconName::ArrowCircle)
+                                            .size(IconSize::Small)
+                                            .color(Color::Muted)
+                                            .with_animation(
+                                                "context-menu-loading",
+                                                Animation::new(Duration::from_secs(2)).repeat(),
+                                                |icon, delta| {
+                                                    icon.transform(Transformation::rotate(
+                                                        percentage(delta),
+                                                    ))
+                                                },
+                                            ),
+                                    )
+                                    .into_any_element(),
+                                ContextMenuItem::Entry {
+                                    label,
+                                    handler,
+                                    icon,
+                                    icon_color,
+                                    action,
+                                    action_button,
+                                    mnemonic,
+                                    shortcut,
+                                    enabled_if: _,
+                                    disabled,
+                                    disabled_reason,
+                                    timestamp,
+                                    value: _,
+                                    indent,
+                                    repeat_on_hold,
+                                    pin_id: _,
+                                } => {
+                                    let disabled = *disabled;
+                                    let repeats_on_hold = repeat_on_hold.is_some() && !disabled;
+                                    let handler = handler.clone();
+                                    let menu = cx.view().downgrade();
+                                    let secondary_menu = menu.clone();
+
+                                    let label_color = if disabled {
+                                        Color::Disabled
+                                    } else {
+                                        Color::Default
+                                    };
+                                    let label_text = match mnemonic {
+                                        Some(mnemonic) => mnemonic_label(
+                                            label.clone(),
+                                            *mnemonic,
+                                            label_color,
+                                            cx,
+                                        ),
+                                        None => Label::new(label.clone())
+                                            .color(label_color)
+                                            .into_any_element(),
+                                    };
+                                    let label_element = if let Some(icon) = icon {
                                         h_flex()
-                                            .w_full()
-                                            .justify_between()
-                                            .child(label_element)
-                                            .debug_selector(|| format!("MENU_ITEM-{}", label))
-                                            .children(action.as_ref().and_then(|action| {
-                                                self.action_context
-                                                    .as_ref()
-                                                    .map(|focus| {
-                                                        KeyBinding::for_action_in(
-                                                            &**action, focus, cx,
-                                                        )
+                                            .gap_1()
+                                            .child(label_text)
+                                            .child(
+                                                Icon::new(*icon)
+                                                    .color(icon_color.unwrap_or(label_color)),
+                                            )
+                                            .into_any_element()
+                                    } else {
+                                        label_text
+                                    };
+
+                                    let row = ListItem::new(ix)
+                                        .inset(true)
+                                        .indent_level(*indent)
+                                        .disabled(disabled)
+                                        .when_some(
+                                            disabled_reason.clone().filter(|_| disabled),
+                                            |item, reason| {
+                                                item.tooltip(move |cx| {
+                                                    Tooltip::text(reason.clone(), cx)
+                                                })
+                                            },
+                                        )
+                                        .selected(Some(ix) == self.selected_index)
+                                        .focused(
+                                            self.show_focus_ring && Some(ix) == self.selected_index,
+                                        )
+                                        .when(!disabled, |item| {
+                                            let hovered_menu = menu.clone();
+                                            item.on_hover(move |hovered, cx| {
+                                                if *hovered {
+                                                    hovered_menu
+                                                        .update(cx, |menu, cx| {
+                                                            menu.select_hovered(ix, cx)
+                                                        })
+                                                        .ok();
+                                                }
+                                            })
+                                            .when(
+                                                !repeats_on_hold,
+                                                |item| {
+                                                    item.on_click(move |_, cx| {
+                                                        handler(cx);
+                                                        menu.update(cx, |menu, cx| {
+                                                            menu.clicked = true;
+                                                            menu.entry_used(ix, cx);
+                                                            menu.dismiss(cx);
+                                                        })
+                                                        .ok();
                                                     })
-                                                    .unwrap_or_else(|| {
-                                                        KeyBinding::for_action(&**action, cx)
+                                                },
+                                            )
+                                        })
+                                        .child(
+                                            h_flex()
+                                                .w_full()
+                                                .justify_between()
+                                                .child(label_element)
+                                                .debug_selector(|| format!("MENU_ITEM-{}", label))
+                                                .children(timestamp.as_ref().map(|timestamp| {
+                                                    div().ml_1().child(
+                                                        Label::new(timestamp())
+                                                            .size(LabelSize::Small)
+                                                            .color(Color::Muted),
+                                                    )
+                                                }))
+                                                .children(
+                                                    shortcut
+                                                        .clone()
+                                                        .map(KeyBinding::new)
+                                                        .or_else(|| {
+                                                            let action = action.as_ref()?;
+                                                            self.action_context
+                                                                .as_ref()
+                                                                .map(|focus| {
+                                                                    KeyBinding::for_action_in(
+                                                                        &**action, focus, cx,
+                                                                    )
+                                                                })
+                                                                .unwrap_or_else(|| {
+                                                                    KeyBinding::for_action(
+                                                                        &**action, cx,
+                                                                    )
+                                                                })
+                                                        })
+                                                        .map(|binding| div().ml_1().child(binding)),
+                                                ),
+                                        )
+                                        .when_some(
+                                            action_button.as_ref(),
+                                            |item, (icon, on_secondary)| {
+                                                let on_secondary = on_secondary.clone();
+                                                item.end_slot(
+                                                    IconButton::new(
+                                                        ("context-menu-action-button", ix),
+                                                        *icon,
+                                                    )
+                                                    .icon_size(IconSize::Small)
+                                                    .on_click(move |_, cx| {
+                                                        secondary_menu
+                                                            .update(cx, |menu, cx| {
+                                                                on_secondary(menu, cx)
+                                                            })
+                                                            .ok();
+                                                    }),
+                                                )
+                                            },
+                                        );
+                                    let row = match &reorder_sections {
+                                        Some(sections) => render_reorderable_entry(
+                                            ix,
+                                            sections[ix].clone(),
+                                            label.clone(),
+                                            row,
+                                            cx,
+                                        ),
+                                        None => row.into_any_element(),
+                                    };
+                                    if repeats_on_hold {
+                                        render_hold_repeat_entry(row, ix, cx)
+                                    } else {
+                                        row
+                                    }
+                                }
+                                ContextMenuItem::CustomEntry {
+                                    entry_render,
+                                    handler: None,
+                                } => entry_render(cx),
+                                ContextMenuItem::CustomEntry {
+                                    entry_render,
+                                    handler: Some(handler),
+                                } => {
+                                    let handler = handler.clone();
+                                    let menu = cx.view().downgrade();
+                                    let hovered_menu = menu.clone();
+                                    ListItem::new(ix)
+                                        .inset(true)
+                                        .selected(Some(ix) == self.selected_index)
+                                        .focused(
+                                            self.show_focus_ring && Some(ix) == self.selected_index,
+                                        )
+                                        .on_hover(move |hovered, cx| {
+                                            if *hovered {
+                                                hovered_menu
+                                                    .update(cx, |menu, cx| {
+                                                        menu.select_hovered(ix, cx)
                                                     })
-                                                    .map(|binding| div().ml_1().child(binding))
-                                            })),
-                                    )
-                                    .into_any_element()
-                            }
-                            ContextMenuItem::CustomEntry {
-                                entry_render,
-                                handler,
-                            } => {
-                                let handler = handler.clone();
-                                let menu = cx.view().downgrade();
-                                ListItem::new(ix)
-                                    .inset(true)
-                                    .selected(Some(ix) == self.selected_index)
-                                    .on_click(move |_, cx| {
-                                        handler(cx);
-                                        menu.update(cx, |menu, cx| {
-                                            menu.clicked = true;
-                                            cx.emit(DismissEvent);
+                                                    .ok();
+                                            }
                                         })
-                                        .ok();
-                                    })
-                                    .child(entry_render(cx))
-                                    .into_any_element()
-                            }
+                                        .on_click(move |_, cx| {
+                                            handler(cx);
+                                            menu.update(cx, |menu, cx| {
+                                                menu.clicked = true;
+                                                menu.entry_used(ix, cx);
+                                                menu.dismiss(cx);
+                                            })
+                                            .ok();
+                                        })
+                                        .child(entry_render(cx))
+                                        .into_any_element()
+                                }
+                            })
+                            .collect::<Vec<_>>();
+                        if items.is_empty() {
+                            return list;
                         }
-                    },
-                ))),
-        )
+                        let columns = self.columns;
+                        let items = if columns > 1 {
+                            // The rows wrap, so that items stay direct children of the scroll
+                            // container. Hidden items are left unwrapped, so they don't take up a
+                            // cell or break a row.
+                            let sections = grid_sections(&self.items);
+                            items
+                                .into_iter()
+                                .enumerate()
+                                .map(|(ix, item)| {
+                                    if filtered_out.get(ix).copied().unwrap_or(false)
+                                        || redundant_separators[ix]
+                                    {
+                                        item
+                                    } else if sections.iter().any(|section| section.contains(&ix)) {
+                                        div()
+                                            .w(relative(1. / columns as f32))
+                                            .child(item)
+                                            .into_any_element()
+                                    } else {
+                                        div().w_full().child(item).into_any_element()
+                                    }
+                                })
+                                .collect()
+                        } else {
+                            items
+                        };
+                        // Rows are laid out as direct children of the scroll container, so that
+                        // the scroll handle can measure them and scroll the selection into view.
+                        list.child(
+                            v_flex()
+                                .id("context-menu-entries")
+                                .track_scroll(&self.scroll_handle)
+                                .when(columns > 1, |el| el.flex_row().flex_wrap())
+                                .when_some(max_height, |el, max_height| {
+                                    el.max_h(max_height).overflow_y_scroll()
+                                })
+                                .children(items),
+                        )
+                    }))
+                    .when_some(self.footer.as_ref(), |el, footer| {
+                        let handler = footer.handler.clone();
+                        let menu = cx.view().downgrade();
+                        el.child(ListSeparator).child(
+                            ListItem::new("context-menu-footer")
+                                .inset(true)
+                                .on_click(move |_, cx| {
+                                    handler(cx);
+                                    menu.update(cx, |menu, cx| {
+                                        menu.clicked = true;
+                                        menu.dismiss(cx);
+                                    })
+                                    .ok();
+                                })
+                                .child(Label::new(footer.label.clone())),
+                        )
+                    }),
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gpui::{TestAppContext, VisualTestContext};
+    use settings::SettingsStore;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_parse_mnemonic() {
+        let parse = |label: &'static str| {
+            let (label, mnemonic) = parse_mnemonic(label.into());
+            (label.to_string(), mnemonic)
+        };
+
+        assert_eq!(parse("Copy"), ("Copy".to_string(), None));
+        assert_eq!(parse("&Copy"), ("Copy".to_string(), Some(0)));
+        assert_eq!(parse("Save &As"), ("Save As".to_string(), Some(5)));
+        // Only the first marker counts.
+        assert_eq!(parse("&Open &File"), ("Open &File".to_string(), Some(0)));
+        // `&&` is a literal `&`, and doesn't shift the marked character.
+        assert_eq!(parse("R&&&D"), ("R&D".to_string(), Some(2)));
+        assert_eq!(
+            parse("Find && Replace"),
+            ("Find & Replace".to_string(), None)
+        );
+        // Only alphanumeric characters can be access keys.
+        assert_eq!(parse("Fish & Chips"), ("Fish & Chips".to_string(), None));
+        assert_eq!(parse("Trailing &"), ("Trailing &".to_string(), None));
+        assert_eq!(parse("&Éclair"), ("Éclair".to_string(), Some(0)));
+        assert_eq!(
+            parse("Crème &Brûlée"),
+            ("Crème Brûlée".to_string(), Some(7))
+        );
+    }
+
+    #[test]
+    fn test_redundant_separators() {
+        let entry = |label: &'static str| ContextMenuItem::entry(label, Rc::new(|_| {}));
+        let header = || ContextMenuItem::Header {
+            title: "Header".into(),
+            action: None,
+        };
+        let items = [
+            ContextMenuItem::Separator,
+            entry("a"),
+            ContextMenuItem::Separator,
+            ContextMenuItem::Separator,
+            header(),
+            ContextMenuItem::PinnedSeparator { section: 0 },
+            entry("b"),
+            ContextMenuItem::Separator,
+            entry("c"),
+            ContextMenuItem::Separator,
+        ];
+
+        assert_eq!(
+            redundant_separators(&items, &[]),
+            [true, false, false, true, false, true, false, false, false, true]
+        );
+
+        // Hiding the only entry between two separators makes the second one redundant, and
+        // hiding the last entry makes the separator before it trailing.
+        let mut filtered_out = vec![false; items.len()];
+        filtered_out[1] = true;
+        filtered_out[8] = true;
+        assert_eq!(
+            redundant_separators(&items, &filtered_out),
+            [true, false, true, true, false, true, false, true, false, true]
+        );
+    }
+
+    #[test]
+    fn test_matches_search_query() {
+        assert!(matches_search_query("Copy Path", ""));
+        assert!(matches_search_query("Copy Path", "copy"));
+        assert!(matches_search_query("Copy Path", "CP"));
+        assert!(matches_search_query("Copy Path", "cpypth"));
+        assert!(matches_search_query("éclair", "ÉC"));
+
+        // The characters have to appear in the same order.
+        assert!(!matches_search_query("Copy Path", "pc"));
+        assert!(!matches_search_query("Copy Path", "copyy"));
+        assert!(!matches_search_query("Copy", "Copy Path"));
+    }
+
+    #[gpui::test]
+    fn test_search_filters_entries_and_groups(cx: &mut TestAppContext) {
+        init_test(cx);
+        let cx = cx.add_empty_window();
+
+        let menu = cx.update(|cx| {
+            ContextMenu::build(cx, |menu, _| {
+                menu.searchable()
+                    .header("Edit")
+                    .entry("Copy", None, |_| {})
+                    .entry("Paste", None, |_| {})
+                    .separator()
+                    .header("View")
+                    .entry("Zoom In", None, |_| {})
+                    .custom_entry(|_| Empty.into_any_element(), |_| {})
+                    .toggle_group(
+                        "Panels",
+                        [("Outline", true), ("Terminal", false)],
+                        |_, _, _| {},
+                    )
+            })
+        });
+        let key = |key: &str| KeyDownEvent {
+            keystroke: Keystroke::parse(key).unwrap(),
+            is_held: false,
+        };
+        let hidden = |menu: &ContextMenu| {
+            (0..menu.items.len())
+                .filter(|ix| menu.is_filtered_out(*ix))
+                .collect::<Vec<_>>()
+        };
+
+        menu.update(cx, |menu, cx| {
+            menu.handle_search_input(&key("t"), cx);
+            menu.handle_search_input(&key("e"), cx);
+            assert_eq!(menu.search_query.as_deref(), Some("te"));
+            // "View" is hidden along with all of its entries, including the custom one, while
+            // the toggles are filtered like entries and keep their group's header.
+            assert_eq!(hidden(menu), &[1, 4, 5, 6]);
+            // The selection moves to the first match.
+            assert_eq!(menu.selected_index, Some(2));
+
+            menu.handle_search_input(&key("r"), cx);
+            assert_eq!(hidden(menu), &[0, 1, 2, 4, 5, 6, 8]);
+            assert_eq!(menu.selected_index, Some(7));
+
+            // Clearing the query shows everything again.
+            for _ in 0..3 {
+                menu.handle_search_input(&key("backspace"), cx);
+            }
+            assert_eq!(hidden(menu), &[] as &[usize]);
+        });
+    }
+
+    #[test]
+    fn test_index_after_move() {
+        // Moving the item at 1 down to 3 shifts the items it passed up by one.
+        let moved = (0..5)
+            .map(|ix| index_after_move(ix, 1, 3))
+            .collect::<Vec<_>>();
+        assert_eq!(moved, [0, 3, 1, 2, 4]);
+
+        // Moving the item at 3 up to 1 shifts the items it passed down by one.
+        let moved = (0..5)
+            .map(|ix| index_after_move(ix, 3, 1))
+            .collect::<Vec<_>>();
+        assert_eq!(moved, [0, 2, 3, 1, 4]);
+
+        let moved = (0..3)
+            .map(|ix| index_after_move(ix, 1, 1))
+            .collect::<Vec<_>>();
+        assert_eq!(moved, [0, 1, 2]);
+    }
+
+    #[gpui::test]
+    fn test_toggle_pinned(cx: &mut TestAppContext) {
+        init_test(cx);
+        let cx = cx.add_empty_window();
+
+        let pin_changes = Rc::new(RefCell::new(Vec::new()));
+        let handler: Rc<dyn Fn(&mut WindowContext)> = Rc::new(|_| {});
+        let menu = cx.update(|cx| {
+            let pin_changes = pin_changes.clone();
+            ContextMenu::build(cx, |menu, _| {
+                // The entries share a label, so they can only be told apart by their ids.
+                menu.pinnable_entries(
+                    [
+                        ("Same", false, handler.clone()),
+                        ("Same", true, handler.clone()),
+                        ("Other", false, handler.clone()),
+                    ],
+                    move |label, pinned, _| pin_changes.borrow_mut().push((label.clone(), pinned)),
+                )
+            })
+        });
+        // The index each entry was added at, with `None` for the pinned section's separator.
+        let pin_indices = |menu: &ContextMenu| {
+            menu.items
+                .iter()
+                .map(|item| match item {
+                    ContextMenuItem::Entry { pin_id, .. } => pin_id.map(|id| id.index),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        menu.update(cx, |menu, cx| {
+            assert_eq!(pin_indices(menu), [Some(1), None, Some(0), Some(2)]);
+            menu.selected_index = Some(2);
+
+            menu.toggle_pinned(
+                PinnedEntryId {
+                    section: 0,
+                    index: 0,
+                },
+                cx,
+            );
+            assert_eq!(pin_indices(menu), [Some(1), Some(0), None, Some(2)]);
+            assert_eq!(menu.selected_index, Some(1));
+
+            menu.toggle_pinned(
+                PinnedEntryId {
+                    section: 0,
+                    index: 1,
+                },
+                cx,
+            );
+            assert_eq!(pin_indices(menu), [Some(0), None, Some(1), Some(2)]);
+            assert_eq!(menu.selected_index, Some(0));
+        });
+        assert_eq!(
+            *pin_changes.borrow(),
+            [(SharedString::from("Same"), true), ("Same".into(), false)]
+        );
+    }
+
+    #[gpui::test]
+    fn test_sorted_by_usage_keeps_entries_in_their_groups(cx: &mut TestAppContext) {
+        init_test(cx);
+        let cx = cx.add_empty_window();
+
+        let score = |label: &SharedString| match label.as_ref() {
+            "Recent" => 5,
+            "Frequent" => 10,
+            _ => 0,
+        };
+        let menu = cx.update(|cx| {
+            ContextMenu::build(cx, |menu, _| {
+                menu.sorted_by_usage(score, |menu| {
+                    menu.header("Edit")
+                        .entry("Copy", None, |_| {})
+                        .entry("Recent", None, |_| {})
+                        .separator()
+                        .header("View")
+                        .entry("Zoom", None, |_| {})
+                        .entry("Frequent", None, |_| {})
+                })
+            })
+        });
+        let labels = menu.update(cx, |menu, _| {
+            menu.items
+                .iter()
+                .map(|item| match item {
+                    ContextMenuItem::Entry { label, .. } => label.to_string(),
+                    ContextMenuItem::Header { title, .. } => format!("# {title}"),
+                    _ => "-".to_string(),
+                })
+                .collect::<Vec<_>>()
+        });
+
+        // The most used entry overall stays under its own header.
+        assert_eq!(
+            labels,
+            &["# Edit", "Recent", "Copy", "-", "# View", "Frequent", "Zoom"]
+        );
+    }
+
+    #[test]
+    fn test_grid_sections() {
+        let entry = || ContextMenuItem::entry("Entry", Rc::new(|_| {}));
+        let items = [
+            entry(),
+            entry(),
+            ContextMenuItem::Separator,
+            ContextMenuItem::Separator,
+            entry(),
+            ContextMenuItem::Loading,
+            entry(),
+            entry(),
+            entry(),
+        ];
+        assert_eq!(grid_sections(&items), [0..2, 4..5, 6..9]);
+    }
+
+    #[gpui::test]
+    fn test_grid_navigation(cx: &mut TestAppContext) {
+        init_test(cx);
+        let cx = cx.add_empty_window();
+
+        // A header followed by a grid of seven entries, three to a row:
+        //
+        // 1 2 3
+        // 4 5 6
+        // 7
+        let menu = cx.update(|cx| {
+            ContextMenu::build(cx, |menu, _| {
+                (1..=7).fold(menu.header("Grid").columns(3), |menu, ix| {
+                    menu.entry(ix.to_string(), None, |_| {})
+                })
+            })
+        });
+        let key = |key: &str| KeyDownEvent {
+            keystroke: Keystroke::parse(key).unwrap(),
+            is_held: false,
+        };
+
+        menu.update(cx, |menu, cx| {
+            assert_eq!(menu.grid_cell(4), Some(((1..=7).collect(), 3)));
+
+            menu.selected_index = Some(1);
+            menu.select_next(&SelectNext, cx);
+            assert_eq!(menu.selected_index, Some(4));
+            menu.select_next(&SelectNext, cx);
+            assert_eq!(menu.selected_index, Some(7));
+            // There's no row below the last one.
+            menu.select_next(&SelectNext, cx);
+            assert_eq!(menu.selected_index, Some(7));
+            menu.select_prev(&SelectPrev, cx);
+            assert_eq!(menu.selected_index, Some(4));
+
+            // Moving down from a column the row below doesn't reach goes to its last entry.
+            menu.selected_index = Some(6);
+            menu.select_next(&SelectNext, cx);
+            assert_eq!(menu.selected_index, Some(7));
+
+            // Left and right continue onto the neighboring rows at the ends of a row.
+            menu.selected_index = Some(3);
+            menu.handle_grid_navigation(&key("right"), cx);
+            assert_eq!(menu.selected_index, Some(4));
+            menu.handle_grid_navigation(&key("left"), cx);
+            assert_eq!(menu.selected_index, Some(3));
+            menu.selected_index = Some(7);
+            menu.handle_grid_navigation(&key("right"), cx);
+            assert_eq!(menu.selected_index, Some(7));
+            // Modified arrow keys are left to the rest of the menu's key bindings.
+            menu.handle_grid_navigation(&key("shift-left"), cx);
+            assert_eq!(menu.selected_index, Some(7));
+        });
+    }
+
+    #[gpui::test]
+    fn test_scroll_to_entry_stays_within_the_entries(cx: &mut TestAppContext) {
+        init_test(cx);
+        let cx = cx.add_empty_window();
+
+        let menu = cx.update(|cx| {
+            ContextMenu::build(cx, |menu, _| {
+                (0..10).fold(menu.max_visible_entries(3), |menu, ix| {
+                    menu.entry(ix.to_string(), None, |_| {})
+                })
+            })
+        });
+        let draw = |cx: &mut VisualTestContext| {
+            cx.draw(
+                gpui::point(px(0.), px(0.)),
+                gpui::size(px(200.), px(1000.)).into(),
+                |_| menu.clone().into_any_element(),
+            )
+        };
+        // The rows are measured on the first frame, and the menu is capped from the next one.
+        draw(cx);
+        draw(cx);
+        let scroll_offset = |menu: &ContextMenu| {
+            (
+                menu.scroll_handle.bounds_for_item(0).unwrap().size.height,
+                menu.scroll_handle.offset().y,
+            )
+        };
+
+        // The last entry can't be centered, so it's scrolled to the bottom of the menu.
+        menu.update(cx, |menu, cx| menu.scroll_to_entry(9, cx));
+        draw(cx);
+        let (row_height, offset) = menu.update(cx, |menu, _| scroll_offset(menu));
+        assert_eq!(offset, -(row_height * 7.));
+
+        // Nor can the first, so it's scrolled back to the top rather than past it.
+        menu.update(cx, |menu, cx| menu.scroll_to_entry(0, cx));
+        draw(cx);
+        assert_eq!(menu.update(cx, |menu, _| scroll_offset(menu)).1, px(0.));
+
+        menu.update(cx, |menu, cx| menu.scroll_to_entry(5, cx));
+        draw(cx);
+        assert_eq!(
+            menu.update(cx, |menu, _| scroll_offset(menu)).1,
+            -(row_height * 4.)
+        );
+    }
+
+    #[gpui::test]
+    fn test_preselected_entry_is_scrolled_into_view(cx: &mut TestAppContext) {
+        init_test(cx);
+        let cx = cx.add_empty_window();
+
+        let menu = cx.update(|cx| {
+            ContextMenu::build(cx, |menu, _| {
+                (0..10)
+                    .fold(menu.max_visible_entries(3), |menu, ix| match ix {
+                        5 => menu.disabled_entry("Current", "Unavailable"),
+                        8 => menu.entry("Current", None, |_| {}),
+                        _ => menu.entry(ix.to_string(), None, |_| {}),
+                    })
+                    .select_entry_matching(|label| label == "Current")
+            })
+        });
+        // The disabled entry matches too, but can't be selected.
+        assert_eq!(menu.update(cx, |menu, _| menu.selected_index), Some(8));
+
+        for _ in 0..2 {
+            cx.draw(
+                gpui::point(px(0.), px(0.)),
+                gpui::size(px(200.), px(1000.)).into(),
+                |_| menu.clone().into_any_element(),
+            );
+        }
+        menu.update(cx, |menu, _| {
+            let first = menu.scroll_handle.bounds_for_item(0).unwrap();
+            let selected = menu.scroll_handle.bounds_for_item(8).unwrap();
+            let offset = menu.scroll_handle.offset().y;
+            assert!(selected.top() + offset >= first.top());
+            assert!(selected.bottom() + offset <= first.top() + first.size.height * 3.);
+        });
+    }
+
+    fn init_test(cx: &mut TestAppContext) {
+        cx.update(|cx| {
+            let settings_store = SettingsStore::test(cx);
+            cx.set_global(settings_store);
+            theme::init(theme::LoadThemes::JustBase, cx);
+        });
     }
 }
@@ -94,6 +94,7 @@ pub enum IconName {
     PageDown,
     PageUp,
     Pencil,
+    Pin,
     Play,
     Plus,
     Public,
@@ -190,6 +191,7 @@ impl IconName {
             IconName::PageDown => "icons/page_down.svg",
             IconName::PageUp => "icons/page_up.svg",
             IconName::Pencil => "icons/pencil.svg",
+            IconName::Pin => "icons/pin.svg",
             IconName::Play => "icons/play.svg",
             IconName::Plus => "icons/plus.svg",
             IconName::Public => "icons/public.svg",
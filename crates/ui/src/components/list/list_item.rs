@@ -17,6 +17,7 @@ pub struct ListItem {
     id: ElementId,
     disabled: bool,
     selected: bool,
+    focused: bool,
     spacing: ListItemSpacing,
     indent_level: usize,
     indent_step_size: Pixels,
@@ -31,6 +32,7 @@ pub struct ListItem {
     toggle: Option<bool>,
     inset: bool,
     on_click: Option<Box<dyn Fn(&ClickEvent, &mut WindowContext) + 'static>>,
+    on_hover: Option<Box<dyn Fn(&bool, &mut WindowContext) + 'static>>,
     on_toggle: Option<Arc<dyn Fn(&ClickEvent, &mut WindowContext) + 'static>>,
     tooltip: Option<Box<dyn Fn(&mut WindowContext) -> AnyView + 'static>>,
     on_secondary_mouse_down: Option<Box<dyn Fn(&MouseDownEvent, &mut WindowContext) + 'static>>,
@@ -43,6 +45,7 @@ impl ListItem {
             id: id.into(),
             disabled: false,
             selected: false,
+            focused: false,
             spacing: ListItemSpacing::Dense,
             indent_level: 0,
             indent_step_size: px(12.),
@@ -52,6 +55,7 @@ impl ListItem {
             toggle: None,
             inset: false,
             on_click: None,
+            on_hover: None,
             on_secondary_mouse_down: None,
             on_toggle: None,
             tooltip: None,
@@ -77,6 +81,12 @@ impl ListItem {
         self
     }
 
+    /// Calls `handler` with whether the item is hovered, each time the mouse enters or leaves it.
+    pub fn on_hover(mut self, handler: impl Fn(&bool, &mut WindowContext) + 'static) -> Self {
+        self.on_hover = Some(Box::new(handler));
+        self
+    }
+
     pub fn tooltip(mut self, tooltip: impl Fn(&mut WindowContext) -> AnyView + 'static) -> Self {
         self.tooltip = Some(Box::new(tooltip));
         self
@@ -120,6 +130,13 @@ impl ListItem {
         self
     }
 
+    /// Outlines the item with the theme's focus color, to point out the item that keyboard
+    /// input acts on more clearly than its selected background does.
+    pub fn focused(mut self, focused: bool) -> Self {
+        self.focused = focused;
+        self
+    }
+
     pub fn end_hover_slot<E: IntoElement>(mut self, end_hover_slot: impl Into<Option<E>>) -> Self {
         self.end_hover_slot = end_hover_slot.into().map(IntoElement::into_any_element);
         self
@@ -158,13 +175,7 @@ impl RenderOnce for ListItem {
                     .px_2()
             })
             .when(!self.inset, |this| {
-                this
-                    // TODO: Add focus state
-                    // .when(self.state == InteractionState::Focused, |this| {
-                    //     this.border()
-                    //         .border_color(cx.theme().colors().border_focused)
-                    // })
-                    .hover(|style| style.bg(cx.theme().colors().ghost_element_hover))
+                this.hover(|style| style.bg(cx.theme().colors().ghost_element_hover))
                     .active(|style| style.bg(cx.theme().colors().ghost_element_active))
                     .when(self.selected, |this| {
                         this.bg(cx.theme().colors().ghost_element_selected)
@@ -183,13 +194,7 @@ impl RenderOnce for ListItem {
                     })
                     .group("list_item")
                     .when(self.inset && !self.disabled, |this| {
-                        this
-                            // TODO: Add focus state
-                            // .when(self.state == InteractionState::Focused, |this| {
-                            //     this.border()
-                            //         .border_color(cx.theme().colors().border_focused)
-                            // })
-                            .hover(|style| style.bg(cx.theme().colors().ghost_element_hover))
+                        this.hover(|style| style.bg(cx.theme().colors().ghost_element_hover))
                             .active(|style| style.bg(cx.theme().colors().ghost_element_active))
                             .when(self.selected, |this| {
                                 this.bg(cx.theme().colors().ghost_element_selected)
@@ -203,6 +208,7 @@ impl RenderOnce for ListItem {
                             (on_mouse_down)(event, cx)
                         })
                     })
+                    .when_some(self.on_hover, |this, on_hover| this.on_hover(on_hover))
                     .when_some(self.tooltip, |this, tooltip| this.tooltip(tooltip))
                     .map(|this| {
                         if self.inset {
@@ -252,6 +258,19 @@ impl RenderOnce for ListItem {
                                 .visible_on_hover("list_item")
                                 .child(end_hover_slot),
                         )
+                    })
+                    // Draw the focus ring over the item, so that it doesn't shift the content.
+                    .when(self.focused, |this| {
+                        this.child(
+                            div()
+                                .absolute()
+                                .top_0()
+                                .left_0()
+                                .size_full()
+                                .when(self.inset, |this| this.rounded_md())
+                                .border_1()
+                                .border_color(cx.theme().colors().border_focused),
+                        )
                     }),
             )
     }
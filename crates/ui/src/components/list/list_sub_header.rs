@@ -1,10 +1,12 @@
 use crate::prelude::*;
 use crate::{h_flex, Icon, IconName, IconSize, Label};
+use gpui::AnyElement;
 
 #[derive(IntoElement)]
 pub struct ListSubHeader {
     label: SharedString,
     start_slot: Option<IconName>,
+    end_slot: Option<AnyElement>,
     inset: bool,
 }
 
@@ -13,6 +15,7 @@ impl ListSubHeader {
         Self {
             label: label.into(),
             start_slot: None,
+            end_slot: None,
             inset: false,
         }
     }
@@ -22,6 +25,11 @@ impl ListSubHeader {
         self
     }
 
+    pub fn end_slot<E: IntoElement>(mut self, end_slot: impl Into<Option<E>>) -> Self {
+        self.end_slot = end_slot.into().map(IntoElement::into_any_element);
+        self
+    }
+
     pub fn inset(mut self, inset: bool) -> Self {
         self.inset = inset;
         self
@@ -50,7 +58,8 @@ impl RenderOnce for ListSubHeader {
                                 .map(|i| Icon::new(i).color(Color::Muted).size(IconSize::Small)),
                         )
                         .child(Label::new(self.label.clone()).color(Color::Muted)),
-                ),
+                )
+                .children(self.end_slot),
         )
     }
 }
@@ -1,7 +1,10 @@
+use std::rc::Rc;
+
 use crate::prelude::*;
-use crate::v_flex;
+use crate::{popover_menu, v_flex, PopoverMenu};
 use gpui::{
-    div, AnyElement, Element, IntoElement, ParentElement, RenderOnce, Styled, WindowContext,
+    div, AnyElement, AppContext, DismissEvent, Element, EventEmitter, FocusHandle, FocusableView,
+    IntoElement, ParentElement, Render, RenderOnce, Styled, View, VisualContext, WindowContext,
 };
 use smallvec::SmallVec;
 
@@ -78,3 +81,51 @@ impl ParentElement for Popover {
         self.children.extend(elements)
     }
 }
+
+/// Creates a [`PopoverMenu`] that shows arbitrary content, such as a color picker or a form,
+/// in a [`Popover`] instead of a menu. It is dismissed when clicking outside of it, and can
+/// be positioned with the same `anchor`, `attach` and `offset` options.
+pub fn popover<E: IntoElement>(
+    id: impl Into<ElementId>,
+    content: impl Fn(&mut WindowContext) -> E + 'static,
+) -> PopoverMenu<PopoverContent> {
+    let content: Rc<dyn Fn(&mut WindowContext) -> AnyElement> =
+        Rc::new(move |cx| content(cx).into_any_element());
+    popover_menu(id).menu(move |cx| Some(PopoverContent::build(cx, content.clone())))
+}
+
+/// The view shown by [`popover`], which renders the caller's content inside a [`Popover`].
+pub struct PopoverContent {
+    focus_handle: FocusHandle,
+    content: Rc<dyn Fn(&mut WindowContext) -> AnyElement>,
+}
+
+impl PopoverContent {
+    pub fn build(
+        cx: &mut WindowContext,
+        content: Rc<dyn Fn(&mut WindowContext) -> AnyElement>,
+    ) -> View<Self> {
+        cx.new_view(|cx| Self {
+            focus_handle: cx.focus_handle(),
+            content,
+        })
+    }
+}
+
+impl FocusableView for PopoverContent {
+    fn focus_handle(&self, _cx: &AppContext) -> FocusHandle {
+        self.focus_handle.clone()
+    }
+}
+
+impl EventEmitter<DismissEvent> for PopoverContent {}
+
+impl Render for PopoverContent {
+    fn render(&mut self, cx: &mut ViewContext<Self>) -> impl IntoElement {
+        div()
+            .track_focus(&self.focus_handle)
+            .on_mouse_down_out(cx.listener(|_, _, cx| cx.emit(DismissEvent)))
+            .on_action(cx.listener(|_, _: &menu::Cancel, cx| cx.emit(DismissEvent)))
+            .child(Popover::new().child((self.content)(cx)))
+    }
+}
@@ -179,9 +179,11 @@ impl<M: ManagedView> Element for PopoverMenu<M> {
                 let mut overlay = overlay().snap_to_window().anchor(this.anchor);
 
                 if let Some(child_bounds) = element_state.child_bounds {
-                    overlay = overlay.position(
-                        this.resolved_attach().corner(child_bounds) + this.resolved_offset(cx),
-                    );
+                    overlay = overlay
+                        .position(
+                            this.resolved_attach().corner(child_bounds) + this.resolved_offset(cx),
+                        )
+                        .avoid_covering(child_bounds);
                 }
 
                 let mut element = overlay
@@ -1,21 +1,78 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
 use gpui::{
-    div, overlay, AnchorCorner, AnyElement, Bounds, DismissEvent, DispatchPhase, Element,
-    ElementContext, ElementId, Hitbox, InteractiveElement, IntoElement, LayoutId, ManagedView,
-    MouseButton, MouseDownEvent, ParentElement, Pixels, Point, View, VisualContext, WindowContext,
+    div, ease_in_out, overlay, prelude::FluentBuilder, AnchorCorner, AnyElement, Bounds,
+    DismissEvent, DispatchPhase, Edges, Element, ElementContext, ElementId, Hitbox,
+    InteractiveElement, IntoElement, LayoutId, ManagedView, MouseButton, MouseDownEvent,
+    MouseMoveEvent, MouseUpEvent, ParentElement, Pixels, Point, Styled, Task, View, VisualContext,
+    WindowContext,
 };
 
+/// How long the primary button has to be held over the trigger before the menu opens, for
+/// triggers that opt into [`RightClickMenu::open_on_long_press`].
+const LONG_PRESS_DURATION: Duration = Duration::from_millis(500);
+
+/// How far the pointer may move during a long press before it is treated as a drag instead.
+const LONG_PRESS_MOVEMENT_THRESHOLD: f64 = 4.;
+
+/// How long the trigger takes to transition between its closed and open states.
+const OPEN_TRANSITION_DURATION: Duration = Duration::from_millis(150);
+
+/// The state of a [`RightClickMenu`]'s trigger, passed to [`RightClickMenu::trigger_with`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MenuTriggerState {
+    /// Whether the menu is currently open.
+    pub is_open: bool,
+    /// How far the trigger has transitioned towards its open state, eased from 0 when the menu
+    /// is closed to 1 once it's open, and back again when it closes. This is for animating
+    /// affordances such as rotating a dropdown's chevron:
+    /// `Transformation::rotate(percentage(state.open_progress * 0.5))`.
+    pub open_progress: f32,
+    /// Whether the mouse is currently over the trigger.
+    pub is_hovered: bool,
+    /// Whether the menu can't be opened, so the trigger should render as disabled.
+    pub is_disabled: bool,
+}
+
+/// Where a [`RightClickMenu`] was opened from, passed to [`RightClickMenu::menu_with_context`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MenuOpenContext {
+    /// The window position of the pointer that opened the menu, such as the right click.
+    pub position: Point<Pixels>,
+    /// The bounds of the trigger in the window.
+    pub trigger_bounds: Bounds<Pixels>,
+}
+
 pub struct RightClickMenu<M: ManagedView> {
     id: ElementId,
-    child_builder: Option<Box<dyn FnOnce(bool) -> AnyElement + 'static>>,
-    menu_builder: Option<Rc<dyn Fn(&mut WindowContext) -> View<M> + 'static>>,
+    child_builder: Option<Box<dyn FnOnce(MenuTriggerState) -> AnyElement + 'static>>,
+    menu_builder: Option<Rc<dyn Fn(MenuOpenContext, &mut WindowContext) -> View<M> + 'static>>,
     anchor: Option<AnchorCorner>,
     attach: Option<AnchorCorner>,
+    disabled: bool,
+    hover_open_delay: Option<Duration>,
+    snap_margin: Option<Edges<Option<Pixels>>>,
+    match_trigger_width: bool,
+    steal_focus: bool,
+    open_on_long_press: bool,
 }
 
 impl<M: ManagedView> RightClickMenu<M> {
     pub fn menu(mut self, f: impl Fn(&mut WindowContext) -> View<M> + 'static) -> Self {
+        self.menu_builder = Some(Rc::new(move |_, cx| f(cx)));
+        self
+    }
+
+    /// Like [`Self::menu`], but the builder is also told where the menu was opened from, so that
+    /// it can tell which part of the trigger was clicked, such as the row under the cursor.
+    pub fn menu_with_context(
+        mut self,
+        f: impl Fn(MenuOpenContext, &mut WindowContext) -> View<M> + 'static,
+    ) -> Self {
         self.menu_builder = Some(Rc::new(f));
         self
     }
@@ -25,6 +82,18 @@ impl<M: ManagedView> RightClickMenu<M> {
         self
     }
 
+    /// Builds the trigger from its current state, so that it can render differently
+    /// while it is hovered or while the menu is open. The trigger is rebuilt every frame while
+    /// it transitions between open and closed, so it can animate along with
+    /// [`MenuTriggerState::open_progress`].
+    pub fn trigger_with<E: IntoElement + 'static>(
+        mut self,
+        f: impl FnOnce(MenuTriggerState) -> E + 'static,
+    ) -> Self {
+        self.child_builder = Some(Box::new(move |state| f(state).into_any_element()));
+        self
+    }
+
     /// anchor defines which corner of the menu to anchor to the attachment point
     /// (by default the cursor position, but see attach)
     pub fn anchor(mut self, anchor: AnchorCorner) -> Self {
@@ -38,6 +107,52 @@ impl<M: ManagedView> RightClickMenu<M> {
         self
     }
 
+    /// disabled keeps the trigger in place but prevents it from opening the menu
+    pub fn disabled(mut self, disabled: bool) -> Self {
+        self.disabled = disabled;
+        self
+    }
+
+    /// open_on_hover also opens the menu once the pointer has rested on the trigger for
+    /// `delay`, as in a menu bar. Leaving the trigger before then cancels it.
+    pub fn open_on_hover(mut self, delay: Duration) -> Self {
+        self.hover_open_delay = Some(delay);
+        self
+    }
+
+    /// snap_margin keeps the menu this far from each window edge when it would overflow it.
+    /// Edges set to `None` are not snapped, so the menu may extend past them.
+    pub fn snap_margin(mut self, margin: Edges<Option<Pixels>>) -> Self {
+        self.snap_margin = Some(margin);
+        self
+    }
+
+    /// match_trigger_width makes the menu at least as wide as its trigger, like the list of a
+    /// dropdown select. The menu still grows wider when its entries need more room.
+    pub fn match_trigger_width(mut self, match_trigger_width: bool) -> Self {
+        self.match_trigger_width = match_trigger_width;
+        self
+    }
+
+    /// steal_focus controls whether opening the menu focuses it, which it does by default. Pass
+    /// false to leave focus where it is, such as in a text field that opens a completion menu
+    /// as the user types. The menu can still be clicked and is still dismissed by clicking
+    /// outside it, and the focused field can forward its keyboard navigation with
+    /// [`ContextMenu::navigate`](crate::ContextMenu::navigate).
+    pub fn steal_focus(mut self, steal_focus: bool) -> Self {
+        self.steal_focus = steal_focus;
+        self
+    }
+
+    /// open_on_long_press also opens the menu when the primary button is held down on the
+    /// trigger without moving, so that it can be opened on touchscreens and with pens, which
+    /// have no right button. It's off by default, since it would otherwise also open for
+    /// triggers that are held down for other reasons, like being dragged.
+    pub fn open_on_long_press(mut self, open_on_long_press: bool) -> Self {
+        self.open_on_long_press = open_on_long_press;
+        self
+    }
+
     fn with_element_state<R>(
         &mut self,
         cx: &mut ElementContext,
@@ -62,12 +177,54 @@ pub fn right_click_menu<M: ManagedView>(id: impl Into<ElementId>) -> RightClickM
         menu_builder: None,
         anchor: None,
         attach: None,
+        disabled: false,
+        hover_open_delay: None,
+        snap_margin: None,
+        match_trigger_width: false,
+        steal_focus: true,
+        open_on_long_press: false,
     }
 }
 
 pub struct MenuHandleElementState<M> {
     menu: Rc<RefCell<Option<View<M>>>>,
     position: Rc<RefCell<Point<Pixels>>>,
+    hovered: Rc<Cell<bool>>,
+    long_press: Rc<RefCell<Option<LongPress>>>,
+    hover_open_timer: Rc<RefCell<Option<Task<()>>>>,
+    child_bounds: Option<Bounds<Pixels>>,
+    was_open: bool,
+    open_progress: f32,
+    /// When the current transition of the trigger started, and how far open it was then.
+    open_transition: Option<(Instant, f32)>,
+}
+
+impl<M> MenuHandleElementState<M> {
+    /// Advances the trigger's transition towards whether the menu is open, returning how far
+    /// open it is and whether it's still transitioning.
+    fn advance_open_transition(&mut self, is_open: bool) -> (f32, bool) {
+        if is_open != self.was_open {
+            self.was_open = is_open;
+            self.open_transition = Some((Instant::now(), self.open_progress));
+        }
+        if let Some((start, from)) = self.open_transition {
+            let target = if is_open { 1. } else { 0. };
+            let delta =
+                (start.elapsed().as_secs_f32() / OPEN_TRANSITION_DURATION.as_secs_f32()).min(1.);
+            self.open_progress = from + (target - from) * ease_in_out(delta);
+            if delta >= 1. {
+                self.open_transition = None;
+            }
+        }
+        (self.open_progress, self.open_transition.is_some())
+    }
+}
+
+/// A press of the primary button that will open the menu unless it is released or moved first.
+struct LongPress {
+    position: Point<Pixels>,
+    opened_menu: bool,
+    _timer: Task<()>,
 }
 
 impl<M> Clone for MenuHandleElementState<M> {
@@ -75,6 +232,13 @@ impl<M> Clone for MenuHandleElementState<M> {
         Self {
             menu: Rc::clone(&self.menu),
             position: Rc::clone(&self.position),
+            hovered: Rc::clone(&self.hovered),
+            long_press: Rc::clone(&self.long_press),
+            hover_open_timer: Rc::clone(&self.hover_open_timer),
+            child_bounds: self.child_bounds,
+            was_open: self.was_open,
+            open_progress: self.open_progress,
+            open_transition: self.open_transition,
         }
     }
 }
@@ -84,6 +248,13 @@ impl<M> Default for MenuHandleElementState<M> {
         Self {
             menu: Rc::default(),
             position: Rc::default(),
+            hovered: Rc::default(),
+            long_press: Rc::default(),
+            hover_open_timer: Rc::default(),
+            child_bounds: None,
+            was_open: false,
+            open_progress: 0.,
+            open_transition: None,
         }
     }
 }
@@ -103,23 +274,57 @@ impl<M: ManagedView> Element for RightClickMenu<M> {
             let mut menu_layout_id = None;
 
             let menu_element = element_state.menu.borrow_mut().as_mut().map(|menu| {
-                let mut overlay = overlay().snap_to_window();
+                let mut overlay = match this.snap_margin.clone() {
+                    Some(margin) => overlay().snap_to_window_with_margin(margin),
+                    None => overlay().snap_to_window(),
+                };
                 if let Some(anchor) = this.anchor {
                     overlay = overlay.anchor(anchor);
                 }
                 overlay = overlay.position(*element_state.position.borrow());
+                if let (Some(_), Some(child_bounds)) = (this.attach, element_state.child_bounds) {
+                    overlay = overlay.avoid_covering(child_bounds);
+                }
+
+                // The trigger is laid out after the menu, so use its bounds from the last frame,
+                // which are always known by the time the menu is open.
+                let min_width = element_state
+                    .child_bounds
+                    .filter(|_| this.match_trigger_width)
+                    .map(|child_bounds| child_bounds.size.width);
 
                 let mut element = overlay
-                    .child(div().occlude().child(menu.clone()))
+                    .child(
+                        div()
+                            .occlude()
+                            .when_some(min_width, |el, min_width| el.min_w(min_width))
+                            .child(menu.clone()),
+                    )
                     .into_any();
                 menu_layout_id = Some(element.before_layout(cx));
                 element
             });
 
+            let is_open = element_state.menu.borrow().is_some();
+            let (open_progress, is_transitioning) = element_state.advance_open_transition(is_open);
+            if is_transitioning {
+                let parent_id = cx.parent_view_id();
+                cx.on_next_frame(move |cx| match parent_id {
+                    Some(parent_id) => cx.notify(parent_id),
+                    None => cx.refresh(),
+                });
+            }
+
+            let trigger_state = MenuTriggerState {
+                is_open,
+                open_progress,
+                is_hovered: element_state.hovered.get(),
+                is_disabled: this.disabled,
+            };
             let mut child_element = this
                 .child_builder
                 .take()
-                .map(|child_builder| (child_builder)(element_state.menu.borrow().is_some()));
+                .map(|child_builder| (child_builder)(trigger_state));
 
             let child_layout_id = child_element
                 .as_mut()
@@ -174,31 +379,91 @@ impl<M: ManagedView> Element for RightClickMenu<M> {
                 child.paint(cx);
             }
 
+            if let Some(child_layout_id) = before_layout.child_layout_id {
+                element_state.child_bounds = Some(cx.layout_bounds(child_layout_id));
+            }
+
+            let hitbox_id = hitbox.id;
+            let hovered = element_state.hovered.clone();
+            cx.on_mouse_event(move |_: &MouseMoveEvent, phase, cx| {
+                if phase == DispatchPhase::Bubble && hitbox_id.is_hovered(cx) != hovered.get() {
+                    hovered.set(!hovered.get());
+                    cx.refresh();
+                }
+            });
+
+            // The press that opens the menu is released while it's open, so the press is
+            // tracked whether or not the menu is showing.
+            if this.open_on_long_press {
+                let long_press = element_state.long_press.clone();
+                cx.on_mouse_event(move |_: &MouseDownEvent, phase, _| {
+                    // A new press ends the one before it, even if its release wasn't seen.
+                    if phase == DispatchPhase::Capture {
+                        long_press.borrow_mut().take();
+                    }
+                });
+
+                let long_press = element_state.long_press.clone();
+                cx.on_mouse_event(move |event: &MouseMoveEvent, phase, _| {
+                    if phase != DispatchPhase::Bubble {
+                        return;
+                    }
+                    let mut long_press = long_press.borrow_mut();
+                    if long_press.as_ref().is_some_and(|long_press| {
+                        !long_press.opened_menu
+                            && (event.position - long_press.position).magnitude()
+                                > LONG_PRESS_MOVEMENT_THRESHOLD
+                    }) {
+                        long_press.take();
+                    }
+                });
+
+                let long_press = element_state.long_press.clone();
+                cx.on_mouse_event(move |event: &MouseUpEvent, phase, cx| {
+                    if phase == DispatchPhase::Capture && event.button == MouseButton::Left {
+                        // Releasing the press that opened the menu shouldn't also click the
+                        // trigger.
+                        if long_press
+                            .borrow_mut()
+                            .take()
+                            .is_some_and(|long_press| long_press.opened_menu)
+                        {
+                            cx.stop_propagation();
+                        }
+                    }
+                });
+            }
+
             if let Some(mut menu) = before_layout.menu_element.take() {
+                element_state.hover_open_timer.borrow_mut().take();
                 menu.paint(cx);
                 return;
             }
 
+            if this.disabled {
+                return;
+            }
+
             let Some(builder) = this.menu_builder.take() else {
                 return;
             };
 
             let attach = this.attach;
+            let steal_focus = this.steal_focus;
             let menu = element_state.menu.clone();
             let position = element_state.position.clone();
             let child_layout_id = before_layout.child_layout_id;
             let child_bounds = cx.layout_bounds(child_layout_id.unwrap());
 
-            let hitbox_id = hitbox.id;
-            cx.on_mouse_event(move |event: &MouseDownEvent, phase, cx| {
-                if phase == DispatchPhase::Bubble
-                    && event.button == MouseButton::Right
-                    && hitbox_id.is_hovered(cx)
-                {
-                    cx.stop_propagation();
-                    cx.prevent_default();
-
-                    let new_menu = (builder)(cx);
+            let open_menu = Rc::new(
+                move |anchor_position: Point<Pixels>,
+                      pointer_position: Point<Pixels>,
+                      cx: &mut WindowContext| {
+                    let context = MenuOpenContext {
+                        position: pointer_position,
+                        trigger_bounds: child_bounds,
+                    };
+                    let new_menu = (builder)(context, cx);
                     let menu2 = menu.clone();
                     let previous_focus_handle = cx.focused();
 
@@ -212,18 +477,110 @@ impl<M: ManagedView> Element for RightClickMenu<M> {
                         cx.refresh();
                     })
                     .detach();
-                    cx.focus_view(&new_menu);
+                    if steal_focus {
+                        cx.focus_view(&new_menu);
+                    }
                     *menu.borrow_mut() = Some(new_menu);
-                    *position.borrow_mut() = if child_layout_id.is_some() {
-                        if let Some(attach) = attach {
-                            attach.corner(child_bounds)
-                        } else {
-                            cx.mouse_position()
-                        }
-                    } else {
-                        cx.mouse_position()
-                    };
+                    *position.borrow_mut() = anchor_position;
                     cx.refresh();
+                },
+            );
+
+            cx.on_mouse_event({
+                let open_menu = open_menu.clone();
+                move |event: &MouseDownEvent, phase, cx| {
+                    if phase == DispatchPhase::Bubble
+                        && event.button == MouseButton::Right
+                        && hitbox_id.is_hovered(cx)
+                    {
+                        cx.stop_propagation();
+                        cx.prevent_default();
+
+                        let anchor_position = match attach {
+                            Some(attach) if child_layout_id.is_some() => {
+                                attach.corner(child_bounds)
+                            }
+                            _ => cx.mouse_position(),
+                        };
+                        open_menu(anchor_position, event.position, cx);
+                    }
+                }
+            });
+
+            if let Some(delay) = this.hover_open_delay {
+                let open_menu = open_menu.clone();
+                let hover_open_timer = element_state.hover_open_timer.clone();
+                cx.on_mouse_event(move |_: &MouseMoveEvent, phase, cx| {
+                    if phase != DispatchPhase::Bubble {
+                        return;
+                    }
+                    if !hitbox_id.is_hovered(cx) {
+                        hover_open_timer.borrow_mut().take();
+                        return;
+                    }
+                    if hover_open_timer.borrow().is_some() {
+                        return;
+                    }
+
+                    let open_menu = open_menu.clone();
+                    let pending_timer = hover_open_timer.clone();
+                    let timer = cx.spawn(|mut cx| async move {
+                        cx.background_executor().timer(delay).await;
+                        cx.update(|cx| {
+                            if pending_timer.borrow_mut().take().is_none() {
+                                return;
+                            }
+                            let anchor_position = match attach {
+                                Some(attach) if child_layout_id.is_some() => {
+                                    attach.corner(child_bounds)
+                                }
+                                _ => cx.mouse_position(),
+                            };
+                            open_menu(anchor_position, cx.mouse_position(), cx);
+                        })
+                        .ok();
+                    });
+                    *hover_open_timer.borrow_mut() = Some(timer);
+                });
+            }
+
+            if !this.open_on_long_press {
+                return;
+            }
+
+            let long_press = element_state.long_press.clone();
+            cx.on_mouse_event(move |event: &MouseDownEvent, phase, cx| {
+                if phase == DispatchPhase::Bubble
+                    && event.button == MouseButton::Left
+                    && hitbox_id.is_hovered(cx)
+                {
+                    let open_menu = open_menu.clone();
+                    let pending_long_press = long_press.clone();
+                    let timer = cx.spawn(|mut cx| async move {
+                        cx.background_executor().timer(LONG_PRESS_DURATION).await;
+                        cx.update(|cx| {
+                            let mut pending_long_press = pending_long_press.borrow_mut();
+                            let Some(long_press) = pending_long_press.as_mut() else {
+                                return;
+                            };
+                            long_press.opened_menu = true;
+                            let position = long_press.position;
+                            drop(pending_long_press);
+                            let anchor_position = match attach {
+                                Some(attach) if child_layout_id.is_some() => {
+                                    attach.corner(child_bounds)
+                                }
+                                _ => position,
+                            };
+                            open_menu(anchor_position, position, cx);
+                        })
+                        .ok();
+                    });
+                    *long_press.borrow_mut() = Some(LongPress {
+                        position: event.position,
+                        opened_menu: false,
+                        _timer: timer,
+                    });
                 }
             });
         })
@@ -237,3 +594,85 @@ impl<M: ManagedView> IntoElement for RightClickMenu<M> {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ContextMenu;
+    use gpui::{px, Modifiers, Render, TestAppContext, ViewContext};
+    use settings::SettingsStore;
+
+    struct LongPressTrigger {
+        menu: Rc<RefCell<Option<View<ContextMenu>>>>,
+        clicks: Rc<Cell<usize>>,
+    }
+
+    impl Render for LongPressTrigger {
+        fn render(&mut self, _cx: &mut ViewContext<Self>) -> impl IntoElement {
+            let menu = self.menu.clone();
+            let clicks = self.clicks.clone();
+            div()
+                .flex()
+                .child(
+                    right_click_menu("trigger")
+                        .open_on_long_press(true)
+                        .trigger(div().size(px(50.)))
+                        .menu(move |cx| {
+                            let new_menu =
+                                ContextMenu::build(cx, |menu, _| menu.entry("Entry", None, |_| {}));
+                            *menu.borrow_mut() = Some(new_menu.clone());
+                            new_menu
+                        }),
+                )
+                .child(
+                    div()
+                        .size(px(50.))
+                        .on_mouse_up(MouseButton::Left, move |_, _| clicks.set(clicks.get() + 1)),
+                )
+        }
+    }
+
+    #[gpui::test]
+    fn test_click_after_long_press(cx: &mut TestAppContext) {
+        cx.update(|cx| {
+            let settings_store = SettingsStore::test(cx);
+            cx.set_global(settings_store);
+            theme::init(theme::LoadThemes::JustBase, cx);
+        });
+        let menu = Rc::new(RefCell::new(None));
+        let clicks = Rc::new(Cell::new(0));
+        let (_, cx) = cx.add_window_view(|_| LongPressTrigger {
+            menu: menu.clone(),
+            clicks: clicks.clone(),
+        });
+        let trigger = gpui::point(px(25.), px(25.));
+        let elsewhere = gpui::point(px(75.), px(25.));
+
+        cx.simulate_event(MouseDownEvent {
+            position: trigger,
+            modifiers: Modifiers::default(),
+            button: MouseButton::Left,
+            click_count: 1,
+            first_mouse: false,
+        });
+        cx.background_executor.advance_clock(LONG_PRESS_DURATION);
+        cx.run_until_parked();
+        let open_menu = menu
+            .borrow()
+            .clone()
+            .expect("long press should open the menu");
+        cx.simulate_event(MouseUpEvent {
+            position: trigger,
+            modifiers: Modifiers::default(),
+            button: MouseButton::Left,
+            click_count: 1,
+        });
+
+        open_menu.update(cx, |_, cx| cx.emit(DismissEvent));
+        cx.run_until_parked();
+
+        // Only the release of the long press is swallowed, not the clicks after it.
+        cx.simulate_click(elsewhere, Modifiers::default());
+        assert_eq!(clicks.get(), 1);
+    }
+}